@@ -63,6 +63,22 @@ pub struct ListArtifactVersionsParams {
     pub list: ListParams,
 }
 
+list_query! {
+    /// Typed filters and ordering for [`ArtifactVersionsApi::list`].
+    pub struct ArtifactVersionQuery for ListArtifactVersionsParams;
+
+    pub enum ArtifactVersionOrder {
+        InsertedAt => "inserted_at",
+        UpdatedAt => "updated_at",
+        Version => "version",
+    }
+
+    filters {
+        artifact(text) => "artifact_prn",
+        version(text) => "version",
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ListArtifactVersionsResponse {
     pub artifact_versions: Vec<ArtifactVersion>,
@@ -61,6 +61,21 @@ pub struct ListArtifactsParams {
     pub list: ListParams,
 }
 
+list_query! {
+    /// Typed filters and ordering for [`ArtifactsApi::list`].
+    pub struct ArtifactQuery for ListArtifactsParams;
+
+    pub enum ArtifactOrder {
+        InsertedAt => "inserted_at",
+        UpdatedAt => "updated_at",
+        Name => "name",
+    }
+
+    filters {
+        name(text) => "name",
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ListArtifactsResponse {
     pub artifacts: Vec<Artifact>,
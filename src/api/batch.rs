@@ -0,0 +1,389 @@
+//! Concurrent execution of mixed API operations.
+//!
+//! A [`BatchExecutor`] runs a list of [`BatchOperation`]s with a concurrency
+//! limit. Operations may name others they must run after; an operation whose
+//! dependency failed or was skipped is skipped itself. Under
+//! [`FailurePolicy::FailFast`] no new operation starts once one has failed,
+//! while those already in flight are allowed to finish. The outcome of every
+//! operation is collected into a serializable [`BatchReport`] in input
+//! order.
+
+use std::collections::HashMap;
+use std::time::Instant;
+
+use futures::stream::{FuturesUnordered, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use snafu::{ResultExt, Snafu};
+
+use super::binaries::UpdateBinaryParams;
+use super::binary_signatures::BinarySignaturesCommand;
+use super::bundle_signatures::BundleSignaturesCommand;
+use super::cohorts::{CreateCohortParams, UpdateCohortParams};
+use super::devices::{CreateDeviceParams, DeleteDeviceParams, UpdateDeviceParams};
+use super::releases::UpdateReleaseParams;
+use super::{Api, JsonSerializationFailed};
+
+#[derive(Debug, Snafu)]
+#[snafu(context(suffix(false)))]
+pub enum Error {
+    #[snafu(display("Batch operation id {} is used more than once", id))]
+    DuplicateId { id: String },
+
+    #[snafu(display("Batch operation {} depends on unknown operation {}", id, dependency))]
+    UnknownDependency { id: String, dependency: String },
+
+    #[snafu(display("Batch operations {} depend on each other in a cycle", ids.join(", ")))]
+    DependencyCycle { ids: Vec<String> },
+}
+
+/// A single API call the executor can run.
+#[derive(Debug)]
+pub enum Operation {
+    CreateDevice(CreateDeviceParams),
+    UpdateDevice(UpdateDeviceParams),
+    DeleteDevice(DeleteDeviceParams),
+    CreateCohort(CreateCohortParams),
+    UpdateCohort(UpdateCohortParams),
+    UpdateBinary(UpdateBinaryParams),
+    UpdateRelease(UpdateReleaseParams),
+    BinarySignature(BinarySignaturesCommand),
+    BundleSignature(BundleSignaturesCommand),
+}
+
+impl Operation {
+    /// A stable name for the kind of call, used in reports.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Operation::CreateDevice(_) => "device.create",
+            Operation::UpdateDevice(_) => "device.update",
+            Operation::DeleteDevice(_) => "device.delete",
+            Operation::CreateCohort(_) => "cohort.create",
+            Operation::UpdateCohort(_) => "cohort.update",
+            Operation::UpdateBinary(_) => "binary.update",
+            Operation::UpdateRelease(_) => "release.update",
+            Operation::BinarySignature(BinarySignaturesCommand::Create(_)) => {
+                "binary_signature.create"
+            }
+            Operation::BinarySignature(BinarySignaturesCommand::Delete(_)) => {
+                "binary_signature.delete"
+            }
+            Operation::BundleSignature(BundleSignaturesCommand::Create(_)) => {
+                "bundle_signature.create"
+            }
+            Operation::BundleSignature(BundleSignaturesCommand::Delete(_)) => {
+                "bundle_signature.delete"
+            }
+        }
+    }
+
+    fn metadata(&self) -> Option<String> {
+        match self {
+            Operation::BinarySignature(BinarySignaturesCommand::Create(command)) => {
+                command.metadata.clone()
+            }
+            Operation::BinarySignature(BinarySignaturesCommand::Delete(command)) => {
+                command.metadata.clone()
+            }
+            Operation::BundleSignature(BundleSignaturesCommand::Create(command)) => {
+                command.metadata.clone()
+            }
+            Operation::BundleSignature(BundleSignaturesCommand::Delete(command)) => {
+                command.metadata.clone()
+            }
+            _ => None,
+        }
+    }
+
+    async fn execute(self, api: &Api) -> Result<Value, super::Error> {
+        fn value<T: Serialize>(response: Option<T>) -> Result<Value, super::Error> {
+            serde_json::to_value(response).context(JsonSerializationFailed)
+        }
+
+        match self {
+            Operation::CreateDevice(params) => value(api.devices().create(params).await?),
+            Operation::UpdateDevice(params) => value(api.devices().update(params).await?),
+            Operation::DeleteDevice(params) => value(api.devices().delete(params).await?),
+            Operation::CreateCohort(params) => value(api.cohorts().create(params).await?),
+            Operation::UpdateCohort(params) => value(api.cohorts().update(params).await?),
+            Operation::UpdateBinary(params) => value(api.binaries().update(params).await?),
+            Operation::UpdateRelease(params) => value(api.releases().update(params).await?),
+            Operation::BinarySignature(BinarySignaturesCommand::Create(command)) => {
+                value(api.binary_signatures().create(command.inner.params).await?)
+            }
+            Operation::BinarySignature(BinarySignaturesCommand::Delete(command)) => {
+                value(api.binary_signatures().delete(command.inner.params).await?)
+            }
+            Operation::BundleSignature(BundleSignaturesCommand::Create(command)) => {
+                value(api.bundle_signatures().create(command.inner.params).await?)
+            }
+            Operation::BundleSignature(BundleSignaturesCommand::Delete(command)) => {
+                value(api.bundle_signatures().delete(command.inner.params).await?)
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct BatchOperation {
+    pub id: String,
+    pub operation: Operation,
+    /// Ids of operations that must succeed before this one starts.
+    pub depends_on: Vec<String>,
+}
+
+impl BatchOperation {
+    pub fn new(id: impl Into<String>, operation: Operation) -> Self {
+        Self {
+            id: id.into(),
+            operation,
+            depends_on: Vec::new(),
+        }
+    }
+
+    pub fn after(mut self, id: impl Into<String>) -> Self {
+        self.depends_on.push(id.into());
+        self
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailurePolicy {
+    /// Start nothing new after the first failure.
+    FailFast,
+    #[default]
+    Continue,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OperationStatus {
+    Succeeded,
+    Failed,
+    Skipped,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct OperationResult {
+    pub id: String,
+    pub operation: String,
+    pub metadata: Option<String>,
+    pub status: OperationStatus,
+    /// The API response for succeeded operations.
+    pub response: Option<Value>,
+    /// Why the operation failed or was skipped.
+    pub error: Option<String>,
+    pub elapsed_ms: Option<u64>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct BatchReport {
+    pub results: Vec<OperationResult>,
+}
+
+impl BatchReport {
+    pub fn count(&self, status: OperationStatus) -> usize {
+        self.results.iter().filter(|r| r.status == status).count()
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.results
+            .iter()
+            .all(|r| r.status == OperationStatus::Succeeded)
+    }
+
+    pub fn get(&self, id: &str) -> Option<&OperationResult> {
+        self.results.iter().find(|r| r.id == id)
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct BatchExecutor {
+    concurrency: usize,
+    policy: FailurePolicy,
+}
+
+impl Default for BatchExecutor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BatchExecutor {
+    /// An executor running up to 8 operations at once and continuing past
+    /// failures.
+    pub fn new() -> Self {
+        Self {
+            concurrency: 8,
+            policy: FailurePolicy::Continue,
+        }
+    }
+
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    pub fn policy(mut self, policy: FailurePolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Runs `operations`, starting them in input order as their dependencies
+    /// complete. Fails without sending anything when ids are duplicated or
+    /// dependencies are unknown or cyclic.
+    pub async fn run(
+        &self,
+        api: &Api,
+        operations: Vec<BatchOperation>,
+    ) -> Result<BatchReport, Error> {
+        let dependencies = plan(&operations)?;
+
+        let mut results: Vec<OperationResult> = operations
+            .iter()
+            .map(|op| OperationResult {
+                id: op.id.clone(),
+                operation: op.operation.kind().to_string(),
+                metadata: op.operation.metadata(),
+                status: OperationStatus::Skipped,
+                response: None,
+                error: None,
+                elapsed_ms: None,
+            })
+            .collect();
+        let mut pending: Vec<Option<Operation>> = operations
+            .into_iter()
+            .map(|op| Some(op.operation))
+            .collect();
+        let mut done = vec![false; pending.len()];
+        let mut running = FuturesUnordered::new();
+        let mut halted = false;
+
+        loop {
+            // Skipping one operation can make later ones skippable, so scan
+            // until nothing changes.
+            let mut changed = !halted;
+            while changed {
+                changed = false;
+                for index in 0..pending.len() {
+                    if pending[index].is_none() || running.len() >= self.concurrency {
+                        continue;
+                    }
+
+                    let deps = &dependencies[index];
+                    if let Some(&blocker) = deps.iter().find(|&&dep| {
+                        done[dep] && results[dep].status != OperationStatus::Succeeded
+                    }) {
+                        pending[index] = None;
+                        done[index] = true;
+                        results[index].error = Some(format!(
+                            "dependency {} did not succeed",
+                            results[blocker].id
+                        ));
+                        changed = true;
+                    } else if deps
+                        .iter()
+                        .all(|&dep| done[dep] && results[dep].status == OperationStatus::Succeeded)
+                    {
+                        let operation = pending[index].take().expect("pending operation");
+                        running.push(async move {
+                            let started = Instant::now();
+                            let result = operation.execute(api).await;
+                            (index, result, started.elapsed())
+                        });
+                    }
+                }
+            }
+
+            let Some((index, result, elapsed)) = running.next().await else {
+                break;
+            };
+
+            done[index] = true;
+            let entry = &mut results[index];
+            entry.elapsed_ms = Some(elapsed.as_millis() as u64);
+            match result {
+                Ok(response) => {
+                    entry.status = OperationStatus::Succeeded;
+                    entry.response = Some(response);
+                }
+                Err(error) => {
+                    entry.status = OperationStatus::Failed;
+                    entry.error = Some(error.to_string());
+                    halted |= self.policy == FailurePolicy::FailFast;
+                }
+            }
+        }
+
+        for (index, operation) in pending.iter().enumerate() {
+            if operation.is_some() {
+                results[index].error = Some("batch stopped after a failure".to_string());
+            }
+        }
+
+        Ok(BatchReport { results })
+    }
+}
+
+/// Resolves dependency ids to indices and rejects cycles.
+fn plan(operations: &[BatchOperation]) -> Result<Vec<Vec<usize>>, Error> {
+    let mut indices = HashMap::new();
+    for (index, op) in operations.iter().enumerate() {
+        if indices.insert(op.id.as_str(), index).is_some() {
+            return DuplicateId { id: &op.id }.fail();
+        }
+    }
+
+    let dependencies = operations
+        .iter()
+        .map(|op| {
+            op.depends_on
+                .iter()
+                .map(|dependency| {
+                    indices.get(dependency.as_str()).copied().ok_or_else(|| {
+                        Error::UnknownDependency {
+                            id: op.id.clone(),
+                            dependency: dependency.clone(),
+                        }
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Kahn's algorithm: whatever can't be ordered is part of a cycle.
+    let mut waiting: Vec<usize> = dependencies.iter().map(Vec::len).collect();
+    let mut ready: Vec<usize> = (0..operations.len())
+        .filter(|&index| waiting[index] == 0)
+        .collect();
+    let mut ordered = 0;
+    while let Some(index) = ready.pop() {
+        ordered += 1;
+        for (dependent, deps) in dependencies.iter().enumerate() {
+            for _ in deps.iter().filter(|&&dep| dep == index) {
+                waiting[dependent] -= 1;
+                if waiting[dependent] == 0 {
+                    ready.push(dependent);
+                }
+            }
+        }
+    }
+    if ordered < operations.len() {
+        return DependencyCycle {
+            ids: operations
+                .iter()
+                .zip(&waiting)
+                .filter(|(_, waiting)| **waiting > 0)
+                .map(|(op, _)| op.id.clone())
+                .collect::<Vec<_>>(),
+        }
+        .fail();
+    }
+
+    Ok(dependencies)
+}
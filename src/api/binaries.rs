@@ -1,3 +1,4 @@
+use super::pagination::list_all;
 use super::{Error, Signature, Validation};
 
 use crate::{json_body, list_params::ListParams, validators, Api};
@@ -6,10 +7,13 @@ use reqwest::Method;
 use serde::{Deserialize, Serialize};
 use serde_json::{Map, Value};
 use snafu::ResultExt;
+use std::collections::BTreeMap;
+use std::fmt;
 use std::str::FromStr;
+use std::time::Duration;
 use validator::Validate;
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 #[serde(rename_all = "lowercase")]
 pub enum BinaryState {
     Uploadable,
@@ -39,6 +43,46 @@ impl FromStr for BinaryState {
     }
 }
 
+impl BinaryState {
+    /// Whether the binary state machine allows moving from `self` to `next`.
+    /// Binaries progress uploadable, hashable, hashing, signable, signed and
+    /// may be destroyed from any state.
+    pub fn can_transition_to(&self, next: &BinaryState) -> bool {
+        matches!(
+            (self, next),
+            (BinaryState::Uploadable, BinaryState::Hashable)
+                | (BinaryState::Hashable, BinaryState::Hashing)
+                | (BinaryState::Hashing, BinaryState::Signable)
+                | (BinaryState::Signable, BinaryState::Signed)
+        ) || (*self != BinaryState::Destroyed && *next == BinaryState::Destroyed)
+    }
+
+    /// Fields a binary must have to enter this state.
+    pub fn required_fields(&self) -> &'static [&'static str] {
+        match self {
+            BinaryState::Hashable
+            | BinaryState::Hashing
+            | BinaryState::Signable
+            | BinaryState::Signed => &["hash", "size"],
+            BinaryState::Uploadable | BinaryState::Destroyed => &[],
+        }
+    }
+}
+
+impl fmt::Display for BinaryState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = match self {
+            BinaryState::Uploadable => "uploadable",
+            BinaryState::Hashable => "hashable",
+            BinaryState::Hashing => "hashing",
+            BinaryState::Signable => "signable",
+            BinaryState::Signed => "signed",
+            BinaryState::Destroyed => "destroyed",
+        };
+        f.write_str(state)
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Binary {
     pub artifact_version_prn: String,
@@ -56,6 +100,42 @@ pub struct Binary {
     pub updated_at: String,
 }
 
+impl Binary {
+    /// Checks locally that this binary may move to `next`, with `hash` and
+    /// `size` overriding the binary's own values as an update would.
+    pub fn check_transition(
+        &self,
+        next: &BinaryState,
+        hash: Option<&str>,
+        size: Option<u64>,
+    ) -> Result<(), Error> {
+        if !self.state.can_transition_to(next) {
+            return Err(Error::InvalidStateTransition {
+                prn: self.prn.clone(),
+                from: self.state.to_string(),
+                to: next.to_string(),
+            });
+        }
+
+        for field in next.required_fields() {
+            let present = match *field {
+                "hash" => hash.or(self.hash.as_deref()).is_some(),
+                "size" => size.or(self.size).is_some(),
+                _ => true,
+            };
+            if !present {
+                return Err(Error::MissingTransitionField {
+                    prn: self.prn.clone(),
+                    state: next.to_string(),
+                    field: field.to_string(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Debug, Serialize, Validate)]
 pub struct CreateBinaryParams {
     pub artifact_version_prn: String,
@@ -109,6 +189,23 @@ pub struct ListBinariesParams {
     pub list: ListParams,
 }
 
+list_query! {
+    /// Typed filters and ordering for [`BinariesApi::list`].
+    pub struct BinaryQuery for ListBinariesParams;
+
+    pub enum BinaryOrder {
+        InsertedAt => "inserted_at",
+        UpdatedAt => "updated_at",
+        Target => "target",
+    }
+
+    filters {
+        artifact_version(text) => "artifact_version_prn",
+        target(text) => "target",
+        state(text) => "state",
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ListBinariesResponse {
     pub binaries: Vec<Binary>,
@@ -141,6 +238,66 @@ pub struct UpdateBinaryResponse {
     pub binary: Binary,
 }
 
+#[derive(Debug)]
+pub struct MarkBinaryHashableParams {
+    pub prn: String,
+    pub hash: Option<String>,
+    pub size: Option<u64>,
+}
+
+#[derive(Debug)]
+pub struct MarkBinarySignableParams {
+    pub prn: String,
+}
+
+#[derive(Debug)]
+pub struct WaitForBinaryStateParams {
+    pub prn: String,
+    pub target_states: Vec<BinaryState>,
+    pub timeout: Duration,
+    pub poll_interval: Duration,
+}
+
+/// The newest revision per target among the binaries of an artifact version,
+/// counting only binaries in `state` when one is given.
+pub(crate) async fn newest_per_target(
+    api: &Api,
+    artifact_version_prn: &str,
+    state: Option<&BinaryState>,
+) -> Result<Option<Vec<Binary>>, Error> {
+    let binaries = list_all(|page| async move {
+        api.binaries()
+            .list(ListBinariesParams {
+                list: ListParams {
+                    page,
+                    ..BinaryQuery::new()
+                        .artifact_version(artifact_version_prn)
+                        .into()
+                },
+            })
+            .await
+    })
+    .await?;
+
+    Ok(binaries.map(|binaries| {
+        let mut newest: BTreeMap<String, Binary> = BTreeMap::new();
+        for binary in binaries {
+            if binary.artifact_version_prn != artifact_version_prn
+                || state.is_some_and(|state| binary.state != *state)
+            {
+                continue;
+            }
+            if newest
+                .get(&binary.target)
+                .is_none_or(|current| binary.revision > current.revision)
+            {
+                newest.insert(binary.target.clone(), binary);
+            }
+        }
+        newest.into_values().collect()
+    }))
+}
+
 pub struct BinariesApi<'a>(pub &'a Api);
 
 impl<'a> BinariesApi<'a> {
@@ -225,4 +382,77 @@ impl<'a> BinariesApi<'a> {
             )
             .await
     }
+
+    /// Moves an uploaded binary to `hashable`. `hash` and `size` may be
+    /// given here when they were not known at creation.
+    pub async fn mark_hashable(
+        &'a self,
+        params: MarkBinaryHashableParams,
+    ) -> Result<Option<UpdateBinaryResponse>, Error> {
+        self.transition(params.prn, BinaryState::Hashable, params.hash, params.size)
+            .await
+    }
+
+    /// Moves a hashed binary to `signable`.
+    pub async fn mark_signable(
+        &'a self,
+        params: MarkBinarySignableParams,
+    ) -> Result<Option<UpdateBinaryResponse>, Error> {
+        self.transition(params.prn, BinaryState::Signable, None, None)
+            .await
+    }
+
+    async fn transition(
+        &'a self,
+        prn: String,
+        state: BinaryState,
+        hash: Option<String>,
+        size: Option<u64>,
+    ) -> Result<Option<UpdateBinaryResponse>, Error> {
+        let binary = self
+            .get(GetBinaryParams { prn: prn.clone() })
+            .await?
+            .ok_or_else(|| Error::EmptyResponse { prn: prn.clone() })?
+            .binary;
+
+        binary.check_transition(&state, hash.as_deref(), size)?;
+
+        self.update(UpdateBinaryParams {
+            prn,
+            custom_metadata: None,
+            description: None,
+            state: Some(state),
+            hash,
+            size,
+        })
+        .await
+    }
+
+    /// Polls the binary until it reaches one of `target_states`, backing off
+    /// from `poll_interval`. `Signed` and `Destroyed` end the state machine,
+    /// so reaching either one when it is not a target fails immediately.
+    pub async fn wait_for_state(
+        &'a self,
+        params: WaitForBinaryStateParams,
+    ) -> Result<Binary, Error> {
+        super::wait::wait_for_state(
+            &params.prn,
+            &params.target_states,
+            &[BinaryState::Signed, BinaryState::Destroyed],
+            params.timeout,
+            params.poll_interval,
+            || async {
+                let response = self
+                    .get(GetBinaryParams {
+                        prn: params.prn.clone(),
+                    })
+                    .await?;
+                Ok(response.map(|r| {
+                    let state = r.binary.state.clone();
+                    (r.binary, state)
+                }))
+            },
+        )
+        .await
+    }
 }
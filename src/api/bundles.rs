@@ -1,3 +1,7 @@
+pub mod builder;
+pub mod diff;
+pub mod migration;
+
 use reqwest::Method;
 use serde::{Deserialize, Serialize};
 use serde_json::{Map, Value};
@@ -7,6 +11,9 @@ use crate::{json_body, list_params::ListParams, Api};
 use super::{Error, Signature};
 use snafu::ResultExt;
 
+pub use builder::BundleBuilder;
+pub use diff::diff;
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct BundleBinary {
     pub custom_metadata: Option<Map<String, Value>>,
@@ -99,6 +106,21 @@ pub struct ListBundlesParams {
     pub list: ListParams,
 }
 
+list_query! {
+    /// Typed filters and ordering for [`BundlesApi::list`].
+    pub struct BundleQuery for ListBundlesParams;
+
+    pub enum BundleOrder {
+        InsertedAt => "inserted_at",
+        UpdatedAt => "updated_at",
+        Name => "name",
+    }
+
+    filters {
+        name(text) => "name",
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ListBundlesResponse {
     pub bundles: Vec<Bundle>,
@@ -131,6 +153,10 @@ pub struct ListBundleSignaturesResponse {
 pub struct BundlesApi<'a>(pub &'a Api);
 
 impl<'a> BundlesApi<'a> {
+    pub fn builder(&self) -> BundleBuilder<'a> {
+        BundleBuilder::new(self.0)
+    }
+
     pub async fn create(
         &'a self,
         params: CreateBundleParams,
@@ -0,0 +1,349 @@
+//! Assembles V2 bundles from `(artifact name, version, target)` tuples.
+//!
+//! Every entry is resolved through the artifacts, artifact versions and
+//! binaries APIs. The resolved binaries must be signed, and every target may
+//! appear only once in a bundle, whichever artifacts the entries name.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use snafu::{OptionExt, ResultExt, Snafu};
+
+use crate::api::artifact_versions::{
+    ArtifactVersion, ArtifactVersionQuery, ListArtifactVersionsParams,
+};
+use crate::api::artifacts::{Artifact, ArtifactQuery, ListArtifactsParams};
+use crate::api::binaries::{Binary, BinaryQuery, BinaryState, ListBinariesParams};
+use crate::api::pagination::{list_all, list_until};
+use crate::list_params::ListParams;
+use crate::Api;
+
+use super::{Bundle, CreateBundleBinary, CreateBundleParams, CreateBundleParamsV2};
+
+#[derive(Debug, Snafu)]
+#[snafu(context(suffix(false)))]
+pub enum Error {
+    #[snafu(display("Bundle builder API request failed: {}", source))]
+    Request { source: crate::api::Error },
+
+    #[snafu(display("Empty response from {}", operation))]
+    MissingResponse { operation: String },
+
+    #[snafu(display("Bundle has no binaries"))]
+    Empty,
+
+    #[snafu(display("Artifact '{}' not found", name))]
+    ArtifactNotFound { name: String },
+
+    #[snafu(display("Version '{}' of artifact '{}' not found", version, artifact))]
+    ArtifactVersionNotFound { artifact: String, version: String },
+
+    #[snafu(display(
+        "No binary for target '{}' in version '{}' of artifact '{}'",
+        target,
+        version,
+        artifact
+    ))]
+    BinaryNotFound {
+        artifact: String,
+        version: String,
+        target: String,
+    },
+
+    #[snafu(display("Binary {} is {:?}, expected signed", prn, state))]
+    BinaryNotSigned { prn: String, state: BinaryState },
+
+    #[snafu(display(
+        "Target '{}' appears more than once, for '{}' and '{}'",
+        target,
+        first,
+        second
+    ))]
+    DuplicateTarget {
+        target: String,
+        first: String,
+        second: String,
+    },
+}
+
+#[derive(Clone, Debug)]
+struct BundleEntry {
+    artifact: String,
+    version: String,
+    target: String,
+    custom_metadata: Option<Map<String, Value>>,
+}
+
+/// One resolved binary of a bundle.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ManifestEntry {
+    pub artifact_name: String,
+    pub artifact_prn: String,
+    pub artifact_version_prn: String,
+    pub version: String,
+    pub target: String,
+    pub binary_prn: String,
+    pub hash: Option<String>,
+    pub size: Option<u64>,
+    pub custom_metadata: Option<Map<String, Value>>,
+}
+
+#[derive(Debug)]
+pub struct BuiltBundle {
+    /// The created bundle, `None` when a dry run only planned creating it.
+    pub bundle: Option<Bundle>,
+    pub manifest: Vec<ManifestEntry>,
+}
+
+pub struct BundleBuilder<'a> {
+    api: &'a Api,
+    id: Option<String>,
+    name: Option<String>,
+    entries: Vec<BundleEntry>,
+}
+
+impl<'a> BundleBuilder<'a> {
+    pub fn new(api: &'a Api) -> Self {
+        Self {
+            api,
+            id: None,
+            name: None,
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn id(mut self, id: impl Into<String>) -> Self {
+        self.id = Some(id.into());
+        self
+    }
+
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Adds the binary for `target` of `version` of the artifact named
+    /// `artifact`. The binary's own custom metadata is used in the bundle.
+    pub fn binary(
+        mut self,
+        artifact: impl Into<String>,
+        version: impl Into<String>,
+        target: impl Into<String>,
+    ) -> Self {
+        self.entries.push(BundleEntry {
+            artifact: artifact.into(),
+            version: version.into(),
+            target: target.into(),
+            custom_metadata: None,
+        });
+        self
+    }
+
+    /// Like [`BundleBuilder::binary`], but overrides the custom metadata
+    /// stored with the binary in the bundle.
+    pub fn binary_with_metadata(
+        mut self,
+        artifact: impl Into<String>,
+        version: impl Into<String>,
+        target: impl Into<String>,
+        custom_metadata: Map<String, Value>,
+    ) -> Self {
+        self.entries.push(BundleEntry {
+            artifact: artifact.into(),
+            version: version.into(),
+            target: target.into(),
+            custom_metadata: Some(custom_metadata),
+        });
+        self
+    }
+
+    /// Resolves and checks every entry without creating the bundle.
+    pub async fn resolve(&self) -> Result<Vec<ManifestEntry>, Error> {
+        if self.entries.is_empty() {
+            return Empty.fail();
+        }
+
+        let mut seen = HashMap::new();
+        for entry in &self.entries {
+            if let Some(first) = seen.insert(&entry.target, &entry.artifact) {
+                return DuplicateTarget {
+                    target: &entry.target,
+                    first,
+                    second: &entry.artifact,
+                }
+                .fail();
+            }
+        }
+
+        let mut manifest = Vec::with_capacity(self.entries.len());
+        for entry in &self.entries {
+            let artifact = self.find_artifact(&entry.artifact).await?;
+            let artifact_version = self
+                .find_artifact_version(&artifact, &entry.version)
+                .await?;
+            let binary = self
+                .find_binary(&artifact_version, &entry.artifact, &entry.target)
+                .await?;
+
+            if !matches!(binary.state, BinaryState::Signed) {
+                return BinaryNotSigned {
+                    prn: binary.prn,
+                    state: binary.state,
+                }
+                .fail();
+            }
+
+            manifest.push(ManifestEntry {
+                artifact_name: artifact.name,
+                artifact_prn: artifact.prn,
+                artifact_version_prn: artifact_version.prn,
+                version: artifact_version.version,
+                target: binary.target,
+                binary_prn: binary.prn,
+                hash: binary.hash,
+                size: binary.size,
+                custom_metadata: entry.custom_metadata.clone().or(binary.custom_metadata),
+            });
+        }
+
+        Ok(manifest)
+    }
+
+    pub async fn create(self) -> Result<BuiltBundle, Error> {
+        let manifest = self.resolve().await?;
+
+        let params = CreateBundleParams::V2(CreateBundleParamsV2 {
+            binaries: manifest
+                .iter()
+                .map(|entry| CreateBundleBinary {
+                    prn: entry.binary_prn.clone(),
+                    custom_metadata: entry.custom_metadata.clone(),
+                })
+                .collect(),
+            id: self.id,
+            name: self.name,
+        });
+
+        let bundle = match self.api.bundles().create(params).await.context(Request)? {
+            Some(created) => Some(created.bundle),
+            None if self.api.is_dry_run() => None,
+            None => {
+                return MissingResponse {
+                    operation: "bundle create",
+                }
+                .fail()
+            }
+        };
+
+        Ok(BuiltBundle { bundle, manifest })
+    }
+
+    async fn find_artifact(&self, name: &str) -> Result<Artifact, Error> {
+        let api = self.api;
+        let matches = |artifact: &Artifact| artifact.name == name;
+        let artifacts = list_until(
+            |page| async move {
+                api.artifacts()
+                    .list(ListArtifactsParams {
+                        list: ListParams {
+                            page,
+                            ..ArtifactQuery::new().name(name).into()
+                        },
+                    })
+                    .await
+            },
+            |artifacts| artifacts.iter().any(matches),
+        )
+        .await
+        .context(Request)?
+        .context(MissingResponse {
+            operation: "artifact list",
+        })?;
+
+        artifacts
+            .into_iter()
+            .find(matches)
+            .context(ArtifactNotFound { name })
+    }
+
+    async fn find_artifact_version(
+        &self,
+        artifact: &Artifact,
+        version: &str,
+    ) -> Result<ArtifactVersion, Error> {
+        let api = self.api;
+        let matches = |artifact_version: &ArtifactVersion| {
+            artifact_version.artifact_prn == artifact.prn && artifact_version.version == version
+        };
+        let artifact_versions = list_until(
+            |page| async move {
+                api.artifact_versions()
+                    .list(ListArtifactVersionsParams {
+                        list: ListParams {
+                            page,
+                            ..ArtifactVersionQuery::new()
+                                .artifact(&artifact.prn)
+                                .version(version)
+                                .into()
+                        },
+                    })
+                    .await
+            },
+            |artifact_versions| artifact_versions.iter().any(matches),
+        )
+        .await
+        .context(Request)?
+        .context(MissingResponse {
+            operation: "artifact version list",
+        })?;
+
+        artifact_versions
+            .into_iter()
+            .find(matches)
+            .context(ArtifactVersionNotFound {
+                artifact: &artifact.name,
+                version,
+            })
+    }
+
+    async fn find_binary(
+        &self,
+        artifact_version: &ArtifactVersion,
+        artifact_name: &str,
+        target: &str,
+    ) -> Result<Binary, Error> {
+        let api = self.api;
+        let binaries = list_all(|page| async move {
+            api.binaries()
+                .list(ListBinariesParams {
+                    list: ListParams {
+                        page,
+                        ..BinaryQuery::new()
+                            .artifact_version(&artifact_version.prn)
+                            .target(target)
+                            .into()
+                    },
+                })
+                .await
+        })
+        .await
+        .context(Request)?
+        .context(MissingResponse {
+            operation: "binary list",
+        })?;
+
+        // Several revisions may exist for a target; the newest one wins.
+        binaries
+            .into_iter()
+            .filter(|binary| {
+                binary.artifact_version_prn == artifact_version.prn && binary.target == target
+            })
+            .max_by_key(|binary| binary.revision)
+            .context(BinaryNotFound {
+                artifact: artifact_name,
+                version: &artifact_version.version,
+                target,
+            })
+    }
+}
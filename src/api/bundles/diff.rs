@@ -0,0 +1,498 @@
+//! Structured comparison of the binaries in two bundles.
+//!
+//! Both bundles are resolved to one entry per artifact and target. Entries
+//! present on one side only are reported as added or removed. Entries present
+//! on both sides are compared by version, binary, hash, size, custom metadata
+//! and signing keys. V1 bundles are resolved through the binaries of their
+//! artifact versions, newest revision per target.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Write;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use snafu::{OptionExt, ResultExt, Snafu};
+
+use crate::api::artifact_versions::{ArtifactVersion, GetArtifactVersionParams};
+use crate::api::artifacts::{Artifact, GetArtifactParams};
+use crate::api::binaries::{newest_per_target, Binary, GetBinaryParams};
+use crate::api::releases::GetReleaseParams;
+use crate::Api;
+
+use super::{Bundle, GetBundleParams};
+
+#[derive(Debug, Snafu)]
+#[snafu(context(suffix(false)))]
+pub enum Error {
+    #[snafu(display("Bundle diff API request failed: {}", source))]
+    Request { source: crate::api::Error },
+
+    #[snafu(display("Empty response from {}", operation))]
+    MissingResponse { operation: String },
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct ResolvedBinary {
+    pub artifact_prn: String,
+    pub artifact_name: String,
+    pub artifact_version_prn: String,
+    pub version: String,
+    pub target: String,
+    pub binary_prn: String,
+    pub hash: Option<String>,
+    pub size: Option<u64>,
+    pub custom_metadata: Option<Map<String, Value>>,
+    pub signing_key_prns: BTreeSet<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case", tag = "field")]
+pub enum FieldChange {
+    Version {
+        from: String,
+        to: String,
+    },
+    Binary {
+        from: String,
+        to: String,
+    },
+    Hash {
+        from: Option<String>,
+        to: Option<String>,
+    },
+    Size {
+        from: Option<u64>,
+        to: Option<u64>,
+    },
+    CustomMetadata {
+        added: Map<String, Value>,
+        removed: Map<String, Value>,
+        changed: BTreeMap<String, (Value, Value)>,
+    },
+    Signatures {
+        added: BTreeSet<String>,
+        removed: BTreeSet<String>,
+    },
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct BinaryChange {
+    pub artifact_name: String,
+    pub artifact_prn: String,
+    pub target: String,
+    pub from: ResolvedBinary,
+    pub to: ResolvedBinary,
+    pub changes: Vec<FieldChange>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct BundleDiff {
+    pub from_bundle_prn: String,
+    pub to_bundle_prn: String,
+    pub added: Vec<ResolvedBinary>,
+    pub removed: Vec<ResolvedBinary>,
+    pub changed: Vec<BinaryChange>,
+    pub unchanged: Vec<ResolvedBinary>,
+}
+
+impl BundleDiff {
+    /// Compares two already resolved bundles.
+    pub fn between(
+        from_bundle_prn: impl Into<String>,
+        from: Vec<ResolvedBinary>,
+        to_bundle_prn: impl Into<String>,
+        to: Vec<ResolvedBinary>,
+    ) -> Self {
+        let key = |b: &ResolvedBinary| (b.artifact_prn.clone(), b.target.clone());
+        let mut from: BTreeMap<_, _> = from.into_iter().map(|b| (key(&b), b)).collect();
+        let to: BTreeMap<_, _> = to.into_iter().map(|b| (key(&b), b)).collect();
+
+        let mut diff = Self {
+            from_bundle_prn: from_bundle_prn.into(),
+            to_bundle_prn: to_bundle_prn.into(),
+            added: Vec::new(),
+            removed: Vec::new(),
+            changed: Vec::new(),
+            unchanged: Vec::new(),
+        };
+
+        for (key, new) in to {
+            let Some(old) = from.remove(&key) else {
+                diff.added.push(new);
+                continue;
+            };
+
+            let changes = compare(&old, &new);
+            if changes.is_empty() {
+                diff.unchanged.push(new);
+            } else {
+                diff.changed.push(BinaryChange {
+                    artifact_name: new.artifact_name.clone(),
+                    artifact_prn: new.artifact_prn.clone(),
+                    target: new.target.clone(),
+                    from: old,
+                    to: new,
+                    changes,
+                });
+            }
+        }
+
+        diff.removed = from.into_values().collect();
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    pub fn render_text(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "{} -> {}", self.from_bundle_prn, self.to_bundle_prn);
+        if self.is_empty() {
+            let _ = writeln!(out, "no changes");
+            return out;
+        }
+
+        for binary in &self.added {
+            let _ = writeln!(
+                out,
+                "+ {} {} ({})",
+                binary.artifact_name, binary.version, binary.target
+            );
+        }
+        for binary in &self.removed {
+            let _ = writeln!(
+                out,
+                "- {} {} ({})",
+                binary.artifact_name, binary.version, binary.target
+            );
+        }
+        for change in &self.changed {
+            let _ = writeln!(out, "~ {} ({})", change.artifact_name, change.target);
+            for field in &change.changes {
+                let _ = writeln!(out, "    {}", describe(field));
+            }
+        }
+        out
+    }
+
+    pub fn render_markdown(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "### `{}` → `{}`\n",
+            self.from_bundle_prn, self.to_bundle_prn
+        );
+        if self.is_empty() {
+            let _ = writeln!(out, "No changes.");
+            return out;
+        }
+
+        let _ = writeln!(out, "| | Artifact | Target | Change |");
+        let _ = writeln!(out, "|---|---|---|---|");
+        for binary in &self.added {
+            let _ = writeln!(
+                out,
+                "| added | {} | {} | {} |",
+                binary.artifact_name, binary.target, binary.version
+            );
+        }
+        for binary in &self.removed {
+            let _ = writeln!(
+                out,
+                "| removed | {} | {} | {} |",
+                binary.artifact_name, binary.target, binary.version
+            );
+        }
+        for change in &self.changed {
+            let details: Vec<String> = change.changes.iter().map(describe).collect();
+            let _ = writeln!(
+                out,
+                "| changed | {} | {} | {} |",
+                change.artifact_name,
+                change.target,
+                details.join("<br>").replace('|', "\\|")
+            );
+        }
+        out
+    }
+}
+
+fn compare(old: &ResolvedBinary, new: &ResolvedBinary) -> Vec<FieldChange> {
+    let mut changes = Vec::new();
+
+    if old.version != new.version {
+        changes.push(FieldChange::Version {
+            from: old.version.clone(),
+            to: new.version.clone(),
+        });
+    }
+    if old.binary_prn != new.binary_prn {
+        changes.push(FieldChange::Binary {
+            from: old.binary_prn.clone(),
+            to: new.binary_prn.clone(),
+        });
+    }
+    if old.hash != new.hash {
+        changes.push(FieldChange::Hash {
+            from: old.hash.clone(),
+            to: new.hash.clone(),
+        });
+    }
+    if old.size != new.size {
+        changes.push(FieldChange::Size {
+            from: old.size,
+            to: new.size,
+        });
+    }
+
+    let empty = Map::new();
+    let old_metadata = old.custom_metadata.as_ref().unwrap_or(&empty);
+    let new_metadata = new.custom_metadata.as_ref().unwrap_or(&empty);
+    let mut added = Map::new();
+    let mut removed = Map::new();
+    let mut changed = BTreeMap::new();
+    for (key, value) in new_metadata {
+        match old_metadata.get(key) {
+            None => {
+                added.insert(key.clone(), value.clone());
+            }
+            Some(old_value) if old_value != value => {
+                changed.insert(key.clone(), (old_value.clone(), value.clone()));
+            }
+            Some(_) => {}
+        }
+    }
+    for (key, value) in old_metadata {
+        if !new_metadata.contains_key(key) {
+            removed.insert(key.clone(), value.clone());
+        }
+    }
+    if !added.is_empty() || !removed.is_empty() || !changed.is_empty() {
+        changes.push(FieldChange::CustomMetadata {
+            added,
+            removed,
+            changed,
+        });
+    }
+
+    if old.signing_key_prns != new.signing_key_prns {
+        changes.push(FieldChange::Signatures {
+            added: &new.signing_key_prns - &old.signing_key_prns,
+            removed: &old.signing_key_prns - &new.signing_key_prns,
+        });
+    }
+
+    changes
+}
+
+fn describe(change: &FieldChange) -> String {
+    let opt = |v: &Option<String>| v.clone().unwrap_or_else(|| "none".to_string());
+    let size = |v: &Option<u64>| v.map_or_else(|| "none".to_string(), |v| v.to_string());
+    let keys = |set: &BTreeSet<String>| set.iter().cloned().collect::<Vec<_>>().join(", ");
+
+    match change {
+        FieldChange::Version { from, to } => format!("version: {from} -> {to}"),
+        FieldChange::Binary { from, to } => format!("binary: {from} -> {to}"),
+        FieldChange::Hash { from, to } => format!("hash: {} -> {}", opt(from), opt(to)),
+        FieldChange::Size { from, to } => format!("size: {} -> {}", size(from), size(to)),
+        FieldChange::CustomMetadata {
+            added,
+            removed,
+            changed,
+        } => {
+            let mut parts = Vec::new();
+            parts.extend(added.iter().map(|(k, v)| format!("+{k}={v}")));
+            parts.extend(removed.keys().map(|k| format!("-{k}")));
+            parts.extend(changed.iter().map(|(k, (a, b))| format!("{k}: {a} -> {b}")));
+            format!("custom_metadata: {}", parts.join(", "))
+        }
+        FieldChange::Signatures { added, removed } => {
+            let mut parts = Vec::new();
+            if !added.is_empty() {
+                parts.push(format!("+[{}]", keys(added)));
+            }
+            if !removed.is_empty() {
+                parts.push(format!("-[{}]", keys(removed)));
+            }
+            format!("signatures: {}", parts.join(" "))
+        }
+    }
+}
+
+/// Diffs the bundles with PRNs `from` and `to`.
+pub async fn diff(api: &Api, from: &str, to: &str) -> Result<BundleDiff, Error> {
+    let mut resolver = Resolver::new(api);
+    let from_binaries = resolver.resolve_bundle(from).await?;
+    let to_binaries = resolver.resolve_bundle(to).await?;
+    Ok(BundleDiff::between(from, from_binaries, to, to_binaries))
+}
+
+/// Diffs the bundles of the releases with PRNs `from` and `to`.
+pub async fn diff_releases(api: &Api, from: &str, to: &str) -> Result<BundleDiff, Error> {
+    let from_bundle = release_bundle_prn(api, from).await?;
+    let to_bundle = release_bundle_prn(api, to).await?;
+    diff(api, &from_bundle, &to_bundle).await
+}
+
+/// Resolves the binaries of the bundle with PRN `bundle_prn`.
+pub async fn resolve_bundle(api: &Api, bundle_prn: &str) -> Result<Vec<ResolvedBinary>, Error> {
+    Resolver::new(api).resolve_bundle(bundle_prn).await
+}
+
+async fn release_bundle_prn(api: &Api, release_prn: &str) -> Result<String, Error> {
+    Ok(api
+        .releases()
+        .get(GetReleaseParams {
+            prn: release_prn.to_string(),
+        })
+        .await
+        .context(Request)?
+        .context(MissingResponse {
+            operation: "release get",
+        })?
+        .release
+        .bundle_prn)
+}
+
+struct Resolver<'a> {
+    api: &'a Api,
+    artifacts: HashMap<String, Artifact>,
+    artifact_versions: HashMap<String, ArtifactVersion>,
+}
+
+impl<'a> Resolver<'a> {
+    fn new(api: &'a Api) -> Self {
+        Self {
+            api,
+            artifacts: HashMap::new(),
+            artifact_versions: HashMap::new(),
+        }
+    }
+
+    async fn resolve_bundle(&mut self, bundle_prn: &str) -> Result<Vec<ResolvedBinary>, Error> {
+        let bundle = self
+            .api
+            .bundles()
+            .get(GetBundleParams {
+                prn: bundle_prn.to_string(),
+            })
+            .await
+            .context(Request)?
+            .context(MissingResponse {
+                operation: "bundle get",
+            })?
+            .bundle;
+
+        let mut resolved = Vec::new();
+        match bundle {
+            Bundle::V2(bundle) => {
+                for bundle_binary in bundle.binaries {
+                    let binary = self
+                        .api
+                        .binaries()
+                        .get(GetBinaryParams {
+                            prn: bundle_binary.prn,
+                        })
+                        .await
+                        .context(Request)?
+                        .context(MissingResponse {
+                            operation: "binary get",
+                        })?
+                        .binary;
+                    resolved.push(
+                        self.resolve_binary(binary, bundle_binary.custom_metadata)
+                            .await?,
+                    );
+                }
+            }
+            Bundle::V1(bundle) => {
+                for artifact_version_prn in bundle.artifact_versions {
+                    for binary in self.newest_binaries(&artifact_version_prn).await? {
+                        let custom_metadata = binary.custom_metadata.clone();
+                        resolved.push(self.resolve_binary(binary, custom_metadata).await?);
+                    }
+                }
+            }
+        }
+
+        Ok(resolved)
+    }
+
+    async fn resolve_binary(
+        &mut self,
+        binary: Binary,
+        custom_metadata: Option<Map<String, Value>>,
+    ) -> Result<ResolvedBinary, Error> {
+        let artifact_version = self.artifact_version(&binary.artifact_version_prn).await?;
+        let version = artifact_version.version.clone();
+        let artifact_prn = artifact_version.artifact_prn.clone();
+        let artifact_name = self.artifact(&artifact_prn).await?.name.clone();
+
+        Ok(ResolvedBinary {
+            artifact_prn,
+            artifact_name,
+            artifact_version_prn: binary.artifact_version_prn,
+            version,
+            target: binary.target,
+            binary_prn: binary.prn,
+            hash: binary.hash,
+            size: binary.size,
+            custom_metadata,
+            signing_key_prns: binary
+                .signatures
+                .unwrap_or_default()
+                .into_iter()
+                .map(|s| s.signing_key_prn)
+                .collect(),
+        })
+    }
+
+    async fn newest_binaries(&self, artifact_version_prn: &str) -> Result<Vec<Binary>, Error> {
+        newest_per_target(self.api, artifact_version_prn, None)
+            .await
+            .context(Request)?
+            .context(MissingResponse {
+                operation: "binary list",
+            })
+    }
+
+    async fn artifact_version(&mut self, prn: &str) -> Result<&ArtifactVersion, Error> {
+        if !self.artifact_versions.contains_key(prn) {
+            let artifact_version = self
+                .api
+                .artifact_versions()
+                .get(GetArtifactVersionParams {
+                    prn: prn.to_string(),
+                })
+                .await
+                .context(Request)?
+                .context(MissingResponse {
+                    operation: "artifact version get",
+                })?
+                .artifact_version;
+            self.artifact_versions
+                .insert(prn.to_string(), artifact_version);
+        }
+        Ok(&self.artifact_versions[prn])
+    }
+
+    async fn artifact(&mut self, prn: &str) -> Result<&Artifact, Error> {
+        if !self.artifacts.contains_key(prn) {
+            let artifact = self
+                .api
+                .artifacts()
+                .get(GetArtifactParams {
+                    prn: prn.to_string(),
+                })
+                .await
+                .context(Request)?
+                .context(MissingResponse {
+                    operation: "artifact get",
+                })?
+                .artifact;
+            self.artifacts.insert(prn.to_string(), artifact);
+        }
+        Ok(&self.artifacts[prn])
+    }
+}
@@ -0,0 +1,429 @@
+//! Migration of V1 bundles (artifact versions) to V2 bundles (binaries).
+//!
+//! Each artifact version of a V1 bundle is expanded into its signed binaries,
+//! newest revision per target, and an equivalent V2 bundle is created, unless
+//! a V2 bundle with the same name and binaries already exists, e.g. from an
+//! earlier run, in which case that one is reused. Releases and bundle
+//! overrides pointing at the V1 bundle can optionally be repointed to the V2
+//! bundle. Failures are recorded per bundle in the returned
+//! [`MigrationReport`] so one bad bundle does not stop the run.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+use snafu::{OptionExt, ResultExt, Snafu};
+
+use crate::api::binaries::{newest_per_target, Binary, BinaryState};
+use crate::api::bundle_overrides::{
+    BundleOverride, ListBundleOverridesParams, UpdateBundleOverrideParams,
+};
+use crate::api::pagination::list_all;
+use crate::api::releases::{ListReleasesParams, Release, UpdateReleaseParams};
+use crate::list_params::ListParams;
+use crate::Api;
+
+use super::{
+    Bundle, BundleV1, BundleV2, CreateBundleBinary, CreateBundleParams, CreateBundleParamsV2,
+    ListBundlesParams,
+};
+
+#[derive(Debug, Snafu)]
+#[snafu(context(suffix(false)))]
+pub enum Error {
+    #[snafu(display("Bundle migration API request failed: {}", source))]
+    Request { source: crate::api::Error },
+
+    #[snafu(display("Empty response from {}", operation))]
+    MissingResponse { operation: String },
+
+    #[snafu(display("Artifact version {} has no signed binaries", artifact_version_prn))]
+    NoSignedBinaries { artifact_version_prn: String },
+
+    #[snafu(display("Created bundle {} is not a V2 bundle", prn))]
+    UnexpectedBundleVersion { prn: String },
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct MigrationOptions {
+    /// Only carry binaries for these targets over. All targets when `None`.
+    pub targets: Option<Vec<String>>,
+    pub repoint_releases: bool,
+    pub repoint_bundle_overrides: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct MigratedBinary {
+    pub artifact_version_prn: String,
+    pub target: String,
+    pub binary_prn: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct BundleMigration {
+    pub v1_bundle_prn: String,
+    pub v2_bundle_prn: Option<String>,
+    /// The V2 bundle already existed and was not created by this run.
+    #[serde(default)]
+    pub reused: bool,
+    /// A dry run only planned creating the V2 bundle, so it has no PRN and
+    /// nothing was repointed to it.
+    #[serde(default)]
+    pub planned: bool,
+    pub binaries: Vec<MigratedBinary>,
+    pub repointed_releases: Vec<String>,
+    pub repointed_bundle_overrides: Vec<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct MigrationReport {
+    pub bundles: Vec<BundleMigration>,
+}
+
+impl MigrationReport {
+    /// V1 bundle PRN to V2 bundle PRN for every successful migration.
+    pub fn mapping(&self) -> BTreeMap<String, String> {
+        self.bundles
+            .iter()
+            .filter_map(|m| {
+                m.v2_bundle_prn
+                    .as_ref()
+                    .map(|v2| (m.v1_bundle_prn.clone(), v2.clone()))
+            })
+            .collect()
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &BundleMigration> {
+        self.bundles.iter().filter(|m| m.error.is_some())
+    }
+}
+
+pub struct BundleMigrator<'a> {
+    api: &'a Api,
+    options: MigrationOptions,
+    releases: Option<Vec<Release>>,
+    bundle_overrides: Option<Vec<BundleOverride>>,
+    v2_bundles: Option<Vec<BundleV2>>,
+}
+
+impl<'a> BundleMigrator<'a> {
+    pub fn new(api: &'a Api, options: MigrationOptions) -> Self {
+        Self {
+            api,
+            options,
+            releases: None,
+            bundle_overrides: None,
+            v2_bundles: None,
+        }
+    }
+
+    /// Migrates every V1 bundle in the organization.
+    pub async fn migrate_all(&mut self) -> Result<MigrationReport, Error> {
+        let api = self.api;
+        let bundles = list_all(|page| async move {
+            api.bundles()
+                .list(ListBundlesParams {
+                    list: ListParams {
+                        page,
+                        ..Default::default()
+                    },
+                })
+                .await
+        })
+        .await
+        .context(Request)?
+        .context(MissingResponse {
+            operation: "bundle list",
+        })?;
+
+        let mut v1_bundles = Vec::new();
+        let mut v2_bundles = Vec::new();
+        for bundle in bundles {
+            match bundle {
+                Bundle::V1(v1) => v1_bundles.push(v1),
+                Bundle::V2(v2) => v2_bundles.push(v2),
+            }
+        }
+        self.v2_bundles = Some(v2_bundles);
+
+        Ok(self.migrate(&v1_bundles).await)
+    }
+
+    pub async fn migrate(&mut self, bundles: &[BundleV1]) -> MigrationReport {
+        let mut report = MigrationReport::default();
+        for bundle in bundles {
+            report.bundles.push(self.migrate_one(bundle).await);
+        }
+        report
+    }
+
+    async fn migrate_one(&mut self, bundle: &BundleV1) -> BundleMigration {
+        let mut migration = BundleMigration {
+            v1_bundle_prn: bundle.prn.clone(),
+            v2_bundle_prn: None,
+            reused: false,
+            planned: false,
+            binaries: Vec::new(),
+            repointed_releases: Vec::new(),
+            repointed_bundle_overrides: Vec::new(),
+            error: None,
+        };
+
+        if let Err(e) = self.try_migrate(bundle, &mut migration).await {
+            migration.error = Some(e.to_string());
+        }
+
+        migration
+    }
+
+    async fn try_migrate(
+        &mut self,
+        bundle: &BundleV1,
+        migration: &mut BundleMigration,
+    ) -> Result<(), Error> {
+        let mut binaries = Vec::new();
+        for artifact_version_prn in &bundle.artifact_versions {
+            let resolved = self.signed_binaries(artifact_version_prn).await?;
+            if resolved.is_empty() {
+                return NoSignedBinaries {
+                    artifact_version_prn,
+                }
+                .fail();
+            }
+            binaries.extend(resolved);
+        }
+
+        let v2_prn = match self.equivalent_bundle(bundle, &binaries).await? {
+            Some(prn) => {
+                migration.reused = true;
+                Some(prn)
+            }
+            None => {
+                let created = self
+                    .api
+                    .bundles()
+                    .create(CreateBundleParams::V2(CreateBundleParamsV2 {
+                        binaries: binaries
+                            .iter()
+                            .map(|binary| CreateBundleBinary {
+                                prn: binary.prn.clone(),
+                                custom_metadata: binary.custom_metadata.clone(),
+                            })
+                            .collect(),
+                        id: None,
+                        name: bundle.name.clone(),
+                    }))
+                    .await
+                    .context(Request)?;
+
+                match created.map(|created| created.bundle) {
+                    Some(Bundle::V2(v2)) => {
+                        let prn = v2.prn.clone();
+                        self.v2_bundles.get_or_insert_with(Vec::new).push(v2);
+                        Some(prn)
+                    }
+                    Some(Bundle::V1(v1)) => return UnexpectedBundleVersion { prn: v1.prn }.fail(),
+                    None if self.api.is_dry_run() => None,
+                    None => {
+                        return MissingResponse {
+                            operation: "bundle create",
+                        }
+                        .fail()
+                    }
+                }
+            }
+        };
+
+        migration.binaries = binaries
+            .into_iter()
+            .map(|binary| MigratedBinary {
+                artifact_version_prn: binary.artifact_version_prn,
+                target: binary.target,
+                binary_prn: binary.prn,
+            })
+            .collect();
+        let Some(v2_prn) = v2_prn else {
+            migration.planned = true;
+            return Ok(());
+        };
+        migration.v2_bundle_prn = Some(v2_prn.clone());
+
+        if self.options.repoint_releases {
+            for release_prn in self.releases_using(&bundle.prn).await? {
+                self.api
+                    .releases()
+                    .update(UpdateReleaseParams {
+                        prn: release_prn.clone(),
+                        bundle_prn: Some(v2_prn.clone()),
+                        description: None,
+                        disabled: None,
+                        name: None,
+                        next_release_prn: None,
+                        phase_mode: None,
+                        phase_tags: None,
+                        phase_value: None,
+                        required: None,
+                        schedule_date: None,
+                        version: None,
+                        version_requirement: None,
+                    })
+                    .await
+                    .context(Request)?;
+                migration.repointed_releases.push(release_prn);
+            }
+        }
+
+        if self.options.repoint_bundle_overrides {
+            for bundle_override_prn in self.bundle_overrides_using(&bundle.prn).await? {
+                self.api
+                    .bundle_overrides()
+                    .update(UpdateBundleOverrideParams {
+                        prn: bundle_override_prn.clone(),
+                        name: None,
+                        description: None,
+                        ends_at: None,
+                        starts_at: None,
+                        bundle_prn: Some(v2_prn.clone()),
+                    })
+                    .await
+                    .context(Request)?;
+                migration
+                    .repointed_bundle_overrides
+                    .push(bundle_override_prn);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// A V2 bundle with the name of `bundle` and exactly `binaries`.
+    async fn equivalent_bundle(
+        &mut self,
+        bundle: &BundleV1,
+        binaries: &[Binary],
+    ) -> Result<Option<String>, Error> {
+        if self.v2_bundles.is_none() {
+            let api = self.api;
+            let bundles = list_all(|page| async move {
+                api.bundles()
+                    .list(ListBundlesParams {
+                        list: ListParams {
+                            page,
+                            ..Default::default()
+                        },
+                    })
+                    .await
+            })
+            .await
+            .context(Request)?
+            .context(MissingResponse {
+                operation: "bundle list",
+            })?;
+            self.v2_bundles = Some(
+                bundles
+                    .into_iter()
+                    .filter_map(|b| match b {
+                        Bundle::V2(v2) => Some(v2),
+                        Bundle::V1(_) => None,
+                    })
+                    .collect(),
+            );
+        }
+
+        let wanted: BTreeSet<&str> = binaries.iter().map(|b| b.prn.as_str()).collect();
+        Ok(self
+            .v2_bundles
+            .iter()
+            .flatten()
+            .find(|v2| {
+                v2.name == bundle.name
+                    && v2
+                        .binaries
+                        .iter()
+                        .map(|b| b.prn.as_str())
+                        .collect::<BTreeSet<_>>()
+                        == wanted
+            })
+            .map(|v2| v2.prn.clone()))
+    }
+
+    async fn signed_binaries(&self, artifact_version_prn: &str) -> Result<Vec<Binary>, Error> {
+        let binaries =
+            newest_per_target(self.api, artifact_version_prn, Some(&BinaryState::Signed))
+                .await
+                .context(Request)?
+                .context(MissingResponse {
+                    operation: "binary list",
+                })?;
+
+        Ok(binaries
+            .into_iter()
+            .filter(|binary| {
+                self.options
+                    .targets
+                    .as_ref()
+                    .is_none_or(|targets| targets.contains(&binary.target))
+            })
+            .collect())
+    }
+
+    async fn releases_using(&mut self, bundle_prn: &str) -> Result<Vec<String>, Error> {
+        if self.releases.is_none() {
+            let api = self.api;
+            let releases = list_all(|page| async move {
+                api.releases()
+                    .list(ListReleasesParams {
+                        list: ListParams {
+                            page,
+                            ..Default::default()
+                        },
+                    })
+                    .await
+            })
+            .await
+            .context(Request)?
+            .context(MissingResponse {
+                operation: "release list",
+            })?;
+            self.releases = Some(releases);
+        }
+
+        Ok(self
+            .releases
+            .iter()
+            .flatten()
+            .filter(|release| release.bundle_prn == bundle_prn)
+            .map(|release| release.prn.clone())
+            .collect())
+    }
+
+    async fn bundle_overrides_using(&mut self, bundle_prn: &str) -> Result<Vec<String>, Error> {
+        if self.bundle_overrides.is_none() {
+            let api = self.api;
+            let bundle_overrides = list_all(|page| async move {
+                api.bundle_overrides()
+                    .list(ListBundleOverridesParams {
+                        list: ListParams {
+                            page,
+                            ..Default::default()
+                        },
+                    })
+                    .await
+            })
+            .await
+            .context(Request)?
+            .context(MissingResponse {
+                operation: "bundle override list",
+            })?;
+            self.bundle_overrides = Some(bundle_overrides);
+        }
+
+        Ok(self
+            .bundle_overrides
+            .iter()
+            .flatten()
+            .filter(|bundle_override| bundle_override.bundle_prn == bundle_prn)
+            .map(|bundle_override| bundle_override.prn.clone())
+            .collect())
+    }
+}
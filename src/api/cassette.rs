@@ -0,0 +1,256 @@
+//! Recording API interactions into a cassette and replaying them offline.
+//!
+//! An [`Api`](super::Api) built with [`Api::record`](super::Api::record)
+//! sends requests as usual and appends every request and its response to a
+//! JSON [`Cassette`] file, with credentials in headers redacted. One built
+//! with [`Api::replay`](super::Api::replay) never touches the network: each
+//! request is answered with the response of a recorded interaction it
+//! matches, and fails with [`Error::UnmatchedRequest`] when none does.
+//!
+//! Paths are recorded without the endpoint, so a cassette recorded against
+//! one environment replays against any other.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use snafu::ResultExt;
+
+use super::{CassetteFormat, CassetteIo, Error};
+
+/// Request headers whose values are never written to a cassette.
+const REDACTED_HEADERS: &[&str] = &["authorization", "cookie", "proxy-authorization"];
+const REDACTED: &str = "[REDACTED]";
+
+/// How a replayed request is matched to the recorded interactions.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Matching {
+    /// Requests must arrive in the recorded order, each with the recorded
+    /// method, path, query and body.
+    #[default]
+    Strict,
+    /// A request takes the first unused interaction with its method and
+    /// path, preferring one whose query and body match too.
+    Lenient,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub path: String,
+    pub query: Vec<(String, String)>,
+    pub headers: BTreeMap<String, String>,
+    pub body: Option<String>,
+}
+
+impl RecordedRequest {
+    pub(super) fn new(path: &str, query: &[(String, String)], request: &reqwest::Request) -> Self {
+        let headers = request
+            .headers()
+            .iter()
+            .map(|(name, value)| {
+                let value = if REDACTED_HEADERS.contains(&name.as_str()) {
+                    REDACTED.to_string()
+                } else {
+                    String::from_utf8_lossy(value.as_bytes()).into_owned()
+                };
+                (name.as_str().to_string(), value)
+            })
+            .collect();
+
+        Self {
+            method: request.method().to_string(),
+            path: path.to_string(),
+            query: query.to_vec(),
+            headers,
+            body: request
+                .body()
+                .and_then(reqwest::Body::as_bytes)
+                .map(|body| String::from_utf8_lossy(body).into_owned()),
+        }
+    }
+
+    fn describe(&self) -> String {
+        format!("{} {}", self.method, self.path)
+    }
+
+    fn same_body(&self, other: &RecordedRequest) -> bool {
+        let json = |body: &Option<String>| {
+            body.as_deref()
+                .and_then(|body| serde_json::from_str::<Value>(body).ok())
+        };
+        match (json(&self.body), json(&other.body)) {
+            (Some(a), Some(b)) => a == b,
+            _ => self.body == other.body,
+        }
+    }
+
+    /// Why `self`, as recorded, doesn't match `request`.
+    fn mismatch(&self, request: &RecordedRequest) -> Option<String> {
+        if self.method != request.method || self.path != request.path {
+            Some(format!("the next recorded request is {}", self.describe()))
+        } else if self.query != request.query {
+            Some(format!(
+                "the query differs from the recorded {:?}",
+                self.query
+            ))
+        } else if !self.same_body(request) {
+            Some(format!(
+                "the body differs from the recorded {}",
+                self.body.as_deref().unwrap_or("empty body")
+            ))
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct RecordedResponse {
+    pub status: u16,
+    pub body: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Interaction {
+    pub request: RecordedRequest,
+    pub response: RecordedResponse,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Cassette {
+    pub interactions: Vec<Interaction>,
+}
+
+impl Cassette {
+    pub async fn read(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let contents = tokio::fs::read(path).await.context(CassetteIo { path })?;
+        serde_json::from_slice(&contents).context(CassetteFormat { path })
+    }
+
+    pub async fn write(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let path = path.as_ref();
+        let json = serde_json::to_vec_pretty(self).context(CassetteFormat { path })?;
+        tokio::fs::write(path, json)
+            .await
+            .context(CassetteIo { path })
+    }
+}
+
+#[derive(Debug)]
+pub(super) enum Vcr {
+    Record {
+        path: PathBuf,
+        cassette: tokio::sync::Mutex<Cassette>,
+    },
+    Replay {
+        interactions: Vec<Interaction>,
+        matching: Matching,
+        used: Mutex<Vec<bool>>,
+    },
+}
+
+impl Vcr {
+    pub(super) fn record(path: PathBuf) -> Self {
+        Vcr::Record {
+            path,
+            cassette: Default::default(),
+        }
+    }
+
+    pub(super) fn replay(cassette: Cassette, matching: Matching) -> Self {
+        Vcr::Replay {
+            used: Mutex::new(vec![false; cassette.interactions.len()]),
+            interactions: cassette.interactions,
+            matching,
+        }
+    }
+
+    /// Appends an interaction and rewrites the cassette, so it is complete
+    /// after every request.
+    pub(super) async fn save(
+        &self,
+        request: RecordedRequest,
+        response: RecordedResponse,
+    ) -> Result<(), Error> {
+        if let Vcr::Record { path, cassette } = self {
+            let mut cassette = cassette.lock().await;
+            cassette
+                .interactions
+                .push(Interaction { request, response });
+            cassette.write(path).await?;
+        }
+        Ok(())
+    }
+
+    /// The recorded response for `request`, which then can't be replayed
+    /// again.
+    pub(super) fn play(&self, request: &RecordedRequest) -> Result<RecordedResponse, Error> {
+        let Vcr::Replay {
+            interactions,
+            matching,
+            used,
+        } = self
+        else {
+            unreachable!("only replaying cassettes play responses");
+        };
+        let mut used = used.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let unused = || (0..interactions.len()).filter(|i| !used[*i]);
+
+        let found = match matching {
+            Matching::Strict => {
+                let Some(next) = unused().next() else {
+                    return unmatched(
+                        request,
+                        format!(
+                            "all {} recorded interactions were replayed",
+                            interactions.len()
+                        ),
+                    );
+                };
+                if let Some(reason) = interactions[next].request.mismatch(request) {
+                    return unmatched(request, format!("interaction {next}: {reason}"));
+                }
+                next
+            }
+            Matching::Lenient => {
+                let candidates: Vec<usize> = unused()
+                    .filter(|i| {
+                        let recorded = &interactions[*i].request;
+                        recorded.method == request.method && recorded.path == request.path
+                    })
+                    .collect();
+                let exact = candidates
+                    .iter()
+                    .find(|i| interactions[**i].request.mismatch(request).is_none());
+                match exact.or(candidates.first()) {
+                    Some(i) => *i,
+                    None => {
+                        return unmatched(
+                            request,
+                            format!(
+                                "no unused interaction has this method and path ({} of {} \
+                                 replayed)",
+                                interactions.len() - unused().count(),
+                                interactions.len()
+                            ),
+                        )
+                    }
+                }
+            }
+        };
+
+        used[found] = true;
+        Ok(interactions[found].response.clone())
+    }
+}
+
+fn unmatched<T>(request: &RecordedRequest, reason: String) -> Result<T, Error> {
+    Err(Error::UnmatchedRequest {
+        request: request.describe(),
+        reason,
+    })
+}
@@ -1,3 +1,5 @@
+pub mod migration;
+
 use reqwest::Method;
 use serde::{Deserialize, Serialize};
 
@@ -45,6 +47,22 @@ pub struct ListCohortsParams {
     pub list: ListParams,
 }
 
+list_query! {
+    /// Typed filters and ordering for [`CohortsApi::list`].
+    pub struct CohortQuery for ListCohortsParams;
+
+    pub enum CohortOrder {
+        InsertedAt => "inserted_at",
+        UpdatedAt => "updated_at",
+        Name => "name",
+    }
+
+    filters {
+        product(text) => "product_prn",
+        name(text) => "name",
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ListCohortsResponse {
     pub cohorts: Vec<Cohort>,
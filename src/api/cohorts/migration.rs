@@ -0,0 +1,375 @@
+//! Moving devices from one cohort to another.
+//!
+//! [`CohortsApi::migrate_devices`] selects devices of the source cohort with a
+//! [`DeviceSelector`] and reassigns them to the destination cohort, which must
+//! belong to the same product. Before any device is touched, their original
+//! assignments are written to a rollback file that
+//! [`CohortsApi::rollback_devices`] can restore.
+
+use std::path::{Path, PathBuf};
+
+use futures::stream::{self, StreamExt};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use snafu::{OptionExt, ResultExt, Snafu};
+
+use crate::api::devices::{Device, DeviceQuery, ListDeviceParams, UpdateDeviceParams};
+use crate::api::pagination::list_all;
+use crate::api::releases::resolver::VersionRequirement;
+use crate::list_params::ListParams;
+
+use super::{CohortsApi, GetCohortParams};
+
+#[derive(Debug, Snafu)]
+#[snafu(context(suffix(false)))]
+pub enum Error {
+    #[snafu(display("Cohort migration API request failed: {}", source))]
+    Request { source: crate::api::Error },
+
+    #[snafu(display("Empty response from {}", operation))]
+    MissingResponse { operation: String },
+
+    #[snafu(display("Devices can't be migrated from cohort {} to itself", prn))]
+    SameCohort { prn: String },
+
+    #[snafu(display(
+        "Cohort {} belongs to product {} but cohort {} belongs to {}",
+        from,
+        from_product_prn,
+        to,
+        to_product_prn
+    ))]
+    ProductMismatch {
+        from: String,
+        from_product_prn: String,
+        to: String,
+        to_product_prn: String,
+    },
+
+    #[snafu(display("Failed to access rollback file {}: {}", path.display(), source))]
+    RollbackIo {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Failed to decode rollback file {}: {}", path.display(), source))]
+    RollbackFormat {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+}
+
+/// Which devices of the source cohort to move. Every criterion that is set
+/// must match; an empty selector matches all devices.
+#[derive(Clone, Debug, Default)]
+pub struct DeviceSelector {
+    /// Devices must carry all of these tags.
+    pub tags: Vec<String>,
+    pub target: Option<String>,
+    /// Identifier pattern where `*` matches any run of characters and `?` a
+    /// single one.
+    pub identifier_pattern: Option<String>,
+    /// Requirement on the reported release version, e.g. `< 2.0.0`. Devices
+    /// that haven't reported a valid version never match.
+    pub release_version: Option<VersionRequirement>,
+}
+
+impl DeviceSelector {
+    pub fn matches(&self, device: &Device) -> bool {
+        let tags = device.tags.as_deref().unwrap_or_default();
+        self.tags.iter().all(|tag| tags.contains(tag))
+            && self
+                .target
+                .as_ref()
+                .is_none_or(|target| device.target.as_ref() == Some(target))
+            && self
+                .identifier_pattern
+                .as_ref()
+                .is_none_or(|pattern| glob_match(pattern, &device.identifier))
+            && self.release_version.as_ref().is_none_or(|requirement| {
+                device
+                    .reported_release_version
+                    .as_deref()
+                    .and_then(|version| Version::parse(version).ok())
+                    .is_some_and(|version| requirement.matches(&version))
+            })
+    }
+}
+
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Where the last `*` was and how much text it has swallowed so far.
+    let mut star: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    p = star_p + 1;
+                    t = star_t + 1;
+                    star = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[derive(Debug)]
+pub struct MigrateDevicesParams {
+    pub from: String,
+    pub to: String,
+    pub selector: DeviceSelector,
+    /// Only report what would be moved.
+    pub dry_run: bool,
+    /// Devices updated at the same time.
+    pub concurrency: usize,
+    /// Where to write the original assignments. Not written on dry runs.
+    pub rollback_path: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceMigrationStatus {
+    /// Selected on a dry run.
+    Planned,
+    Migrated,
+    Failed,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DeviceMigration {
+    pub prn: String,
+    pub identifier: String,
+    pub status: DeviceMigrationStatus,
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DeviceMigrationReport {
+    pub from: String,
+    pub to: String,
+    pub dry_run: bool,
+    pub devices: Vec<DeviceMigration>,
+}
+
+impl DeviceMigrationReport {
+    pub fn failures(&self) -> impl Iterator<Item = &DeviceMigration> {
+        self.devices
+            .iter()
+            .filter(|d| d.status == DeviceMigrationStatus::Failed)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct RollbackEntry {
+    pub prn: String,
+    pub identifier: String,
+    pub cohort_prn: String,
+}
+
+/// The assignments before a migration.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct CohortRollback {
+    pub from: String,
+    pub to: String,
+    pub devices: Vec<RollbackEntry>,
+}
+
+impl CohortRollback {
+    pub async fn read(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let contents = tokio::fs::read(path).await.context(RollbackIo { path })?;
+        serde_json::from_slice(&contents).context(RollbackFormat { path })
+    }
+
+    pub async fn write(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let path = path.as_ref();
+        let json = serde_json::to_vec_pretty(self).context(RollbackFormat { path })?;
+        tokio::fs::write(path, json)
+            .await
+            .context(RollbackIo { path })
+    }
+}
+
+impl<'a> CohortsApi<'a> {
+    /// Moves the devices of cohort `params.from` matched by `params.selector`
+    /// to cohort `params.to`. Devices that fail to update are reported
+    /// without stopping the others.
+    pub async fn migrate_devices(
+        &'a self,
+        params: MigrateDevicesParams,
+    ) -> Result<DeviceMigrationReport, Error> {
+        if params.from == params.to {
+            return SameCohort { prn: params.from }.fail();
+        }
+        let from_product_prn = self.product_prn(&params.from).await?;
+        let to_product_prn = self.product_prn(&params.to).await?;
+        if from_product_prn != to_product_prn {
+            return ProductMismatch {
+                from: params.from,
+                from_product_prn,
+                to: params.to,
+                to_product_prn,
+            }
+            .fail();
+        }
+
+        let devices: Vec<Device> = self
+            .cohort_devices(&params.from)
+            .await?
+            .into_iter()
+            .filter(|device| params.selector.matches(device))
+            .collect();
+
+        if params.dry_run {
+            return Ok(DeviceMigrationReport {
+                from: params.from,
+                to: params.to,
+                dry_run: true,
+                devices: devices
+                    .into_iter()
+                    .map(|device| DeviceMigration {
+                        prn: device.prn,
+                        identifier: device.identifier,
+                        status: DeviceMigrationStatus::Planned,
+                        error: None,
+                    })
+                    .collect(),
+            });
+        }
+
+        let rollback = CohortRollback {
+            from: params.from.clone(),
+            to: params.to.clone(),
+            devices: devices
+                .iter()
+                .map(|device| RollbackEntry {
+                    prn: device.prn.clone(),
+                    identifier: device.identifier.clone(),
+                    cohort_prn: params.from.clone(),
+                })
+                .collect(),
+        };
+        if let Some(path) = &params.rollback_path {
+            rollback.write(path).await?;
+        }
+
+        let assignments = rollback
+            .devices
+            .into_iter()
+            .map(|entry| (entry.prn, entry.identifier, params.to.clone()))
+            .collect();
+        Ok(DeviceMigrationReport {
+            from: params.from,
+            to: params.to,
+            dry_run: false,
+            devices: self.assign(assignments, params.concurrency).await,
+        })
+    }
+
+    /// Puts the devices recorded in `rollback` back into their original
+    /// cohorts.
+    pub async fn rollback_devices(
+        &'a self,
+        rollback: CohortRollback,
+        concurrency: usize,
+    ) -> Result<DeviceMigrationReport, Error> {
+        let assignments = rollback
+            .devices
+            .into_iter()
+            .map(|entry| (entry.prn, entry.identifier, entry.cohort_prn))
+            .collect();
+        Ok(DeviceMigrationReport {
+            devices: self.assign(assignments, concurrency).await,
+            from: rollback.to,
+            to: rollback.from,
+            dry_run: false,
+        })
+    }
+
+    async fn assign(
+        &'a self,
+        assignments: Vec<(String, String, String)>,
+        concurrency: usize,
+    ) -> Vec<DeviceMigration> {
+        stream::iter(assignments)
+            .map(|(prn, identifier, cohort_prn)| async move {
+                let result = self
+                    .0
+                    .devices()
+                    .update(UpdateDeviceParams {
+                        product_prn: None,
+                        cohort_prn: Some(cohort_prn),
+                        prn: prn.clone(),
+                        description: None,
+                        quarantined: None,
+                        tags: None,
+                        target: None,
+                    })
+                    .await;
+                let (status, error) = match result {
+                    Ok(_) => (DeviceMigrationStatus::Migrated, None),
+                    Err(error) => (DeviceMigrationStatus::Failed, Some(error.to_string())),
+                };
+                DeviceMigration {
+                    prn,
+                    identifier,
+                    status,
+                    error,
+                }
+            })
+            .buffered(concurrency.max(1))
+            .collect()
+            .await
+    }
+
+    async fn product_prn(&'a self, prn: &str) -> Result<String, Error> {
+        Ok(self
+            .get(GetCohortParams {
+                prn: prn.to_string(),
+            })
+            .await
+            .context(Request)?
+            .context(MissingResponse {
+                operation: "cohort get",
+            })?
+            .cohort
+            .product_prn)
+    }
+
+    async fn cohort_devices(&'a self, cohort_prn: &str) -> Result<Vec<Device>, Error> {
+        let api = self.0;
+        let devices = list_all(|page| async move {
+            api.devices()
+                .list(ListDeviceParams {
+                    list: ListParams {
+                        page,
+                        ..DeviceQuery::new().cohort(cohort_prn).into()
+                    },
+                })
+                .await
+        })
+        .await
+        .context(Request)?
+        .context(MissingResponse {
+            operation: "device list",
+        })?;
+
+        Ok(devices
+            .into_iter()
+            .filter(|device| device.cohort_prn.as_deref() == Some(cohort_prn))
+            .collect())
+    }
+}
@@ -23,7 +23,8 @@ pub struct ListDeviceCertificateParams {
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ListDeviceCertificateResponse {
     pub device_certificates: Vec<DeviceCertificate>,
-    pub next_page: String,
+    /// `null` on the last page, as for every other list endpoint.
+    pub next_page: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -1,9 +1,16 @@
+pub mod bulk;
+pub mod inventory;
+pub mod simulator;
+
+use futures::stream::{self, StreamExt};
+use log::debug;
 use reqwest::Method;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
 use crate::{json_body, list_params::ListParams, Api};
 
+use super::pagination::list_all;
 use super::Error;
 use snafu::ResultExt;
 
@@ -25,12 +32,27 @@ pub struct Device {
     pub updated_at: String,
 }
 
+string_enum! {
+    pub enum DeviceUpdateStatus {
+        Update => "update",
+        NoUpdate => "no_update",
+    }
+}
+
+string_enum! {
+    /// What selected the bundle offered in a [`DeviceUpdate`].
+    pub enum DeviceUpdateSourceType {
+        Release => "release",
+        BundleOverride => "bundle_override",
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct DeviceUpdate {
-    pub status: String,
+    pub status: DeviceUpdateStatus,
     pub bundle_prn: Option<String>,
     pub source_prn: Option<String>,
-    pub source_type: Option<String>,
+    pub source_type: Option<DeviceUpdateSourceType>,
     pub manifest: Option<Vec<UpdateManifest>>,
 }
 
@@ -73,6 +95,29 @@ pub struct ListDeviceParams {
     pub list: ListParams,
 }
 
+list_query! {
+    /// Typed filters and ordering for [`DevicesApi::list`], e.g.
+    /// `DeviceQuery::new().cohort(prn).tag("canary").quarantined(false)`.
+    pub struct DeviceQuery for ListDeviceParams;
+
+    pub enum DeviceOrder {
+        InsertedAt => "inserted_at",
+        UpdatedAt => "updated_at",
+        LastConnectedAt => "last_connected_at",
+        Identifier => "identifier",
+    }
+
+    filters {
+        cohort(text) => "cohort_prn",
+        product(text) => "product_prn",
+        identifier(text) => "identifier",
+        target(text) => "target",
+        /// Devices carrying this tag; repeat for several tags.
+        tag(text) => "tags",
+        quarantined(flag) => "quarantined",
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ListDeviceResponse {
     pub devices: Vec<Device>,
@@ -173,6 +218,104 @@ pub struct GetUpdateDeviceParams {
 
 type GetUpdateDeviceResponse = DeviceUpdate;
 
+/// How [`DevicesApi::update_tags`] changes a device's tags.
+#[derive(Clone, Debug)]
+pub enum TagChange {
+    /// Appends the tags the device doesn't have yet.
+    Add(Vec<String>),
+    Remove(Vec<String>),
+    Replace(Vec<String>),
+}
+
+impl TagChange {
+    /// The tags resulting from applying this change to `current`.
+    pub fn apply(&self, current: &[String]) -> Vec<String> {
+        let mut tags: Vec<String> = Vec::new();
+        let mut push = |tag: &String| {
+            if !tags.contains(tag) {
+                tags.push(tag.clone());
+            }
+        };
+        match self {
+            TagChange::Add(added) => current.iter().chain(added).for_each(&mut push),
+            TagChange::Remove(removed) => current
+                .iter()
+                .filter(|tag| !removed.contains(tag))
+                .for_each(&mut push),
+            TagChange::Replace(replacement) => replacement.iter().for_each(&mut push),
+        }
+        tags
+    }
+}
+
+#[derive(Debug)]
+pub struct UpdateDeviceTagsParams {
+    pub prn: String,
+    pub change: TagChange,
+    /// Attempts allowed before giving up on concurrent edits, each computing
+    /// the change from a fresh copy of the device. `0` counts as `1`.
+    pub max_attempts: u32,
+}
+
+#[derive(Debug)]
+pub struct UpdateMatchingDeviceTagsParams {
+    /// Selects the devices, in the same syntax as [`ListParams::search`].
+    pub search: String,
+    pub change: TagChange,
+    pub max_attempts: u32,
+    /// Devices updated at the same time.
+    pub concurrency: usize,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DeviceTagsChange {
+    pub prn: String,
+    pub identifier: String,
+    pub before: Vec<String>,
+    pub after: Vec<String>,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+impl DeviceTagsChange {
+    pub fn is_changed(&self) -> bool {
+        self.before != self.after
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DeviceTagsFailure {
+    pub prn: String,
+    pub error: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DeviceTagsSummary {
+    pub changes: Vec<DeviceTagsChange>,
+    pub failures: Vec<DeviceTagsFailure>,
+}
+
+impl DeviceTagsSummary {
+    pub fn changed(&self) -> impl Iterator<Item = &DeviceTagsChange> {
+        self.changes.iter().filter(|change| change.is_changed())
+    }
+}
+
+/// Every device, across all pages of the device list.
+pub(crate) async fn list_all_devices(api: &Api) -> Result<Option<Vec<Device>>, Error> {
+    list_all(|page| async move {
+        api.devices()
+            .list(ListDeviceParams {
+                list: ListParams {
+                    page,
+                    ..Default::default()
+                },
+            })
+            .await
+    })
+    .await
+}
+
 pub struct DevicesApi<'a>(pub &'a Api);
 
 impl<'a> DevicesApi<'a> {
@@ -242,13 +385,151 @@ impl<'a> DevicesApi<'a> {
         params: GetUpdateDeviceParams,
     ) -> Result<Option<GetUpdateDeviceResponse>, Error> {
         let prn = &params.prn;
+        let path = format!("/devices/{prn}/update");
+        let body = Some(json_body!(&params));
 
-        self.0
-            .execute(
-                Method::POST,
-                format!("/devices/{prn}/update"),
-                Some(json_body!(&params)),
-            )
+        if params.write {
+            self.0.execute(Method::POST, path, body).await
+        } else {
+            self.0.execute_read_only(Method::POST, path, body).await
+        }
+    }
+
+    /// Applies `params.change` to the device's tags.
+    ///
+    /// The API only replaces the whole tag list, so the device is read again
+    /// right before writing. When its `updated_at` moved in between, someone
+    /// else edited it and the change is recomputed from the fresh copy, up to
+    /// `params.max_attempts` attempts.
+    ///
+    /// Comparing `updated_at` reduces lost updates but does not prevent them.
+    /// The API has no conditional write, so an edit landing between the
+    /// second read and the write is still overwritten.
+    pub async fn update_tags(
+        &'a self,
+        params: UpdateDeviceTagsParams,
+    ) -> Result<DeviceTagsChange, Error> {
+        let prn = params.prn;
+        let max_attempts = params.max_attempts.max(1);
+        let mut device = self.fetch(&prn).await?;
+
+        for _ in 0..max_attempts {
+            let before = device.tags.clone().unwrap_or_default();
+            let after = params.change.apply(&before);
+            let change = DeviceTagsChange {
+                prn: prn.clone(),
+                identifier: device.identifier.clone(),
+                added: after
+                    .iter()
+                    .filter(|tag| !before.contains(tag))
+                    .cloned()
+                    .collect(),
+                removed: before
+                    .iter()
+                    .filter(|tag| !after.contains(tag))
+                    .cloned()
+                    .collect(),
+                before,
+                after,
+            };
+            if !change.is_changed() {
+                return Ok(change);
+            }
+
+            let current = self.fetch(&prn).await?;
+            if current.updated_at != device.updated_at {
+                debug!("device {prn} changed while updating tags, retrying");
+                device = current;
+                continue;
+            }
+
+            self.update(UpdateDeviceParams {
+                product_prn: None,
+                cohort_prn: None,
+                prn: prn.clone(),
+                description: None,
+                quarantined: None,
+                tags: Some(change.after.clone()),
+                target: None,
+            })
+            .await?
+            .ok_or_else(|| Error::EmptyResponse { prn: prn.clone() })?;
+            return Ok(change);
+        }
+
+        Err(Error::UpdateConflict {
+            prn,
+            attempts: max_attempts,
+        })
+    }
+
+    /// Applies `params.change` to every device matching `params.search`.
+    /// Devices that fail are reported in the summary instead of stopping the
+    /// others.
+    pub async fn update_tags_matching(
+        &'a self,
+        params: UpdateMatchingDeviceTagsParams,
+    ) -> Result<DeviceTagsSummary, Error> {
+        let search = &params.search;
+        let prns: Vec<String> = list_all(|page| async move {
+            self.list(ListDeviceParams {
+                list: ListParams {
+                    search: Some(search.clone()),
+                    page,
+                    ..Default::default()
+                },
+            })
             .await
+        })
+        .await?
+        .ok_or_else(|| Error::MissingResponse {
+            operation: "device list".to_string(),
+        })?
+        .into_iter()
+        .map(|device| device.prn)
+        .collect();
+
+        let change = &params.change;
+        let results: Vec<_> = stream::iter(prns)
+            .map(|prn| async move {
+                let result = self
+                    .update_tags(UpdateDeviceTagsParams {
+                        prn: prn.clone(),
+                        change: change.clone(),
+                        max_attempts: params.max_attempts,
+                    })
+                    .await;
+                (prn, result)
+            })
+            .buffered(params.concurrency.max(1))
+            .collect()
+            .await;
+
+        let mut summary = DeviceTagsSummary {
+            changes: Vec::new(),
+            failures: Vec::new(),
+        };
+        for (prn, result) in results {
+            match result {
+                Ok(change) => summary.changes.push(change),
+                Err(error) => summary.failures.push(DeviceTagsFailure {
+                    prn,
+                    error: error.to_string(),
+                }),
+            }
+        }
+        Ok(summary)
+    }
+
+    async fn fetch(&'a self, prn: &str) -> Result<Device, Error> {
+        Ok(self
+            .get(GetDeviceParams {
+                prn: prn.to_string(),
+            })
+            .await?
+            .ok_or_else(|| Error::EmptyResponse {
+                prn: prn.to_string(),
+            })?
+            .device)
     }
 }
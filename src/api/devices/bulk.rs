@@ -0,0 +1,577 @@
+//! Bulk device import and export.
+//!
+//! Rows are read from CSV or JSON into [`DeviceRecord`]s and validated
+//! locally before anything is sent. [`import`] matches rows to existing
+//! devices by identifier, so running it twice over the same file creates
+//! nothing the second time: existing devices are only updated where a field
+//! differs, and a certificate is only registered when no registered
+//! certificate has the same serial. [`export`] dumps every device in the
+//! same format.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use futures::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use snafu::{OptionExt, ResultExt, Snafu};
+
+use crate::api::device_certificates::{CreateDeviceCertificateParams, ListDeviceCertificateParams};
+use crate::api::pagination::list_all;
+use crate::certificates::{normalize_serial, parse_pem};
+use crate::list_params::ListParams;
+use crate::{report, Api};
+
+use super::{list_all_devices, CreateDeviceParams, Device, UpdateDeviceParams};
+
+const CSV_HEADERS: &[&str] = &[
+    "identifier",
+    "product_prn",
+    "cohort_prn",
+    "tags",
+    "target",
+    "description",
+    "quarantined",
+    "certificate",
+];
+
+/// Separates tags inside the single CSV `tags` column.
+const TAG_SEPARATOR: char = ';';
+
+#[derive(Debug, Snafu)]
+#[snafu(context(suffix(false)))]
+pub enum Error {
+    #[snafu(display("Device bulk API request failed: {}", source))]
+    Request { source: crate::api::Error },
+
+    #[snafu(display("Missing response from {}", operation))]
+    MissingResponse { operation: String },
+
+    #[snafu(display("Invalid device CSV: {}", source))]
+    Csv { source: csv::Error },
+
+    #[snafu(display("Invalid device JSON: {}", source))]
+    Json { source: serde_json::Error },
+
+    #[snafu(display("Failed to access {}: {}", path.display(), source))]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Can't tell the format of {} from its extension", path.display()))]
+    UnknownFormat { path: PathBuf },
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Format {
+    Csv,
+    Json,
+}
+
+impl Format {
+    /// Picks the format from a `.csv` or `.json` extension.
+    pub fn from_path(path: &Path) -> Result<Self, Error> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("csv") => Ok(Format::Csv),
+            Some(ext) if ext.eq_ignore_ascii_case("json") => Ok(Format::Json),
+            _ => UnknownFormat { path }.fail(),
+        }
+    }
+}
+
+/// One device row. Optional fields left empty are not touched on existing
+/// devices.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct DeviceRecord {
+    pub identifier: String,
+    pub product_prn: String,
+    #[serde(default)]
+    pub cohort_prn: Option<String>,
+    #[serde(default)]
+    pub tags: Option<Vec<String>>,
+    #[serde(default)]
+    pub target: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub quarantined: Option<bool>,
+    /// PEM device certificate to register for the device.
+    #[serde(default)]
+    pub certificate: Option<String>,
+}
+
+impl DeviceRecord {
+    fn from_device(device: Device) -> Self {
+        Self {
+            identifier: device.identifier,
+            product_prn: device.product_prn,
+            cohort_prn: device.cohort_prn,
+            tags: device.tags.filter(|tags| !tags.is_empty()),
+            target: device.target,
+            description: device.description,
+            quarantined: Some(device.quarantined),
+            certificate: None,
+        }
+    }
+
+    /// Checks the row without contacting the API, returning every problem
+    /// found.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut problems = Vec::new();
+
+        if self.identifier.is_empty() {
+            problems.push("identifier is empty".to_string());
+        } else if self.identifier.chars().any(char::is_whitespace) {
+            problems.push("identifier contains whitespace".to_string());
+        }
+        if !is_prn_of(&self.product_prn, "product") {
+            problems.push(format!("{:?} is not a product PRN", self.product_prn));
+        }
+        if let Some(cohort_prn) = &self.cohort_prn {
+            if !is_prn_of(cohort_prn, "cohort") {
+                problems.push(format!("{cohort_prn:?} is not a cohort PRN"));
+            }
+        }
+        if let Some(tags) = &self.tags {
+            if tags.iter().any(|tag| tag.is_empty()) {
+                problems.push("tags contain an empty tag".to_string());
+            }
+        }
+        if let Some(certificate) = &self.certificate {
+            if let Err(error) = parse_pem(certificate) {
+                problems.push(format!("certificate: {error}"));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
+    }
+
+    /// The update needed to bring `device` in line with this row, if any.
+    fn changes(&self, device: &Device) -> Option<UpdateDeviceParams> {
+        let update = UpdateDeviceParams {
+            prn: device.prn.clone(),
+            product_prn: Some(self.product_prn.clone()).filter(|p| *p != device.product_prn),
+            cohort_prn: self
+                .cohort_prn
+                .clone()
+                .filter(|c| device.cohort_prn.as_ref() != Some(c)),
+            description: self
+                .description
+                .clone()
+                .filter(|d| device.description.as_ref() != Some(d)),
+            quarantined: self.quarantined.filter(|q| *q != device.quarantined),
+            tags: self
+                .tags
+                .clone()
+                .filter(|t| device.tags.as_ref() != Some(t)),
+            target: self
+                .target
+                .clone()
+                .filter(|t| device.target.as_ref() != Some(t)),
+        };
+
+        let changed = update.product_prn.is_some()
+            || update.cohort_prn.is_some()
+            || update.description.is_some()
+            || update.quarantined.is_some()
+            || update.tags.is_some()
+            || update.target.is_some();
+        changed.then_some(update)
+    }
+}
+
+fn is_prn_of(prn: &str, resource: &str) -> bool {
+    let parts: Vec<&str> = prn.split(':').collect();
+    parts.len() == 5 && parts[0] == "prn" && parts[3] == resource && !parts[4].is_empty()
+}
+
+/// The flat shape of a CSV row; tags share one column.
+#[derive(Deserialize)]
+struct CsvRow {
+    identifier: String,
+    product_prn: String,
+    cohort_prn: Option<String>,
+    tags: Option<String>,
+    target: Option<String>,
+    description: Option<String>,
+    quarantined: Option<bool>,
+    certificate: Option<String>,
+}
+
+impl From<CsvRow> for DeviceRecord {
+    fn from(row: CsvRow) -> Self {
+        let non_empty = |value: Option<String>| value.filter(|v| !v.is_empty());
+        Self {
+            identifier: row.identifier,
+            product_prn: row.product_prn,
+            cohort_prn: non_empty(row.cohort_prn),
+            tags: non_empty(row.tags).map(|tags| {
+                tags.split(TAG_SEPARATOR)
+                    .map(|tag| tag.trim().to_string())
+                    .collect()
+            }),
+            target: non_empty(row.target),
+            description: non_empty(row.description),
+            quarantined: row.quarantined,
+            certificate: non_empty(row.certificate),
+        }
+    }
+}
+
+/// Parses rows from `input`.
+pub fn parse(input: &str, format: Format) -> Result<Vec<DeviceRecord>, Error> {
+    match format {
+        Format::Json => serde_json::from_str(input).context(Json),
+        Format::Csv => csv::Reader::from_reader(input.as_bytes())
+            .deserialize::<CsvRow>()
+            .map(|row| row.map(DeviceRecord::from).context(Csv))
+            .collect(),
+    }
+}
+
+/// Renders rows in `format`, the inverse of [`parse`].
+pub fn render(records: &[DeviceRecord], format: Format) -> Result<String, Error> {
+    match format {
+        Format::Json => serde_json::to_string_pretty(records).context(Json),
+        Format::Csv => {
+            let rows: Vec<Vec<String>> = records
+                .iter()
+                .map(|record| {
+                    let opt = |value: &Option<String>| value.clone().unwrap_or_default();
+                    vec![
+                        record.identifier.clone(),
+                        record.product_prn.clone(),
+                        opt(&record.cohort_prn),
+                        record
+                            .tags
+                            .as_ref()
+                            .map(|tags| tags.join(&TAG_SEPARATOR.to_string()))
+                            .unwrap_or_default(),
+                        opt(&record.target),
+                        opt(&record.description),
+                        record
+                            .quarantined
+                            .map(|q| q.to_string())
+                            .unwrap_or_default(),
+                        opt(&record.certificate),
+                    ]
+                })
+                .collect();
+            Ok(report::csv(CSV_HEADERS, &rows))
+        }
+    }
+}
+
+/// Reads rows from a `.csv` or `.json` file.
+pub fn read_file(path: impl AsRef<Path>) -> Result<Vec<DeviceRecord>, Error> {
+    let path = path.as_ref();
+    let format = Format::from_path(path)?;
+    let input = std::fs::read_to_string(path).context(Io { path })?;
+    parse(&input, format)
+}
+
+/// Writes rows to a `.csv` or `.json` file.
+pub fn write_file(path: impl AsRef<Path>, records: &[DeviceRecord]) -> Result<(), Error> {
+    let path = path.as_ref();
+    let output = render(records, Format::from_path(path)?)?;
+    std::fs::write(path, output).context(Io { path })
+}
+
+#[derive(Clone, Debug)]
+pub struct ImportOptions {
+    /// Rows processed at the same time.
+    pub concurrency: usize,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        Self { concurrency: 8 }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportAction {
+    Created,
+    Updated,
+    Unchanged,
+    /// Rejected by local validation; nothing was sent.
+    Invalid,
+    Failed,
+}
+
+impl std::fmt::Display for ImportAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ImportAction::Created => "created",
+            ImportAction::Updated => "updated",
+            ImportAction::Unchanged => "unchanged",
+            ImportAction::Invalid => "invalid",
+            ImportAction::Failed => "failed",
+        })
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ImportResult {
+    /// 1-based position of the row in the input.
+    pub row: usize,
+    pub identifier: String,
+    pub action: ImportAction,
+    pub device_prn: Option<String>,
+    /// Whether the row's certificate was registered by this run.
+    pub certificate_registered: bool,
+    pub errors: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ImportReport {
+    pub results: Vec<ImportResult>,
+}
+
+impl ImportReport {
+    pub fn count(&self, action: ImportAction) -> usize {
+        self.results.iter().filter(|r| r.action == action).count()
+    }
+
+    /// Whether every row was imported or already up to date.
+    pub fn is_success(&self) -> bool {
+        self.count(ImportAction::Invalid) == 0 && self.count(ImportAction::Failed) == 0
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+
+    pub fn to_csv(&self) -> String {
+        report::csv(Self::HEADERS, &self.rows())
+    }
+
+    pub fn to_table(&self) -> String {
+        report::table(Self::HEADERS, &self.rows())
+    }
+
+    const HEADERS: &'static [&'static str] = &[
+        "row",
+        "identifier",
+        "action",
+        "device_prn",
+        "certificate_registered",
+        "errors",
+    ];
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        self.results
+            .iter()
+            .map(|r| {
+                vec![
+                    r.row.to_string(),
+                    r.identifier.clone(),
+                    r.action.to_string(),
+                    r.device_prn.clone().unwrap_or_default(),
+                    r.certificate_registered.to_string(),
+                    r.errors.join("; "),
+                ]
+            })
+            .collect()
+    }
+}
+
+/// Creates or updates a device for every valid row.
+///
+/// Identifiers repeated within `records` are rejected as invalid. Failures of
+/// single rows are recorded in the report; only failing to read the current
+/// devices or certificates aborts the import.
+pub async fn import(
+    api: &Api,
+    records: Vec<DeviceRecord>,
+    options: ImportOptions,
+) -> Result<ImportReport, Error> {
+    let existing: HashMap<String, Device> = list_all_devices(api)
+        .await
+        .context(Request)?
+        .context(MissingResponse {
+            operation: "device list",
+        })?
+        .into_iter()
+        .map(|device| (device.identifier.clone(), device))
+        .collect();
+
+    let registered: HashSet<String> = if records.iter().any(|r| r.certificate.is_some()) {
+        list_certificate_serials(api).await?
+    } else {
+        HashSet::new()
+    };
+
+    let mut seen = HashMap::new();
+    for record in &records {
+        *seen.entry(record.identifier.clone()).or_insert(0) += 1;
+    }
+
+    let existing = &existing;
+    let registered = &registered;
+    let seen = &seen;
+    let results = stream::iter(records.into_iter().enumerate())
+        .map(|(index, record)| async move {
+            let mut result = ImportResult {
+                row: index + 1,
+                identifier: record.identifier.clone(),
+                action: ImportAction::Invalid,
+                device_prn: None,
+                certificate_registered: false,
+                errors: Vec::new(),
+            };
+
+            if let Err(problems) = record.validate() {
+                result.errors = problems;
+            }
+            if seen[&record.identifier] > 1 {
+                result
+                    .errors
+                    .push("identifier appears more than once".to_string());
+            }
+            if !result.errors.is_empty() {
+                return result;
+            }
+
+            if let Err(error) = import_record(api, &record, existing, registered, &mut result).await
+            {
+                result.action = ImportAction::Failed;
+                result.errors.push(error.to_string());
+            }
+            result
+        })
+        .buffered(options.concurrency.max(1))
+        .collect()
+        .await;
+
+    Ok(ImportReport { results })
+}
+
+async fn import_record(
+    api: &Api,
+    record: &DeviceRecord,
+    existing: &HashMap<String, Device>,
+    registered: &HashSet<String>,
+    result: &mut ImportResult,
+) -> Result<(), Error> {
+    let device_prn = match existing.get(&record.identifier) {
+        Some(device) => {
+            result.device_prn = Some(device.prn.clone());
+            match record.changes(device) {
+                Some(update) => {
+                    let updated = api.devices().update(update).await.context(Request)?;
+                    if updated.is_none() && !api.is_dry_run() {
+                        return MissingResponse {
+                            operation: "device update",
+                        }
+                        .fail();
+                    }
+                    result.action = ImportAction::Updated;
+                }
+                None => result.action = ImportAction::Unchanged,
+            }
+            device.prn.clone()
+        }
+        None => {
+            let created = api
+                .devices()
+                .create(CreateDeviceParams {
+                    product_prn: record.product_prn.clone(),
+                    description: record.description.clone(),
+                    quarantined: record.quarantined,
+                    identifier: record.identifier.clone(),
+                    tags: record.tags.clone(),
+                    target: record.target.clone(),
+                    cohort_prn: record.cohort_prn.clone(),
+                })
+                .await
+                .context(Request)?;
+            result.action = ImportAction::Created;
+            match created {
+                Some(created) => {
+                    result.device_prn = Some(created.device.prn.clone());
+                    created.device.prn
+                }
+                // Without the PRN of the planned device there is no
+                // certificate registration to plan either.
+                None if api.is_dry_run() => return Ok(()),
+                None => {
+                    return MissingResponse {
+                        operation: "device create",
+                    }
+                    .fail()
+                }
+            }
+        }
+    };
+
+    if let Some(certificate) = &record.certificate {
+        // Validation already parsed the certificate.
+        let serial = parse_pem(certificate)
+            .map(|info| info.serial)
+            .unwrap_or_default();
+        if !registered.contains(&serial) {
+            let registered = api
+                .device_certificates()
+                .create(CreateDeviceCertificateParams {
+                    certificate: certificate.clone(),
+                    device_prn,
+                })
+                .await
+                .context(Request)?;
+            if registered.is_none() && !api.is_dry_run() {
+                return MissingResponse {
+                    operation: "device certificate create",
+                }
+                .fail();
+            }
+            result.certificate_registered = true;
+        }
+    }
+
+    Ok(())
+}
+
+/// Every device, in the format [`import`] reads.
+pub async fn export(api: &Api) -> Result<Vec<DeviceRecord>, Error> {
+    Ok(list_all_devices(api)
+        .await
+        .context(Request)?
+        .context(MissingResponse {
+            operation: "device list",
+        })?
+        .into_iter()
+        .map(DeviceRecord::from_device)
+        .collect())
+}
+
+/// Normalized serials of every registered device certificate. The API does
+/// not say which device a certificate belongs to, so a serial already
+/// registered for any device is not registered again.
+async fn list_certificate_serials(api: &Api) -> Result<HashSet<String>, Error> {
+    let certificates = list_all(|page| async move {
+        api.device_certificates()
+            .list(ListDeviceCertificateParams {
+                list: ListParams {
+                    page,
+                    ..Default::default()
+                },
+            })
+            .await
+    })
+    .await
+    .context(Request)?
+    .context(MissingResponse {
+        operation: "device certificate list",
+    })?;
+
+    Ok(certificates
+        .into_iter()
+        .map(|c| normalize_serial(&c.serial))
+        .collect())
+}
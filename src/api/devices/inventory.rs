@@ -0,0 +1,332 @@
+//! Fleet inventory: which devices run what.
+//!
+//! [`Inventory::collect`] pages through every device once, resolves the names
+//! of the releases and bundles they report and buckets them by how long ago
+//! they last connected. [`Inventory::group_by`] then counts devices along any
+//! combination of [`Dimension`]s into an [`InventoryReport`] that renders as
+//! JSON, CSV or a text table.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use snafu::{OptionExt, ResultExt, Snafu};
+
+use crate::api::bundles::{Bundle, GetBundleParams};
+use crate::api::releases::GetReleaseParams;
+use crate::{report, Api};
+
+use super::list_all_devices;
+
+const NONE: &str = "none";
+
+#[derive(Debug, Snafu)]
+#[snafu(context(suffix(false)))]
+pub enum Error {
+    #[snafu(display("Inventory API request failed: {}", source))]
+    Request { source: crate::api::Error },
+
+    #[snafu(display("Empty response from {}", operation))]
+    MissingResponse { operation: String },
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Dimension {
+    ReleaseVersion,
+    Release,
+    Bundle,
+    Cohort,
+    Target,
+    Quarantined,
+    Staleness,
+}
+
+impl Dimension {
+    fn name(&self) -> &'static str {
+        match self {
+            Dimension::ReleaseVersion => "release_version",
+            Dimension::Release => "release",
+            Dimension::Bundle => "bundle",
+            Dimension::Cohort => "cohort",
+            Dimension::Target => "target",
+            Dimension::Quarantined => "quarantined",
+            Dimension::Staleness => "last_connected",
+        }
+    }
+}
+
+impl fmt::Display for Dimension {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct InventoryOptions {
+    /// Upper bounds of the `last_connected_at` buckets, ascending.
+    pub staleness_buckets: Vec<Duration>,
+    /// Evaluate staleness as of this time instead of now.
+    pub now: Option<DateTime<Utc>>,
+}
+
+impl Default for InventoryOptions {
+    fn default() -> Self {
+        Self {
+            staleness_buckets: vec![
+                Duration::hours(1),
+                Duration::days(1),
+                Duration::days(7),
+                Duration::days(30),
+            ],
+            now: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct InventoryDevice {
+    pub prn: String,
+    pub identifier: String,
+    pub cohort_prn: Option<String>,
+    pub target: Option<String>,
+    pub quarantined: bool,
+    pub release_version: Option<String>,
+    pub release_prn: Option<String>,
+    pub release_name: Option<String>,
+    pub bundle_prn: Option<String>,
+    pub bundle_name: Option<String>,
+    pub last_connected_at: Option<String>,
+    /// Label of the staleness bucket, e.g. `<= 1d` or `never`.
+    pub staleness: String,
+}
+
+impl InventoryDevice {
+    fn value(&self, dimension: Dimension) -> String {
+        let or_none = |value: &Option<String>| value.clone().unwrap_or_else(|| NONE.to_string());
+        match dimension {
+            Dimension::ReleaseVersion => or_none(&self.release_version),
+            Dimension::Release => or_none(&self.release_name.clone().or(self.release_prn.clone())),
+            Dimension::Bundle => or_none(&self.bundle_name.clone().or(self.bundle_prn.clone())),
+            Dimension::Cohort => or_none(&self.cohort_prn),
+            Dimension::Target => or_none(&self.target),
+            Dimension::Quarantined => self.quarantined.to_string(),
+            Dimension::Staleness => self.staleness.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Inventory {
+    pub generated_at: DateTime<Utc>,
+    pub devices: Vec<InventoryDevice>,
+}
+
+impl Inventory {
+    pub async fn collect(api: &Api, options: InventoryOptions) -> Result<Self, Error> {
+        let now = options.now.unwrap_or_else(Utc::now);
+        let mut buckets = options.staleness_buckets;
+        buckets.sort();
+
+        let devices = list_all_devices(api)
+            .await
+            .context(Request)?
+            .context(MissingResponse {
+                operation: "device list",
+            })?;
+        let mut release_names: HashMap<String, Option<String>> = HashMap::new();
+        let mut bundle_names: HashMap<String, Option<String>> = HashMap::new();
+
+        let mut inventory = Vec::with_capacity(devices.len());
+        for device in devices {
+            let release_name = match &device.reported_release_prn {
+                Some(prn) => cached(&mut release_names, prn, || release_name(api, prn)).await?,
+                None => None,
+            };
+            let bundle_name = match &device.reported_bundle_prn {
+                Some(prn) => cached(&mut bundle_names, prn, || bundle_name(api, prn)).await?,
+                None => None,
+            };
+
+            inventory.push(InventoryDevice {
+                staleness: staleness(device.last_connected_at.as_deref(), now, &buckets),
+                prn: device.prn,
+                identifier: device.identifier,
+                cohort_prn: device.cohort_prn,
+                target: device.target,
+                quarantined: device.quarantined,
+                release_version: device.reported_release_version,
+                release_prn: device.reported_release_prn,
+                release_name,
+                bundle_prn: device.reported_bundle_prn,
+                bundle_name,
+                last_connected_at: device.last_connected_at,
+            });
+        }
+
+        Ok(Self {
+            generated_at: now,
+            devices: inventory,
+        })
+    }
+
+    /// Counts devices per distinct combination of `dimensions`, largest
+    /// groups first.
+    pub fn group_by(&self, dimensions: &[Dimension]) -> InventoryReport {
+        let mut groups: BTreeMap<Vec<String>, Vec<String>> = BTreeMap::new();
+        for device in &self.devices {
+            let key = dimensions.iter().map(|d| device.value(*d)).collect();
+            groups.entry(key).or_default().push(device.prn.clone());
+        }
+
+        let mut groups: Vec<InventoryGroup> = groups
+            .into_iter()
+            .map(|(values, device_prns)| InventoryGroup {
+                values,
+                count: device_prns.len(),
+                device_prns,
+            })
+            .collect();
+        groups.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.values.cmp(&b.values)));
+
+        InventoryReport {
+            generated_at: self.generated_at,
+            total: self.devices.len(),
+            dimensions: dimensions.to_vec(),
+            groups,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct InventoryGroup {
+    /// One value per dimension of the report, in the same order.
+    pub values: Vec<String>,
+    pub count: usize,
+    pub device_prns: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct InventoryReport {
+    pub generated_at: DateTime<Utc>,
+    pub total: usize,
+    pub dimensions: Vec<Dimension>,
+    pub groups: Vec<InventoryGroup>,
+}
+
+impl InventoryReport {
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+
+    pub fn to_csv(&self) -> String {
+        report::csv(&self.headers(), &self.rows())
+    }
+
+    pub fn to_table(&self) -> String {
+        report::table(&self.headers(), &self.rows())
+    }
+
+    fn headers(&self) -> Vec<&'static str> {
+        self.dimensions
+            .iter()
+            .map(Dimension::name)
+            .chain(["devices"])
+            .collect()
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        self.groups
+            .iter()
+            .map(|group| {
+                let mut row = group.values.clone();
+                row.push(group.count.to_string());
+                row
+            })
+            .collect()
+    }
+}
+
+fn staleness(last_connected_at: Option<&str>, now: DateTime<Utc>, buckets: &[Duration]) -> String {
+    let Some(connected) = last_connected_at.and_then(|at| DateTime::parse_from_rfc3339(at).ok())
+    else {
+        return "never".to_string();
+    };
+    let age = now - connected.with_timezone(&Utc);
+
+    match buckets.iter().find(|bucket| age <= **bucket) {
+        Some(bucket) => format!("<= {}", label(*bucket)),
+        None => match buckets.last() {
+            Some(last) => format!("> {}", label(*last)),
+            None => "connected".to_string(),
+        },
+    }
+}
+
+fn label(duration: Duration) -> String {
+    if duration.num_days() > 0 && duration == Duration::days(duration.num_days()) {
+        format!("{}d", duration.num_days())
+    } else if duration.num_hours() > 0 && duration == Duration::hours(duration.num_hours()) {
+        format!("{}h", duration.num_hours())
+    } else {
+        format!("{}m", duration.num_minutes())
+    }
+}
+
+async fn cached<F, Fut>(
+    cache: &mut HashMap<String, Option<String>>,
+    prn: &str,
+    fetch: F,
+) -> Result<Option<String>, Error>
+where
+    F: FnOnce() -> Fut,
+    Fut: std::future::Future<Output = Result<Option<String>, Error>>,
+{
+    if let Some(name) = cache.get(prn) {
+        return Ok(name.clone());
+    }
+    let name = fetch().await?;
+    cache.insert(prn.to_string(), name.clone());
+    Ok(name)
+}
+
+/// A release or bundle may have been deleted since the device reported it.
+fn not_found(error: &crate::api::Error) -> bool {
+    matches!(
+        error,
+        crate::api::Error::StructuredError { status: 404, .. }
+            | crate::api::Error::HttpError { status: 404, .. }
+    )
+}
+
+async fn release_name(api: &Api, prn: &str) -> Result<Option<String>, Error> {
+    match api
+        .releases()
+        .get(GetReleaseParams {
+            prn: prn.to_string(),
+        })
+        .await
+    {
+        Ok(response) => Ok(response.map(|r| r.release.name)),
+        Err(error) if not_found(&error) => Ok(None),
+        Err(error) => Err(error).context(Request),
+    }
+}
+
+async fn bundle_name(api: &Api, prn: &str) -> Result<Option<String>, Error> {
+    match api
+        .bundles()
+        .get(GetBundleParams {
+            prn: prn.to_string(),
+        })
+        .await
+    {
+        Ok(response) => Ok(response.and_then(|r| match r.bundle {
+            Bundle::V1(bundle) => bundle.name,
+            Bundle::V2(bundle) => bundle.name,
+        })),
+        Err(error) if not_found(&error) => Ok(None),
+        Err(error) => Err(error).context(Request),
+    }
+}
@@ -0,0 +1,380 @@
+//! Simulated device check-ins for testing release targeting.
+//!
+//! A [`Simulator`] asks the server what each [`VirtualDevice`] would be
+//! offered by calling [`DevicesApi::get_update`](super::DevicesApi::get_update)
+//! with `write: false`, so nothing about the devices is recorded. Virtual
+//! devices are matched to devices of the organization by identifier. The
+//! server resolves updates from the cohort and tags it has stored, so where
+//! those differ from the virtual device the difference is flagged; existing
+//! devices are never changed. With [`SimulatorOptions::sync`] missing devices
+//! are created first, and [`Simulator::teardown`] deletes them again.
+//!
+//! Pointing the [`Api`] at a local mock server, such as the `FakeServer` of
+//! the `testing` feature, works the same way.
+
+use std::collections::{BTreeMap, HashMap};
+
+use futures::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use snafu::{OptionExt, ResultExt, Snafu};
+
+use crate::Api;
+
+use super::{
+    list_all_devices, CreateDeviceParams, DeleteDeviceParams, Device, DeviceUpdate,
+    DeviceUpdateStatus, GetUpdateDeviceParams,
+};
+
+#[derive(Debug, Snafu)]
+#[snafu(context(suffix(false)))]
+pub enum Error {
+    #[snafu(display("Simulator API request failed: {}", source))]
+    Request { source: crate::api::Error },
+
+    #[snafu(display("Empty response from {}", operation))]
+    MissingResponse { operation: String },
+
+    #[snafu(display("Syncing devices requires a product PRN to create them under"))]
+    MissingProductPrn,
+}
+
+/// What a virtual device is expected to be offered.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case", tag = "expect")]
+pub enum Expectation {
+    /// Any update; only getting none is flagged.
+    #[default]
+    Any,
+    NoUpdate,
+    Bundle {
+        bundle_prn: String,
+    },
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct VirtualDevice {
+    pub identifier: String,
+    #[serde(default)]
+    pub cohort_prn: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// The release version the device reports it runs.
+    #[serde(default)]
+    pub release_version: Option<String>,
+    #[serde(default)]
+    pub release_prn: Option<String>,
+    #[serde(default)]
+    pub bundle_prn: Option<String>,
+    #[serde(default)]
+    pub expectation: Expectation,
+}
+
+#[derive(Clone, Debug)]
+pub struct SimulatorOptions {
+    /// Check-ins simulated at the same time.
+    pub concurrency: usize,
+    /// Create missing devices before simulating. Existing devices are left
+    /// as they are.
+    pub sync: bool,
+    /// Product of devices created by `sync`, required when `sync` is on.
+    pub product_prn: Option<String>,
+}
+
+impl Default for SimulatorOptions {
+    fn default() -> Self {
+        Self {
+            concurrency: 8,
+            sync: false,
+            product_prn: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case", tag = "flag")]
+pub enum Flag {
+    /// The device would get no update although one was expected.
+    NoUpdate,
+    /// The device would get a different bundle than expected, or an update
+    /// when none was expected.
+    Unexpected {
+        expected: Option<String>,
+        actual: Option<String>,
+    },
+    /// The server's record differs from the virtual device, so the result
+    /// reflects the stored cohort or tags.
+    StateMismatch { field: String },
+    /// No device with this identifier exists and `sync` is off.
+    NotFound,
+    /// A dry run only planned creating the device, so there is nothing to
+    /// check in yet.
+    CreatePlanned,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SimulationResult {
+    pub identifier: String,
+    pub device_prn: Option<String>,
+    /// The device was created by this run.
+    #[serde(default)]
+    pub created: bool,
+    pub update: Option<DeviceUpdate>,
+    pub flags: Vec<Flag>,
+    pub error: Option<String>,
+}
+
+impl SimulationResult {
+    pub fn bundle_prn(&self) -> Option<&str> {
+        self.update.as_ref().and_then(|u| u.bundle_prn.as_deref())
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SimulationReport {
+    pub results: Vec<SimulationResult>,
+}
+
+impl SimulationReport {
+    /// Identifiers of the devices offered each bundle. Devices without an
+    /// update are listed under `None`.
+    pub fn by_bundle(&self) -> BTreeMap<Option<String>, Vec<String>> {
+        let mut groups: BTreeMap<Option<String>, Vec<String>> = BTreeMap::new();
+        for result in self.results.iter().filter(|r| r.error.is_none()) {
+            groups
+                .entry(result.bundle_prn().map(str::to_string))
+                .or_default()
+                .push(result.identifier.clone());
+        }
+        groups
+    }
+
+    pub fn flagged(&self) -> impl Iterator<Item = &SimulationResult> {
+        self.results
+            .iter()
+            .filter(|r| !r.flags.is_empty() || r.error.is_some())
+    }
+
+    /// PRNs of the devices created by this run.
+    pub fn created(&self) -> impl Iterator<Item = &str> {
+        self.results
+            .iter()
+            .filter(|r| r.created)
+            .filter_map(|r| r.device_prn.as_deref())
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TeardownFailure {
+    pub prn: String,
+    pub error: String,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct TeardownSummary {
+    /// PRNs of the devices that were deleted.
+    pub deleted: Vec<String>,
+    pub failures: Vec<TeardownFailure>,
+}
+
+pub struct Simulator<'a> {
+    api: &'a Api,
+    options: SimulatorOptions,
+}
+
+impl<'a> Simulator<'a> {
+    pub fn new(api: &'a Api, options: SimulatorOptions) -> Self {
+        Self { api, options }
+    }
+
+    /// Simulates a check-in of every device. Failures of single devices are
+    /// recorded in their result.
+    pub async fn run(&self, devices: Vec<VirtualDevice>) -> Result<SimulationReport, Error> {
+        if self.options.sync {
+            self.product_prn()?;
+        }
+
+        let existing: HashMap<String, Device> = list_all_devices(self.api)
+            .await
+            .context(Request)?
+            .context(MissingResponse {
+                operation: "device list",
+            })?
+            .into_iter()
+            .map(|device| (device.identifier.clone(), device))
+            .collect();
+
+        let existing = &existing;
+        let results = stream::iter(devices)
+            .map(|device| async move {
+                let mut result = SimulationResult {
+                    identifier: device.identifier.clone(),
+                    device_prn: None,
+                    created: false,
+                    update: None,
+                    flags: Vec::new(),
+                    error: None,
+                };
+                if let Err(error) = self
+                    .simulate(&device, existing.get(&device.identifier), &mut result)
+                    .await
+                {
+                    result.error = Some(error.to_string());
+                }
+                result
+            })
+            .buffered(self.options.concurrency.max(1))
+            .collect()
+            .await;
+
+        Ok(SimulationReport { results })
+    }
+
+    /// Deletes the devices `report` created. A device that fails to delete
+    /// is recorded in the summary and the rest are still deleted.
+    pub async fn teardown(&self, report: &SimulationReport) -> TeardownSummary {
+        let mut summary = TeardownSummary::default();
+        for prn in report.created() {
+            let result = self
+                .api
+                .devices()
+                .delete(DeleteDeviceParams {
+                    prn: prn.to_string(),
+                })
+                .await;
+            match result {
+                Ok(_) => summary.deleted.push(prn.to_string()),
+                Err(error) => summary.failures.push(TeardownFailure {
+                    prn: prn.to_string(),
+                    error: error.to_string(),
+                }),
+            }
+        }
+        summary
+    }
+
+    async fn simulate(
+        &self,
+        virtual_device: &VirtualDevice,
+        device: Option<&Device>,
+        result: &mut SimulationResult,
+    ) -> Result<(), Error> {
+        let prn = match (device, self.options.sync) {
+            (Some(device), _) => {
+                result.flags.extend(mismatches(virtual_device, device));
+                device.prn.clone()
+            }
+            (None, true) => match self.create(virtual_device).await? {
+                Some(prn) => {
+                    result.created = true;
+                    prn
+                }
+                None => {
+                    result.flags.push(Flag::CreatePlanned);
+                    return Ok(());
+                }
+            },
+            (None, false) => {
+                result.flags.push(Flag::NotFound);
+                return Ok(());
+            }
+        };
+        result.device_prn = Some(prn.clone());
+
+        let update = self
+            .api
+            .devices()
+            .get_update(GetUpdateDeviceParams {
+                prn,
+                release_prn: virtual_device.release_prn.clone(),
+                bundle_prn: virtual_device.bundle_prn.clone(),
+                release_version: virtual_device.release_version.clone(),
+                write: false,
+            })
+            .await
+            .context(Request)?
+            .context(MissingResponse {
+                operation: "device get update",
+            })?;
+
+        let offered = match update.status {
+            DeviceUpdateStatus::Update => Some(update.bundle_prn.clone()),
+            _ => None,
+        };
+        match (&virtual_device.expectation, offered) {
+            (Expectation::Any, None) => result.flags.push(Flag::NoUpdate),
+            (Expectation::Any, Some(_)) | (Expectation::NoUpdate, None) => {}
+            (Expectation::NoUpdate, Some(actual)) => result.flags.push(Flag::Unexpected {
+                expected: None,
+                actual,
+            }),
+            (Expectation::Bundle { .. }, None) => result.flags.push(Flag::NoUpdate),
+            (Expectation::Bundle { bundle_prn }, Some(actual)) => {
+                if actual.as_ref() != Some(bundle_prn) {
+                    result.flags.push(Flag::Unexpected {
+                        expected: Some(bundle_prn.clone()),
+                        actual,
+                    });
+                }
+            }
+        }
+        result.update = Some(update);
+
+        Ok(())
+    }
+
+    async fn create(&self, virtual_device: &VirtualDevice) -> Result<Option<String>, Error> {
+        let created = self
+            .api
+            .devices()
+            .create(CreateDeviceParams {
+                product_prn: self.product_prn()?.to_string(),
+                description: None,
+                quarantined: None,
+                identifier: virtual_device.identifier.clone(),
+                tags: Some(virtual_device.tags.clone()),
+                target: None,
+                cohort_prn: virtual_device.cohort_prn.clone(),
+            })
+            .await
+            .context(Request)?;
+        match created {
+            Some(created) => Ok(Some(created.device.prn)),
+            None if self.api.is_dry_run() => Ok(None),
+            None => MissingResponse {
+                operation: "device create",
+            }
+            .fail(),
+        }
+    }
+
+    fn product_prn(&self) -> Result<&str, Error> {
+        self.options
+            .product_prn
+            .as_deref()
+            .filter(|prn| !prn.is_empty())
+            .context(MissingProductPrn)
+    }
+}
+
+fn mismatches(virtual_device: &VirtualDevice, device: &Device) -> Vec<Flag> {
+    let mut flags = Vec::new();
+    if virtual_device.cohort_prn.is_some() && virtual_device.cohort_prn != device.cohort_prn {
+        flags.push(Flag::StateMismatch {
+            field: "cohort_prn".to_string(),
+        });
+    }
+    let mut wanted = virtual_device.tags.clone();
+    let mut stored = device.tags.clone().unwrap_or_default();
+    wanted.sort();
+    stored.sort();
+    if wanted != stored {
+        flags.push(Flag::StateMismatch {
+            field: "tags".to_string(),
+        });
+    }
+    flags
+}
@@ -0,0 +1,67 @@
+//! Planning mutations without sending them.
+//!
+//! An [`Api`](super::Api) built with [`Api::dry_run`](super::Api::dry_run)
+//! still sends requests that only read, which are `GET` requests and checking
+//! a device for an update without `write`. Every other request is logged and
+//! kept as a [`PlannedOperation`] instead. A planned request returns
+//! `Ok(None)`, as if the server had answered `204 No Content`, so callers that
+//! need the created or updated resource see nothing rather than a made-up
+//! one. The bundle builder and migration, bulk import, provisioning and the
+//! simulator report such steps as planned and carry on where they can.
+//! Helpers that need the server's answer to go on, such as CA registration
+//! or opening a tunnel, fail with their missing response error.
+//!
+//! The plan is shared between clones of the `Api` and is read back with
+//! [`Api::planned_operations`](super::Api::planned_operations).
+
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct PlannedOperation {
+    pub method: String,
+    pub path: String,
+    pub query: Vec<(String, String)>,
+    pub body: Option<String>,
+}
+
+impl PlannedOperation {
+    pub(super) fn new(path: &str, query: &[(String, String)], request: &reqwest::Request) -> Self {
+        Self {
+            method: request.method().to_string(),
+            path: path.to_string(),
+            query: query.to_vec(),
+            body: request
+                .body()
+                .and_then(reqwest::Body::as_bytes)
+                .map(|body| String::from_utf8_lossy(body).into_owned()),
+        }
+    }
+
+    /// The body parsed as JSON, if it is JSON.
+    pub fn json_body(&self) -> Option<serde_json::Value> {
+        self.body
+            .as_deref()
+            .and_then(|body| serde_json::from_str(body).ok())
+    }
+}
+
+#[derive(Debug, Default)]
+pub(super) struct Plan(Mutex<Vec<PlannedOperation>>);
+
+impl Plan {
+    pub(super) fn push(&self, operation: PlannedOperation) {
+        self.lock().push(operation);
+    }
+
+    pub(super) fn operations(&self) -> Vec<PlannedOperation> {
+        self.lock().clone()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<PlannedOperation>> {
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
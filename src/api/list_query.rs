@@ -0,0 +1,110 @@
+/// Declares a typed query for the list endpoint of one resource.
+///
+/// The query only has methods for the fields the endpoint can search and
+/// only orders by the columns of its order enum, so a filter on anything
+/// else doesn't compile. Text filters take any string, flag filters a
+/// `bool`; each call adds one term and all terms must match. The query
+/// converts into the [`ListParams`](crate::list_params::ListParams) of the
+/// resource and into its list params struct.
+macro_rules! list_query {
+    (@filter text $(#[$meta:meta])* $filter:ident $field:literal) => {
+        $(#[$meta])*
+        pub fn $filter(mut self, value: impl Into<String>) -> Self {
+            self.search.push($field, value.into());
+            self
+        }
+    };
+    (@filter flag $(#[$meta:meta])* $filter:ident $field:literal) => {
+        $(#[$meta])*
+        pub fn $filter(mut self, value: bool) -> Self {
+            self.search.push($field, value.to_string());
+            self
+        }
+    };
+    (
+        $(#[$meta:meta])*
+        pub struct $name:ident for $params:ident;
+
+        $(#[$order_meta:meta])*
+        pub enum $order:ident {
+            $($variant:ident => $column:literal,)+
+        }
+
+        filters {
+            $($(#[$filter_meta:meta])* $filter:ident($kind:ident) => $field:literal,)+
+        }
+    ) => {
+        $(#[$order_meta])*
+        #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+        pub enum $order {
+            $($variant,)+
+        }
+
+        impl $order {
+            pub fn as_str(&self) -> &'static str {
+                match self {
+                    $($order::$variant => $column,)+
+                }
+            }
+        }
+
+        $(#[$meta])*
+        #[derive(Clone, Debug, Default)]
+        pub struct $name {
+            search: $crate::list_params::SearchTerms,
+            order: Option<($order, $crate::list_params::Direction)>,
+            limit: Option<u8>,
+        }
+
+        impl $name {
+            pub fn new() -> Self {
+                Self::default()
+            }
+
+            $(list_query!(@filter $kind $(#[$filter_meta])* $filter $field);)+
+
+            pub fn order_by(
+                mut self,
+                column: $order,
+                direction: $crate::list_params::Direction,
+            ) -> Self {
+                self.order = Some((column, direction));
+                self
+            }
+
+            pub fn limit(mut self, limit: u8) -> Self {
+                self.limit = Some(limit);
+                self
+            }
+
+            /// The filters in the syntax of `ListParams::search`.
+            pub fn search(&self) -> Option<String> {
+                self.search.render()
+            }
+
+            /// The ordering in the syntax of `ListParams::order`, e.g.
+            /// `inserted_at:desc`.
+            pub fn order(&self) -> Option<String> {
+                self.order
+                    .map(|(column, direction)| format!("{}:{}", column.as_str(), direction.as_str()))
+            }
+        }
+
+        impl From<$name> for $crate::list_params::ListParams {
+            fn from(query: $name) -> Self {
+                Self {
+                    limit: query.limit,
+                    order: query.order(),
+                    search: query.search(),
+                    page: None,
+                }
+            }
+        }
+
+        impl From<$name> for $params {
+            fn from(query: $name) -> Self {
+                Self { list: query.into() }
+            }
+        }
+    };
+}
@@ -1,7 +1,12 @@
+#[macro_use]
+mod list_query;
+#[macro_use]
+mod string_enum;
 mod users;
 
 pub mod artifact_versions;
 pub mod artifacts;
+pub mod batch;
 pub mod binaries;
 pub mod binary_parts;
 pub mod binary_signatures;
@@ -9,9 +14,11 @@ pub mod bundle_overrides;
 pub mod bundle_signatures;
 pub mod bundles;
 pub mod ca_certificates;
+pub mod cassette;
 pub mod cohorts;
 pub mod device_certificates;
 pub mod devices;
+pub mod dry_run;
 pub mod error;
 pub mod events;
 pub mod products;
@@ -21,7 +28,10 @@ pub mod signing_keys;
 pub mod tunnels;
 pub mod webhooks;
 
-use log::debug;
+pub(crate) mod pagination;
+mod wait;
+
+use log::{debug, info};
 use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
 use reqwest::{header, Client, ClientBuilder, Method};
 use serde::de::DeserializeOwned;
@@ -33,6 +43,8 @@ use std::fmt::{Debug, Display};
 use std::fs::File;
 use std::io::Read;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 use validator::ValidationErrors;
 
 pub use artifacts::ArtifactsApi;
@@ -106,6 +118,63 @@ pub enum Error {
 
     #[snafu(display("Validation Errors: {}", source))]
     Validation { source: ValidationErrors },
+
+    #[snafu(display("Binary {} cannot transition from {} to {}", prn, from, to))]
+    InvalidStateTransition {
+        prn: String,
+        from: String,
+        to: String,
+    },
+
+    #[snafu(display("Binary {} needs a {} to enter {}", prn, field, state))]
+    MissingTransitionField {
+        prn: String,
+        state: String,
+        field: String,
+    },
+
+    #[snafu(display("Empty response for {}", prn))]
+    EmptyResponse { prn: String },
+
+    #[snafu(display("Empty response from {}", operation))]
+    MissingResponse { operation: String },
+
+    #[snafu(display("{} reached terminal state {}", prn, state))]
+    UnexpectedState { prn: String, state: String },
+
+    #[snafu(display(
+        "Timed out after {:?} waiting for {} (last state {})",
+        timeout,
+        prn,
+        state
+    ))]
+    WaitTimeout {
+        prn: String,
+        state: String,
+        timeout: Duration,
+    },
+
+    #[snafu(display(
+        "{} kept changing concurrently, gave up after {} attempts",
+        prn,
+        attempts
+    ))]
+    UpdateConflict { prn: String, attempts: u32 },
+
+    #[snafu(display("Cassette {}: {}", path.display(), source))]
+    CassetteIo {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Cassette {} is malformed: {}", path.display(), source))]
+    CassetteFormat {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display("No recorded interaction for {}: {}", request, reason))]
+    UnmatchedRequest { request: String, reason: String },
 }
 
 #[macro_export]
@@ -126,6 +195,8 @@ pub struct Api {
     endpoint: String,
     pub api_version: u8,
     http: Client,
+    vcr: Option<Arc<cassette::Vcr>>,
+    plan: Option<Arc<dry_run::Plan>>,
 }
 
 pub struct ApiOptions {
@@ -173,9 +244,50 @@ impl Api {
                 .unwrap_or_else(|| LATEST_ENDPOINT.into()),
             api_version: api_options.api_version,
             http: client,
+            vcr: None,
+            plan: None,
         }
     }
 
+    /// Keeps every request that changes something made through the returned
+    /// `Api` as a planned operation instead of sending it. Planned requests
+    /// return `Ok(None)`; see [`dry_run`].
+    pub fn dry_run(mut self) -> Self {
+        self.plan = Some(Default::default());
+        self
+    }
+
+    pub fn is_dry_run(&self) -> bool {
+        self.plan.is_some()
+    }
+
+    /// The requests a dry run didn't send, in the order they were made.
+    pub fn planned_operations(&self) -> Vec<dry_run::PlannedOperation> {
+        self.plan
+            .as_ref()
+            .map(|plan| plan.operations())
+            .unwrap_or_default()
+    }
+
+    /// Records every request made through the returned `Api` and its
+    /// response into a new cassette at `path`, replacing any existing file.
+    pub fn record(mut self, path: impl Into<PathBuf>) -> Self {
+        self.vcr = Some(Arc::new(cassette::Vcr::record(path.into())));
+        self
+    }
+
+    /// Answers every request made through the returned `Api` from the
+    /// cassette at `path` instead of the network.
+    pub async fn replay(
+        mut self,
+        path: impl AsRef<std::path::Path>,
+        matching: cassette::Matching,
+    ) -> Result<Self, Error> {
+        let cassette = cassette::Cassette::read(path).await?;
+        self.vcr = Some(Arc::new(cassette::Vcr::replay(cassette, matching)));
+        Ok(self)
+    }
+
     async fn execute<P, T>(
         &self,
         method: Method,
@@ -186,7 +298,25 @@ impl Api {
         P: AsRef<str> + Display,
         T: DeserializeOwned,
     {
-        self.execute_full(method, path, body, vec![], vec![]).await
+        let writes = method != Method::GET;
+        self.execute_full(method, path, body, vec![], vec![], writes)
+            .await
+    }
+
+    /// Like [`Api::execute`] for a request that doesn't change anything
+    /// although its method isn't `GET`, so a dry run still sends it.
+    async fn execute_read_only<P, T>(
+        &self,
+        method: Method,
+        path: P,
+        body: Option<BodyType>,
+    ) -> Result<Option<T>, Error>
+    where
+        P: AsRef<str> + Display,
+        T: DeserializeOwned,
+    {
+        self.execute_full(method, path, body, vec![], vec![], false)
+            .await
     }
 
     async fn execute_with_params<P, T>(
@@ -200,7 +330,9 @@ impl Api {
         P: AsRef<str> + Display,
         T: DeserializeOwned,
     {
-        self.execute_full(method, path, body, params, vec![]).await
+        let writes = method != Method::GET;
+        self.execute_full(method, path, body, params, vec![], writes)
+            .await
     }
 
     async fn execute_with_headers<P, T>(
@@ -214,7 +346,9 @@ impl Api {
         P: AsRef<str> + Display,
         T: DeserializeOwned,
     {
-        self.execute_full(method, path, body, vec![], headers).await
+        let writes = method != Method::GET;
+        self.execute_full(method, path, body, vec![], headers, writes)
+            .await
     }
 
     async fn execute_full<P, T>(
@@ -224,6 +358,7 @@ impl Api {
         body: Option<BodyType>,
         params: Vec<(String, String)>,
         headers: Vec<(String, String)>,
+        writes: bool,
     ) -> Result<Option<T>, Error>
     where
         P: AsRef<str> + Display,
@@ -256,21 +391,53 @@ impl Api {
             None => req_builder.build(),
         };
 
-        let res = self
-            .http
-            .execute(req.context(BadRequestParams)?)
-            .await
-            .context(RequestFailed)?;
+        let req = req.context(BadRequestParams)?;
+
+        if let Some(plan) = &self.plan {
+            if writes {
+                let operation = dry_run::PlannedOperation::new(path.as_ref(), &params, &req);
+                info!(
+                    "Dry run, not sending {} {}",
+                    operation.method, operation.path
+                );
+                plan.push(operation);
+                return Ok(None);
+            }
+        }
+        let recorded = self
+            .vcr
+            .as_ref()
+            .map(|_| cassette::RecordedRequest::new(path.as_ref(), &params, &req));
+
+        let (status_code, response_body) = match (self.vcr.as_deref(), recorded) {
+            (Some(vcr @ cassette::Vcr::Replay { .. }), Some(recorded)) => {
+                let response = vcr.play(&recorded)?;
+                (response.status, response.body)
+            }
+            (vcr, recorded) => {
+                let res = self.http.execute(req).await.context(RequestFailed)?;
 
-        let status_code = res.status().as_u16();
-        debug!("Response status code: {}", status_code);
+                let status_code = res.status().as_u16();
+
+                // Log peridio-request-id header if present
+                if let Some(request_id) = res.headers().get("peridio-request-id") {
+                    if let Ok(request_id_str) = request_id.to_str() {
+                        debug!("peridio-request-id: {}", request_id_str);
+                    }
+                }
 
-        // Log peridio-request-id header if present
-        if let Some(request_id) = res.headers().get("peridio-request-id") {
-            if let Ok(request_id_str) = request_id.to_str() {
-                debug!("peridio-request-id: {}", request_id_str);
+                let response_body = res.text().await.context(BadResponse)?;
+                if let (Some(vcr), Some(recorded)) = (vcr, recorded) {
+                    let response = cassette::RecordedResponse {
+                        status: status_code,
+                        body: response_body.clone(),
+                    };
+                    vcr.save(recorded, response).await?;
+                }
+                (status_code, response_body)
             }
-        }
+        };
+        debug!("Response status code: {}", status_code);
 
         match status_code {
             204 => {
@@ -278,8 +445,6 @@ impl Api {
                 Ok(None)
             }
             200..=299 => {
-                let response_body = res.text().await.context(BadResponse)?;
-
                 // Try to format as JSON for debug logging
                 match serde_json::from_str::<serde_json::Value>(&response_body) {
                     Ok(json_value) => {
@@ -301,8 +466,6 @@ impl Api {
                 Ok(Some(res))
             }
             _ => {
-                let response_body = res.text().await.context(BadResponse)?;
-
                 // Try to format as JSON for debug logging
                 match serde_json::from_str::<serde_json::Value>(&response_body) {
                     Ok(json_value) => {
@@ -0,0 +1,101 @@
+//! Collecting every page of a list endpoint.
+
+use std::future::Future;
+
+use super::artifact_versions::{ArtifactVersion, ListArtifactVersionsResponse};
+use super::artifacts::{Artifact, ListArtifactsResponse};
+use super::binaries::{Binary, ListBinariesResponse};
+use super::bundle_overrides::{BundleOverride, ListBundleOverridesResponse};
+use super::bundles::{Bundle, ListBundlesResponse};
+use super::ca_certificates::{CaCertificate, ListCaCertificateResponse};
+use super::device_certificates::{DeviceCertificate, ListDeviceCertificateResponse};
+use super::devices::{Device, ListDeviceResponse};
+use super::releases::{ListReleasesResponse, Release};
+use super::tunnels::{ListTunnelsResponse, Tunnel};
+use super::Error;
+
+/// A response of a paginated list endpoint.
+pub(crate) trait Page {
+    type Item;
+
+    fn into_page(self) -> (Vec<Self::Item>, Option<String>);
+}
+
+macro_rules! page {
+    ($($response:ty => $items:ident: $item:ty,)*) => {
+        $(
+            impl Page for $response {
+                type Item = $item;
+
+                fn into_page(self) -> (Vec<$item>, Option<String>) {
+                    (self.$items, self.next_page)
+                }
+            }
+        )*
+    };
+}
+
+page! {
+    ListArtifactVersionsResponse => artifact_versions: ArtifactVersion,
+    ListArtifactsResponse => artifacts: Artifact,
+    ListBinariesResponse => binaries: Binary,
+    ListBundleOverridesResponse => bundle_overrides: BundleOverride,
+    ListBundlesResponse => bundles: Bundle,
+    ListCaCertificateResponse => ca_certificates: CaCertificate,
+    ListDeviceCertificateResponse => device_certificates: DeviceCertificate,
+    ListDeviceResponse => devices: Device,
+    ListReleasesResponse => releases: Release,
+    ListTunnelsResponse => tunnels: Tunnel,
+}
+
+/// Calls `list` with the `next_page` of the previous response, starting from
+/// `None`, until a response has no next page, and returns the items of all
+/// pages in order. `None` if a page came back empty, like the list methods.
+///
+/// ```ignore
+/// let devices = list_all(|page| async move {
+///     api.devices()
+///         .list(ListDeviceParams {
+///             list: ListParams { page, ..DeviceQuery::new().cohort(prn).into() },
+///         })
+///         .await
+/// })
+/// .await?;
+/// ```
+pub(crate) async fn list_all<R, F, Fut>(list: F) -> Result<Option<Vec<R::Item>>, Error>
+where
+    R: Page,
+    F: FnMut(Option<String>) -> Fut,
+    Fut: Future<Output = Result<Option<R>, Error>>,
+{
+    list_until(list, |_| false).await
+}
+
+/// Like [`list_all`], but stops requesting pages once `done` returns `true`
+/// for the items collected so far, e.g. when a lookup found its match.
+pub(crate) async fn list_until<R, F, Fut>(
+    mut list: F,
+    mut done: impl FnMut(&[R::Item]) -> bool,
+) -> Result<Option<Vec<R::Item>>, Error>
+where
+    R: Page,
+    F: FnMut(Option<String>) -> Fut,
+    Fut: Future<Output = Result<Option<R>, Error>>,
+{
+    let mut items = Vec::new();
+    let mut page = None;
+    loop {
+        let Some(response) = list(page).await? else {
+            return Ok(None);
+        };
+        let (page_items, next_page) = response.into_page();
+        items.extend(page_items);
+        if done(&items) {
+            return Ok(Some(items));
+        }
+        page = match next_page {
+            Some(next_page) => Some(next_page),
+            None => return Ok(Some(items)),
+        };
+    }
+}
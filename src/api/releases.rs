@@ -1,3 +1,6 @@
+pub mod resolver;
+pub mod rollout;
+
 use reqwest::Method;
 use serde::{Deserialize, Serialize};
 
@@ -6,6 +9,20 @@ use crate::{json_body, list_params::ListParams, Api};
 use super::Error;
 use snafu::ResultExt;
 
+string_enum! {
+    /// How devices in a release's cohort are selected for the release.
+    pub enum PhaseMode {
+        Tags => "tags",
+        Numeric => "numeric",
+    }
+}
+
+string_enum! {
+    pub enum PhaseType {
+        Static => "static",
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Release {
     pub bundle_prn: String,
@@ -16,9 +33,9 @@ pub struct Release {
     pub name: String,
     pub next_release_prn: Option<String>,
     pub organization_prn: String,
-    pub phase_mode: Option<String>,
+    pub phase_mode: Option<PhaseMode>,
     pub phase_tags: Option<Vec<String>>,
-    pub phase_type: Option<String>,
+    pub phase_type: Option<PhaseType>,
     pub phase_value: Option<f64>,
     pub required: bool,
     pub schedule_date: String,
@@ -43,7 +60,7 @@ pub struct CreateReleaseParams {
     pub next_release_prn: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default)]
-    pub phase_mode: Option<String>,
+    pub phase_mode: Option<PhaseMode>,
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default)]
     pub phase_tags: Option<Vec<String>>,
@@ -87,6 +104,26 @@ pub struct ListReleasesParams {
     pub list: ListParams,
 }
 
+list_query! {
+    /// Typed filters and ordering for [`ReleasesApi::list`].
+    pub struct ReleaseQuery for ListReleasesParams;
+
+    pub enum ReleaseOrder {
+        InsertedAt => "inserted_at",
+        UpdatedAt => "updated_at",
+        Name => "name",
+        Version => "version",
+    }
+
+    filters {
+        cohort(text) => "cohort_prn",
+        bundle(text) => "bundle_prn",
+        name(text) => "name",
+        version(text) => "version",
+        disabled(flag) => "disabled",
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ListReleasesResponse {
     pub releases: Vec<Release>,
@@ -98,6 +135,9 @@ pub struct UpdateReleaseParams {
     pub prn: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default)]
+    pub bundle_prn: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
     pub description: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default)]
@@ -110,7 +150,7 @@ pub struct UpdateReleaseParams {
     pub next_release_prn: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default)]
-    pub phase_mode: Option<String>,
+    pub phase_mode: Option<PhaseMode>,
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default)]
     pub phase_tags: Option<Vec<String>>,
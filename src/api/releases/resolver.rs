@@ -0,0 +1,554 @@
+//! Local prediction of the release and bundle a device will be offered.
+//!
+//! The resolver mirrors the server's update resolution so rollouts can be
+//! previewed before they are scheduled:
+//!
+//! 1. An active bundle override the device belongs to always wins.
+//! 2. Otherwise the device's cohort releases are walked in the order the
+//!    server links them through `next_release_prn`. Releases outside any
+//!    chain are ordered by version, so without links the walk is in version
+//!    order, and a chain is walked where its earliest version would be.
+//!    Disabled releases, releases scheduled in the future, releases whose
+//!    `version_requirement` does not match the device's reported version and
+//!    releases whose phase excludes the device are skipped.
+//! 3. A `required` release cannot be skipped, so resolution stops at the first
+//!    required release newer than the device's reported version whose phase
+//!    includes the device.
+//!
+//! Numeric phases are assigned by the server as devices check in. Locally the
+//! device is placed in a stable bucket derived from its PRN, so the prediction
+//! for a numeric phase is an estimate of the share of devices included.
+
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use snafu::{ResultExt, Snafu};
+
+use crate::api::bundle_overrides::BundleOverride;
+use crate::api::devices::Device;
+
+use super::{PhaseMode, Release};
+
+#[derive(Debug, Snafu)]
+#[snafu(context(suffix(false)))]
+pub enum Error {
+    #[snafu(display("Invalid version requirement '{}': {}", requirement, reason))]
+    InvalidRequirement { requirement: String, reason: String },
+
+    #[snafu(display("Invalid version '{}': {}", version, source))]
+    InvalidVersion {
+        version: String,
+        source: semver::Error,
+    },
+
+    #[snafu(display("Invalid timestamp '{}': {}", value, source))]
+    InvalidTimestamp {
+        value: String,
+        source: chrono::ParseError,
+    },
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+    Compatible,
+}
+
+#[derive(Clone, Debug)]
+struct Comparator {
+    op: Op,
+    version: Version,
+    // Number of components given in the requirement, used by `~>`.
+    components: usize,
+}
+
+impl Comparator {
+    fn matches(&self, version: &Version) -> bool {
+        let ordering = cmp_precedence(version, &self.version);
+        match self.op {
+            Op::Eq => ordering == Ordering::Equal,
+            Op::Ne => ordering != Ordering::Equal,
+            Op::Gt => ordering == Ordering::Greater,
+            Op::Ge => ordering != Ordering::Less,
+            Op::Lt => ordering == Ordering::Less,
+            Op::Le => ordering != Ordering::Greater,
+            Op::Compatible => {
+                let upper = if self.components <= 2 {
+                    Version::new(self.version.major + 1, 0, 0)
+                } else {
+                    Version::new(self.version.major, self.version.minor + 1, 0)
+                };
+                ordering != Ordering::Less && cmp_precedence(version, &upper) == Ordering::Less
+            }
+        }
+    }
+}
+
+/// A parsed `version_requirement`.
+///
+/// Requirements use the server's syntax: comparisons such as `== 1.0.0`,
+/// `>= 1.2.0` or `~> 2.1` combined with `and` / `or`, where `and` binds
+/// tighter than `or`. A bare version is treated as an exact match.
+#[derive(Clone, Debug)]
+pub struct VersionRequirement {
+    raw: String,
+    alternatives: Vec<Vec<Comparator>>,
+}
+
+impl VersionRequirement {
+    pub fn matches(&self, version: &Version) -> bool {
+        self.alternatives
+            .iter()
+            .any(|clauses| clauses.iter().all(|c| c.matches(version)))
+    }
+}
+
+impl fmt::Display for VersionRequirement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.raw)
+    }
+}
+
+impl FromStr for VersionRequirement {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: &str| Error::InvalidRequirement {
+            requirement: input.to_string(),
+            reason: reason.to_string(),
+        };
+
+        let mut alternatives = Vec::new();
+        for alternative in split_keyword(input, "or") {
+            let mut clauses = Vec::new();
+            for clause in split_keyword(&alternative, "and") {
+                clauses.push(parse_comparator(&clause).map_err(|reason| invalid(&reason))?);
+            }
+            if clauses.is_empty() {
+                return Err(invalid("empty clause"));
+            }
+            alternatives.push(clauses);
+        }
+
+        if alternatives.is_empty() {
+            return Err(invalid("empty requirement"));
+        }
+
+        Ok(Self {
+            raw: input.trim().to_string(),
+            alternatives,
+        })
+    }
+}
+
+fn split_keyword(input: &str, keyword: &str) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut current: Vec<&str> = Vec::new();
+    for token in input.split_whitespace() {
+        if token == keyword {
+            parts.push(current.join(" "));
+            current.clear();
+        } else {
+            current.push(token);
+        }
+    }
+    parts.push(current.join(" "));
+    parts.retain(|part| !part.is_empty());
+    parts
+}
+
+fn parse_comparator(input: &str) -> Result<Comparator, String> {
+    let input = input.trim();
+    let (op, rest) = [
+        ("==", Op::Eq),
+        ("!=", Op::Ne),
+        (">=", Op::Ge),
+        ("<=", Op::Le),
+        ("~>", Op::Compatible),
+        (">", Op::Gt),
+        ("<", Op::Lt),
+        ("=", Op::Eq),
+    ]
+    .iter()
+    .find_map(|(token, op)| input.strip_prefix(token).map(|rest| (*op, rest)))
+    .unwrap_or((Op::Eq, input));
+
+    let rest = rest.trim();
+    if rest.is_empty() {
+        return Err(format!("missing version in '{input}'"));
+    }
+
+    let core = rest.split(['-', '+']).next().unwrap_or(rest);
+    let components = core.split('.').count();
+    if components > 3 {
+        return Err(format!("too many version components in '{rest}'"));
+    }
+    if components < 3 && op != Op::Compatible {
+        return Err(format!("'{rest}' must be a full version"));
+    }
+
+    let padded = match components {
+        1 => format!("{rest}.0.0"),
+        2 => format!("{rest}.0"),
+        _ => rest.to_string(),
+    };
+    let version = Version::parse(&padded).map_err(|e| e.to_string())?;
+
+    Ok(Comparator {
+        op,
+        version,
+        components,
+    })
+}
+
+// Semver precedence ignores build metadata.
+fn cmp_precedence(a: &Version, b: &Version) -> Ordering {
+    (a.major, a.minor, a.patch, &a.pre).cmp(&(b.major, b.minor, b.patch, &b.pre))
+}
+
+/// The device attributes that influence resolution.
+#[derive(Clone, Debug, Default)]
+pub struct DeviceState {
+    pub prn: String,
+    pub cohort_prn: Option<String>,
+    pub tags: Vec<String>,
+    pub release_version: Option<String>,
+}
+
+impl From<&Device> for DeviceState {
+    fn from(device: &Device) -> Self {
+        Self {
+            prn: device.prn.clone(),
+            cohort_prn: device.cohort_prn.clone(),
+            tags: device.tags.clone().unwrap_or_default(),
+            release_version: device.reported_release_version.clone(),
+        }
+    }
+}
+
+/// A bundle override together with the devices it applies to.
+#[derive(Debug)]
+pub struct OverrideMembership<'a> {
+    pub bundle_override: &'a BundleOverride,
+    pub device_prns: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case", tag = "reason")]
+pub enum SkipReason {
+    OtherCohort,
+    Disabled,
+    Scheduled { schedule_date: String },
+    NotNewer,
+    RequirementNotMet { version_requirement: String },
+    NotInPhase,
+    BlockedByRequired { release_prn: String },
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct SkippedRelease {
+    pub release_prn: String,
+    #[serde(flatten)]
+    pub reason: SkipReason,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum Outcome {
+    BundleOverride {
+        bundle_override_prn: String,
+        bundle_prn: String,
+    },
+    Release {
+        release_prn: String,
+        bundle_prn: String,
+        version: Option<String>,
+    },
+    NoUpdate,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct Resolution {
+    pub outcome: Outcome,
+    pub skipped: Vec<SkippedRelease>,
+}
+
+impl Resolution {
+    pub fn bundle_prn(&self) -> Option<&str> {
+        match &self.outcome {
+            Outcome::BundleOverride { bundle_prn, .. } | Outcome::Release { bundle_prn, .. } => {
+                Some(bundle_prn)
+            }
+            Outcome::NoUpdate => None,
+        }
+    }
+}
+
+/// Predicts updates for devices from a cohort's releases and the bundle
+/// overrides in effect.
+pub struct ReleaseResolver<'a> {
+    releases: &'a [Release],
+    overrides: Vec<OverrideMembership<'a>>,
+    now: DateTime<Utc>,
+}
+
+impl<'a> ReleaseResolver<'a> {
+    pub fn new(releases: &'a [Release]) -> Self {
+        Self {
+            releases,
+            overrides: Vec::new(),
+            now: Utc::now(),
+        }
+    }
+
+    pub fn with_override(mut self, membership: OverrideMembership<'a>) -> Self {
+        self.overrides.push(membership);
+        self
+    }
+
+    /// Evaluates schedules and override windows at `now` instead of the
+    /// current time.
+    pub fn at(mut self, now: DateTime<Utc>) -> Self {
+        self.now = now;
+        self
+    }
+
+    pub fn resolve(&self, device: &DeviceState) -> Result<Resolution, Error> {
+        if let Some(bundle_override) = self.active_override(device)? {
+            return Ok(Resolution {
+                outcome: Outcome::BundleOverride {
+                    bundle_override_prn: bundle_override.prn.clone(),
+                    bundle_prn: bundle_override.bundle_prn.clone(),
+                },
+                skipped: Vec::new(),
+            });
+        }
+
+        let current = device
+            .release_version
+            .as_deref()
+            .map(|v| Version::parse(v).context(InvalidVersion { version: v }))
+            .transpose()?;
+
+        let mut skipped = Vec::new();
+        let mut candidates = Vec::new();
+        for release in self.releases {
+            let skip = |reason| SkippedRelease {
+                release_prn: release.prn.clone(),
+                reason,
+            };
+
+            if device.cohort_prn.as_deref() != Some(release.cohort_prn.as_str()) {
+                skipped.push(skip(SkipReason::OtherCohort));
+                continue;
+            }
+
+            if release.disabled.unwrap_or(false) {
+                skipped.push(skip(SkipReason::Disabled));
+                continue;
+            }
+
+            if parse_timestamp(&release.schedule_date)? > self.now {
+                skipped.push(skip(SkipReason::Scheduled {
+                    schedule_date: release.schedule_date.clone(),
+                }));
+                continue;
+            }
+
+            let version = release
+                .version
+                .as_deref()
+                .map(|v| Version::parse(v).context(InvalidVersion { version: v }))
+                .transpose()?;
+
+            if let (Some(current), Some(version)) = (&current, &version) {
+                if cmp_precedence(version, current) != Ordering::Greater {
+                    skipped.push(skip(SkipReason::NotNewer));
+                    continue;
+                }
+            }
+
+            candidates.push((release, version));
+        }
+
+        // Releases without a version come before versioned ones, so the
+        // order stays total when only some releases carry a version.
+        candidates.sort_by(|(a, av), (b, bv)| {
+            av.is_some()
+                .cmp(&bv.is_some())
+                .then_with(|| match (av, bv) {
+                    (Some(av), Some(bv)) => cmp_precedence(av, bv),
+                    _ => Ordering::Equal,
+                })
+                .then_with(|| a.schedule_date.cmp(&b.schedule_date))
+        });
+        let candidates = chain_order(candidates, self.releases);
+
+        let mut selected: Option<&Release> = None;
+        let mut blocked_by: Option<&Release> = None;
+        for (release, _) in candidates {
+            let skip = |reason| SkippedRelease {
+                release_prn: release.prn.clone(),
+                reason,
+            };
+
+            if let Some(blocker) = blocked_by {
+                skipped.push(skip(SkipReason::BlockedByRequired {
+                    release_prn: blocker.prn.clone(),
+                }));
+                continue;
+            }
+
+            if let Some(requirement) = release.version_requirement.as_deref() {
+                let parsed: VersionRequirement = requirement.parse()?;
+                if !current.as_ref().is_some_and(|v| parsed.matches(v)) {
+                    skipped.push(skip(SkipReason::RequirementNotMet {
+                        version_requirement: requirement.to_string(),
+                    }));
+                    continue;
+                }
+            }
+
+            if !in_phase(release, device) {
+                skipped.push(skip(SkipReason::NotInPhase));
+                continue;
+            }
+
+            if release.required {
+                blocked_by = Some(release);
+            }
+
+            selected = Some(release);
+        }
+
+        let outcome = match selected {
+            Some(release) => Outcome::Release {
+                release_prn: release.prn.clone(),
+                bundle_prn: release.bundle_prn.clone(),
+                version: release.version.clone(),
+            },
+            None => Outcome::NoUpdate,
+        };
+
+        Ok(Resolution { outcome, skipped })
+    }
+
+    fn active_override(&self, device: &DeviceState) -> Result<Option<&'a BundleOverride>, Error> {
+        let mut active: Option<(&'a BundleOverride, DateTime<Utc>)> = None;
+        for membership in &self.overrides {
+            if !membership.device_prns.iter().any(|prn| prn == &device.prn) {
+                continue;
+            }
+
+            let bundle_override = membership.bundle_override;
+            let starts_at = parse_timestamp(&bundle_override.starts_at)?;
+            let ended = match bundle_override.ends_at.as_deref() {
+                Some(ends_at) => parse_timestamp(ends_at)? <= self.now,
+                None => false,
+            };
+
+            if starts_at > self.now || ended {
+                continue;
+            }
+
+            // The most recently started override takes precedence.
+            if active.is_none_or(|(_, latest)| starts_at > latest) {
+                active = Some((bundle_override, starts_at));
+            }
+        }
+
+        Ok(active.map(|(bundle_override, _)| bundle_override))
+    }
+}
+
+fn parse_timestamp(value: &str) -> Result<DateTime<Utc>, Error> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|dt| dt.with_timezone(&Utc))
+        .context(InvalidTimestamp { value })
+}
+
+/// Reorders `candidates`, sorted by version, along the `next_release_prn`
+/// links between `releases`. Links are followed through releases that are
+/// not candidates, so skipping one does not break its chain. Each chain takes
+/// the place of its earliest candidate and keeps its own order.
+fn chain_order<'r>(
+    mut candidates: Vec<(&'r Release, Option<Version>)>,
+    releases: &'r [Release],
+) -> Vec<(&'r Release, Option<Version>)> {
+    let by_prn: HashMap<&str, &Release> = releases.iter().map(|r| (r.prn.as_str(), r)).collect();
+    let linked: HashSet<&str> = releases
+        .iter()
+        .filter_map(|r| r.next_release_prn.as_deref())
+        .collect();
+
+    // Chain head and step of every release reachable from a head.
+    let mut positions: HashMap<&str, (&str, usize)> = HashMap::new();
+    for head in releases.iter().filter(|r| !linked.contains(r.prn.as_str())) {
+        let mut current = Some(head);
+        let mut step = 0;
+        while let Some(release) = current {
+            if positions.contains_key(release.prn.as_str()) {
+                break;
+            }
+            positions.insert(&release.prn, (&head.prn, step));
+            step += 1;
+            current = release
+                .next_release_prn
+                .as_deref()
+                .and_then(|prn| by_prn.get(prn).copied());
+        }
+    }
+    let position = |release: &'r Release| {
+        positions
+            .get(release.prn.as_str())
+            .copied()
+            .unwrap_or((&release.prn, 0))
+    };
+
+    let mut ranks: HashMap<&str, usize> = HashMap::new();
+    for (release, _) in &candidates {
+        let next = ranks.len();
+        ranks.entry(position(release).0).or_insert(next);
+    }
+    candidates.sort_by_key(|(release, _)| {
+        let (head, step) = position(release);
+        (ranks[head], step)
+    });
+    candidates
+}
+
+fn in_phase(release: &Release, device: &DeviceState) -> bool {
+    match release.phase_mode {
+        Some(PhaseMode::Tags) => release
+            .phase_tags
+            .as_ref()
+            .is_some_and(|tags| tags.iter().any(|tag| device.tags.contains(tag))),
+        Some(PhaseMode::Numeric) => match release.phase_value {
+            Some(value) => phase_bucket(&device.prn) < value,
+            None => true,
+        },
+        _ => true,
+    }
+}
+
+/// Places a device in a stable bucket in `[0, 1)` used to estimate numeric
+/// phase membership.
+pub fn phase_bucket(device_prn: &str) -> f64 {
+    // FNV-1a, stable across platforms and releases.
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in device_prn.as_bytes() {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    (hash % 10_000) as f64 / 10_000.0
+}
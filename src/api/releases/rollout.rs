@@ -0,0 +1,387 @@
+//! Phased rollout controller for releases.
+//!
+//! A [`RolloutController`] advances a release's `phase_value` through a
+//! [`RolloutPlan`], waiting after every step and consulting a health gate
+//! before moving on. When the gate fails the rollout is paused or the release
+//! is disabled, depending on [`GateFailureAction`]. Progress is persisted to a
+//! JSON state file after every transition so a restarted controller picks up
+//! where the previous one stopped.
+
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use snafu::{ResultExt, Snafu};
+
+use crate::Api;
+
+use super::UpdateReleaseParams;
+
+#[derive(Debug, Snafu)]
+#[snafu(context(suffix(false)))]
+pub enum Error {
+    #[snafu(display("Rollout API request failed: {}", source))]
+    Request { source: crate::api::Error },
+
+    #[snafu(display("Invalid rollout plan: {}", reason))]
+    InvalidPlan { reason: String },
+
+    #[snafu(display("Failed to access rollout state {}: {}", path.display(), source))]
+    StateIo {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Failed to decode rollout state {}: {}", path.display(), source))]
+    StateFormat {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display(
+        "Rollout state {} belongs to release {}, not {}",
+        path.display(),
+        found,
+        expected
+    ))]
+    StateMismatch {
+        path: PathBuf,
+        expected: String,
+        found: String,
+    },
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct RolloutStep {
+    pub phase_value: f64,
+    /// How long to let the step soak before the health gate is consulted.
+    #[serde(with = "duration_secs")]
+    pub wait: Duration,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct RolloutPlan {
+    pub release_prn: String,
+    pub steps: Vec<RolloutStep>,
+}
+
+impl RolloutPlan {
+    /// Builds a plan that applies `phase_values` in order with the same wait
+    /// after each step.
+    pub fn uniform(release_prn: impl Into<String>, phase_values: &[f64], wait: Duration) -> Self {
+        Self {
+            release_prn: release_prn.into(),
+            steps: phase_values
+                .iter()
+                .map(|phase_value| RolloutStep {
+                    phase_value: *phase_value,
+                    wait,
+                })
+                .collect(),
+        }
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        if self.steps.is_empty() {
+            return InvalidPlan {
+                reason: "plan has no steps",
+            }
+            .fail();
+        }
+
+        let mut previous = 0.0;
+        for step in &self.steps {
+            if !(0.0..=1.0).contains(&step.phase_value) {
+                return InvalidPlan {
+                    reason: format!("phase value {} is outside 0.0..=1.0", step.phase_value),
+                }
+                .fail();
+            }
+            if step.phase_value < previous {
+                return InvalidPlan {
+                    reason: format!(
+                        "phase value {} is lower than the previous step",
+                        step.phase_value
+                    ),
+                }
+                .fail();
+            }
+            previous = step.phase_value;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub enum GateFailureAction {
+    /// Stop advancing and leave the current phase in place.
+    #[default]
+    Pause,
+    /// Disable the release so no further devices receive it.
+    Disable,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RolloutStatus {
+    Running,
+    Paused,
+    Disabled,
+    Completed,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct RolloutState {
+    pub release_prn: String,
+    pub status: RolloutStatus,
+    /// Index of the step currently applied, if any.
+    pub current_step: Option<usize>,
+    pub applied_at: Option<DateTime<Utc>>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl RolloutState {
+    fn new(release_prn: &str) -> Self {
+        Self {
+            release_prn: release_prn.to_string(),
+            status: RolloutStatus::Running,
+            current_step: None,
+            applied_at: None,
+            updated_at: Utc::now(),
+        }
+    }
+}
+
+/// What the health gate is asked to judge.
+#[derive(Clone, Debug)]
+pub struct GateContext {
+    pub release_prn: String,
+    pub step: usize,
+    pub phase_value: f64,
+}
+
+pub struct RolloutController<'a, G> {
+    api: &'a Api,
+    plan: RolloutPlan,
+    state_path: PathBuf,
+    gate: G,
+    on_failure: GateFailureAction,
+}
+
+impl<'a, G, Fut> RolloutController<'a, G>
+where
+    G: FnMut(GateContext) -> Fut,
+    Fut: Future<Output = bool>,
+{
+    pub fn new(
+        api: &'a Api,
+        plan: RolloutPlan,
+        state_path: impl Into<PathBuf>,
+        gate: G,
+    ) -> Result<Self, Error> {
+        plan.validate()?;
+
+        Ok(Self {
+            api,
+            plan,
+            state_path: state_path.into(),
+            gate,
+            on_failure: GateFailureAction::default(),
+        })
+    }
+
+    pub fn on_failure(mut self, action: GateFailureAction) -> Self {
+        self.on_failure = action;
+        self
+    }
+
+    pub async fn state(&self) -> Result<RolloutState, Error> {
+        load_state(&self.state_path, &self.plan.release_prn).await
+    }
+
+    /// Drives the rollout until it completes, pauses or the release is
+    /// disabled, and returns the final state.
+    ///
+    /// Paused and disabled rollouts are left untouched; call
+    /// [`RolloutController::resume`] to continue one.
+    pub async fn run(&mut self) -> Result<RolloutState, Error> {
+        let mut state = self.state().await?;
+
+        while state.status == RolloutStatus::Running {
+            let step_index = match state.current_step {
+                Some(current) if state.applied_at.is_some() => current,
+                Some(current) => current + 1,
+                None => 0,
+            };
+
+            let Some(step) = self.plan.steps.get(step_index).cloned() else {
+                state.status = RolloutStatus::Completed;
+                self.save(&mut state).await?;
+                break;
+            };
+
+            let applied_at = match (state.current_step, state.applied_at) {
+                (Some(current), Some(applied_at)) if current == step_index => applied_at,
+                _ => {
+                    self.apply(step_index, &step).await?;
+                    let applied_at = Utc::now();
+                    state.current_step = Some(step_index);
+                    state.applied_at = Some(applied_at);
+                    self.save(&mut state).await?;
+                    applied_at
+                }
+            };
+
+            // Only wait for what is left of the soak when resuming mid-step.
+            let elapsed = (Utc::now() - applied_at).to_std().unwrap_or_default();
+            if let Some(remaining) = step.wait.checked_sub(elapsed) {
+                tokio::time::sleep(remaining).await;
+            }
+
+            let healthy = (self.gate)(GateContext {
+                release_prn: self.plan.release_prn.clone(),
+                step: step_index,
+                phase_value: step.phase_value,
+            })
+            .await;
+
+            if healthy {
+                debug!(
+                    "Rollout of {} passed health gate at step {}",
+                    self.plan.release_prn, step_index
+                );
+                state.applied_at = None;
+            } else {
+                debug!(
+                    "Rollout of {} failed health gate at step {}",
+                    self.plan.release_prn, step_index
+                );
+                state.status = match self.on_failure {
+                    GateFailureAction::Pause => RolloutStatus::Paused,
+                    GateFailureAction::Disable => {
+                        self.set_disabled(true).await?;
+                        RolloutStatus::Disabled
+                    }
+                };
+            }
+
+            self.save(&mut state).await?;
+        }
+
+        Ok(state)
+    }
+
+    /// Continues a paused or disabled rollout from the step it stopped at.
+    /// A disabled release is re-enabled first.
+    pub async fn resume(&mut self) -> Result<RolloutState, Error> {
+        let mut state = self.state().await?;
+
+        match state.status {
+            RolloutStatus::Paused => {}
+            RolloutStatus::Disabled => self.set_disabled(false).await?,
+            RolloutStatus::Running | RolloutStatus::Completed => return self.run().await,
+        }
+
+        // The failed step has to soak and pass the gate again.
+        state.status = RolloutStatus::Running;
+        state.applied_at = state.current_step.map(|_| Utc::now());
+        self.save(&mut state).await?;
+
+        self.run().await
+    }
+
+    async fn apply(&self, step_index: usize, step: &RolloutStep) -> Result<(), Error> {
+        debug!(
+            "Rollout of {} applying step {} (phase_value {})",
+            self.plan.release_prn, step_index, step.phase_value
+        );
+
+        self.api
+            .releases()
+            .update(UpdateReleaseParams {
+                phase_value: Some(step.phase_value),
+                ..update_params(&self.plan.release_prn)
+            })
+            .await
+            .context(Request)?;
+
+        Ok(())
+    }
+
+    async fn set_disabled(&self, disabled: bool) -> Result<(), Error> {
+        self.api
+            .releases()
+            .update(UpdateReleaseParams {
+                disabled: Some(disabled),
+                ..update_params(&self.plan.release_prn)
+            })
+            .await
+            .context(Request)?;
+
+        Ok(())
+    }
+
+    async fn save(&self, state: &mut RolloutState) -> Result<(), Error> {
+        state.updated_at = Utc::now();
+        let path = &self.state_path;
+        let json = serde_json::to_vec_pretty(state).context(StateFormat { path })?;
+        tokio::fs::write(path, json).await.context(StateIo { path })
+    }
+}
+
+async fn load_state(path: &Path, release_prn: &str) -> Result<RolloutState, Error> {
+    let contents = match tokio::fs::read(path).await {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(RolloutState::new(release_prn))
+        }
+        Err(e) => return Err(e).context(StateIo { path }),
+    };
+
+    let state: RolloutState = serde_json::from_slice(&contents).context(StateFormat { path })?;
+    if state.release_prn != release_prn {
+        return StateMismatch {
+            path,
+            expected: release_prn,
+            found: state.release_prn,
+        }
+        .fail();
+    }
+
+    Ok(state)
+}
+
+fn update_params(release_prn: &str) -> UpdateReleaseParams {
+    UpdateReleaseParams {
+        prn: release_prn.to_string(),
+        bundle_prn: None,
+        description: None,
+        disabled: None,
+        name: None,
+        next_release_prn: None,
+        phase_mode: None,
+        phase_tags: None,
+        phase_value: None,
+        required: None,
+        schedule_date: None,
+        version: None,
+        version_requirement: None,
+    }
+}
+
+mod duration_secs {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(value: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(value.as_secs())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        u64::deserialize(deserializer).map(Duration::from_secs)
+    }
+}
@@ -1,3 +1,8 @@
+pub mod session;
+pub mod wireguard;
+
+use std::time::Duration;
+
 use reqwest::Method;
 use serde::{Deserialize, Serialize};
 
@@ -6,6 +11,15 @@ use crate::{json_body, list_params::ListParams, Api};
 use super::Error;
 use snafu::ResultExt;
 
+string_enum! {
+    pub enum TunnelState {
+        Requested => "requested",
+        Open => "open",
+        Closed => "closed",
+        Expired => "expired",
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Tunnel {
     pub cidr_block_allowlist: Option<Vec<String>>,
@@ -23,7 +37,7 @@ pub struct Tunnel {
     pub server_public_key: Option<String>,
     pub server_tunnel_ip_address: Option<String>,
     pub server_tunnel_port: Option<u16>,
-    pub state: String,
+    pub state: TunnelState,
     pub updated_at: String,
 }
 
@@ -33,6 +47,9 @@ pub struct CreateTunnelParams {
     #[serde(default)]
     pub cidr_block_allowlist: Option<Vec<String>>,
     pub device_prn: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub device_public_key: Option<String>,
     pub device_tunnel_port: u16,
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default)]
@@ -71,7 +88,7 @@ pub struct UpdateTunnelParams {
     pub prn: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default)]
-    pub state: Option<String>,
+    pub state: Option<TunnelState>,
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default)]
     pub ttl: Option<u16>,
@@ -82,6 +99,17 @@ pub struct UpdateTunnelResponse {
     pub tunnel: Tunnel,
 }
 
+#[derive(Debug)]
+pub struct WaitForTunnelStateParams {
+    pub prn: String,
+    pub target_states: Vec<TunnelState>,
+    pub timeout: Duration,
+    pub poll_interval: Duration,
+}
+
+/// States a tunnel never leaves.
+const TERMINAL_STATES: &[TunnelState] = &[TunnelState::Closed, TunnelState::Expired];
+
 pub struct TunnelsApi<'a>(pub &'a Api);
 
 impl<'a> TunnelsApi<'a> {
@@ -132,4 +160,32 @@ impl<'a> TunnelsApi<'a> {
             )
             .await
     }
+
+    /// Polls the tunnel until it reaches one of `target_states`, backing off
+    /// from `poll_interval`. Reaching a terminal state that is not a target
+    /// fails immediately.
+    pub async fn wait_for_state(
+        &'a self,
+        params: WaitForTunnelStateParams,
+    ) -> Result<Tunnel, Error> {
+        super::wait::wait_for_state(
+            &params.prn,
+            &params.target_states,
+            TERMINAL_STATES,
+            params.timeout,
+            params.poll_interval,
+            || async {
+                let response = self
+                    .get(GetTunnelParams {
+                        prn: params.prn.clone(),
+                    })
+                    .await?;
+                Ok(response.map(|r| {
+                    let state = r.tunnel.state.clone();
+                    (r.tunnel, state)
+                }))
+            },
+        )
+        .await
+    }
 }
@@ -0,0 +1,302 @@
+//! Long lived tunnel sessions.
+//!
+//! A [`TunnelSession`] creates a tunnel, waits for it to open and keeps
+//! extending its TTL in the background so it does not expire mid session.
+//! The tunnel is closed by [`TunnelSession::close`] or, failing that, when
+//! the session is dropped. [`reap`] closes tunnels that were left behind.
+
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use snafu::{OptionExt, ResultExt, Snafu};
+use tokio::task::JoinHandle;
+
+use crate::api::pagination::list_all;
+use crate::list_params::ListParams;
+use crate::Api;
+
+use super::{
+    CreateTunnelParams, ListTunnelsParams, Tunnel, TunnelState, UpdateTunnelParams,
+    WaitForTunnelStateParams, TERMINAL_STATES,
+};
+
+#[derive(Debug, Snafu)]
+#[snafu(context(suffix(false)))]
+pub enum Error {
+    #[snafu(display("Tunnel API request failed: {}", source))]
+    Request { source: crate::api::Error },
+
+    #[snafu(display("Empty response from {}", operation))]
+    MissingResponse { operation: String },
+
+    #[snafu(display("Invalid tunnel timestamp '{}': {}", value, source))]
+    InvalidTimestamp {
+        value: String,
+        source: chrono::ParseError,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct SessionOptions {
+    /// TTL in seconds requested on creation and on every renewal.
+    pub ttl: u16,
+    /// How long before expiry the TTL is extended.
+    pub renew_before: Duration,
+    /// Lower bound between two renewals, also used to retry failed ones.
+    pub min_renew_interval: Duration,
+    pub open_timeout: Duration,
+    pub poll_interval: Duration,
+}
+
+impl Default for SessionOptions {
+    fn default() -> Self {
+        Self {
+            ttl: 3600,
+            renew_before: Duration::from_secs(300),
+            min_renew_interval: Duration::from_secs(30),
+            open_timeout: Duration::from_secs(60),
+            poll_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+pub struct TunnelSession {
+    api: Api,
+    tunnel: Tunnel,
+    keepalive: Option<JoinHandle<()>>,
+}
+
+impl TunnelSession {
+    /// Creates the tunnel, waits until it is open and starts the keepalive.
+    /// `params.ttl` is replaced by the session TTL.
+    pub async fn open(
+        api: &Api,
+        mut params: CreateTunnelParams,
+        options: SessionOptions,
+    ) -> Result<Self, Error> {
+        params.ttl = Some(options.ttl);
+        let created = api
+            .tunnels()
+            .create(params)
+            .await
+            .context(Request)?
+            .context(MissingResponse {
+                operation: "tunnel create",
+            })?
+            .tunnel;
+
+        let mut session = Self {
+            api: api.clone(),
+            tunnel: created,
+            keepalive: None,
+        };
+
+        if session.tunnel.state != TunnelState::Open {
+            let waited = api
+                .tunnels()
+                .wait_for_state(WaitForTunnelStateParams {
+                    prn: session.tunnel.prn.clone(),
+                    target_states: vec![TunnelState::Open],
+                    timeout: options.open_timeout,
+                    poll_interval: options.poll_interval,
+                })
+                .await;
+            match waited {
+                Ok(tunnel) => session.tunnel = tunnel,
+                // Dropping the session closes the tunnel that never opened.
+                Err(source) => return Err(Error::Request { source }),
+            }
+        }
+
+        let expires_at = parse_timestamp(&session.tunnel.expires_at)?;
+        session.keepalive = Some(tokio::spawn(keepalive(
+            api.clone(),
+            session.tunnel.prn.clone(),
+            expires_at,
+            options,
+        )));
+
+        Ok(session)
+    }
+
+    /// The tunnel as it was when the session opened.
+    pub fn tunnel(&self) -> &Tunnel {
+        &self.tunnel
+    }
+
+    /// Stops the keepalive and closes the tunnel.
+    pub async fn close(mut self) -> Result<Tunnel, Error> {
+        if let Some(keepalive) = self.keepalive.take() {
+            keepalive.abort();
+        }
+        let closed = close_tunnel(&self.api, &self.tunnel.prn).await?;
+        // Closed here, so dropping must not close it again. Had closing
+        // failed, dropping would try once more.
+        self.tunnel.state = TunnelState::Closed;
+        Ok(closed)
+    }
+}
+
+impl Drop for TunnelSession {
+    fn drop(&mut self) {
+        if let Some(keepalive) = self.keepalive.take() {
+            keepalive.abort();
+        }
+        if self.tunnel.state == TunnelState::Closed {
+            return;
+        }
+
+        let prn = self.tunnel.prn.clone();
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                let api = self.api.clone();
+                handle.spawn(async move {
+                    if let Err(e) = close_tunnel(&api, &prn).await {
+                        warn!("Failed to close tunnel {prn}: {e}");
+                    }
+                });
+            }
+            Err(_) => warn!("No runtime to close tunnel {prn} on drop"),
+        }
+    }
+}
+
+async fn keepalive(api: Api, prn: String, mut expires_at: DateTime<Utc>, options: SessionOptions) {
+    loop {
+        let until_renewal = (expires_at - Utc::now())
+            .to_std()
+            .unwrap_or_default()
+            .saturating_sub(options.renew_before)
+            .max(options.min_renew_interval);
+        tokio::time::sleep(until_renewal).await;
+
+        let renewed = api
+            .tunnels()
+            .update(UpdateTunnelParams {
+                prn: prn.clone(),
+                state: None,
+                ttl: Some(options.ttl),
+            })
+            .await;
+
+        match renewed {
+            // Closed or expired elsewhere, so there is nothing left to renew.
+            Ok(Some(response)) if TERMINAL_STATES.contains(&response.tunnel.state) => {
+                warn!(
+                    "Tunnel {prn} is {}, stopping renewals",
+                    response.tunnel.state
+                );
+                return;
+            }
+            Ok(Some(response)) => match parse_timestamp(&response.tunnel.expires_at) {
+                Ok(renewed_until) => expires_at = renewed_until,
+                Err(e) => warn!("Tunnel {prn} renewal returned {e}"),
+            },
+            Ok(None) => warn!("Tunnel {prn} renewal returned no tunnel"),
+            Err(e) if not_found(&e) => {
+                warn!("Tunnel {prn} no longer exists, stopping renewals");
+                return;
+            }
+            Err(e) => warn!("Failed to renew tunnel {prn}: {e}"),
+        }
+    }
+}
+
+fn not_found(error: &crate::api::Error) -> bool {
+    matches!(
+        error,
+        crate::api::Error::StructuredError { status: 404, .. }
+            | crate::api::Error::HttpError { status: 404, .. }
+    )
+}
+
+async fn close_tunnel(api: &Api, prn: &str) -> Result<Tunnel, Error> {
+    Ok(api
+        .tunnels()
+        .update(UpdateTunnelParams {
+            prn: prn.to_string(),
+            state: Some(TunnelState::Closed),
+            ttl: None,
+        })
+        .await
+        .context(Request)?
+        .context(MissingResponse {
+            operation: "tunnel update",
+        })?
+        .tunnel)
+}
+
+fn parse_timestamp(value: &str) -> Result<DateTime<Utc>, Error> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|dt| dt.with_timezone(&Utc))
+        .context(InvalidTimestamp { value })
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ReapFailure {
+    pub prn: String,
+    pub error: String,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct ReapSummary {
+    /// PRNs of the tunnels that were closed.
+    pub closed: Vec<String>,
+    pub failures: Vec<ReapFailure>,
+}
+
+/// Closes every requested or open tunnel that has expired or was not updated
+/// for more than `max_age`. A [`TunnelSession`] renewing its TTL updates its
+/// tunnel, so live sessions are left alone however long they have run.
+///
+/// A tunnel that fails to close is recorded in the summary and the rest are
+/// still closed.
+pub async fn reap(api: &Api, max_age: Duration) -> Result<ReapSummary, Error> {
+    let now = Utc::now();
+    let max_age = chrono::Duration::from_std(max_age).unwrap_or(chrono::Duration::MAX);
+
+    let tunnels = list_all(|page| async move {
+        api.tunnels()
+            .list(ListTunnelsParams {
+                list: ListParams {
+                    page,
+                    ..Default::default()
+                },
+            })
+            .await
+    })
+    .await
+    .context(Request)?
+    .context(MissingResponse {
+        operation: "tunnel list",
+    })?;
+
+    let mut summary = ReapSummary::default();
+    for tunnel in tunnels {
+        if !matches!(tunnel.state, TunnelState::Requested | TunnelState::Open) {
+            continue;
+        }
+        let result = match is_stale(&tunnel, now, max_age) {
+            Ok(false) => continue,
+            Ok(true) => close_tunnel(api, &tunnel.prn).await.map(|_| ()),
+            Err(error) => Err(error),
+        };
+        match result {
+            Ok(()) => summary.closed.push(tunnel.prn),
+            Err(error) => summary.failures.push(ReapFailure {
+                prn: tunnel.prn,
+                error: error.to_string(),
+            }),
+        }
+    }
+
+    Ok(summary)
+}
+
+fn is_stale(tunnel: &Tunnel, now: DateTime<Utc>, max_age: chrono::Duration) -> Result<bool, Error> {
+    let expired = parse_timestamp(&tunnel.expires_at)? <= now;
+    let idle = now - parse_timestamp(&tunnel.updated_at)? > max_age;
+    Ok(expired || idle)
+}
@@ -0,0 +1,391 @@
+//! WireGuard configuration for tunnels.
+//!
+//! A tunnel is a WireGuard link between the device and the Peridio tunnel
+//! server. [`create`] generates a device keypair, sends its public key with
+//! the tunnel request and returns a [`WireGuardTunnel`] that renders the
+//! device's `wg-quick` config, peered with the tunnel server, from the
+//! addresses and keys the server assigned. Operators don't join the
+//! WireGuard link; they connect to the tunnel server's proxy endpoint, see
+//! [`WireGuardTunnel::operator_endpoint`]. [`WireGuardConfig`] also parses
+//! existing `wg-quick` files back so sessions can be managed from the files
+//! on disk.
+//!
+//! Only the keys this module models are kept when parsing; hooks such as
+//! `PostUp` are dropped.
+
+use std::fmt;
+use std::str::FromStr;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use snafu::{OptionExt, ResultExt, Snafu};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::Api;
+
+use super::{CreateTunnelParams, Tunnel};
+
+/// Keepalive interval for the device side, which usually sits behind NAT.
+const PERSISTENT_KEEPALIVE: u16 = 25;
+
+#[derive(Debug, Snafu)]
+#[snafu(context(suffix(false)))]
+pub enum Error {
+    #[snafu(display("Tunnel API request failed: {}", source))]
+    Request { source: crate::api::Error },
+
+    #[snafu(display("Empty response from {}", operation))]
+    MissingResponse { operation: String },
+
+    #[snafu(display("Tunnel {} has no {}", prn, field))]
+    MissingTunnelField { prn: String, field: String },
+
+    #[snafu(display(
+        "Tunnel {} has device public key {}, expected {}",
+        prn,
+        actual,
+        expected
+    ))]
+    KeyMismatch {
+        prn: String,
+        expected: String,
+        actual: String,
+    },
+
+    #[snafu(display("Invalid WireGuard key: {}", reason))]
+    InvalidKey { reason: String },
+
+    #[snafu(display("Invalid WireGuard config line {}: {}", line, reason))]
+    Syntax { line: usize, reason: String },
+
+    #[snafu(display("WireGuard config is missing {}", field))]
+    MissingField { field: String },
+}
+
+/// A base64 encoded x25519 keypair as used by WireGuard.
+#[derive(Clone, Eq, PartialEq)]
+pub struct Keypair {
+    pub private_key: String,
+    pub public_key: String,
+}
+
+impl fmt::Debug for Keypair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Keypair")
+            .field("public_key", &self.public_key)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Keypair {
+    pub fn generate() -> Self {
+        Self::from_secret(StaticSecret::random())
+    }
+
+    /// Derives the public key for an existing base64 private key.
+    pub fn from_private_key(private_key: &str) -> Result<Self, Error> {
+        let bytes: [u8; 32] = decode_key(private_key)?;
+        Ok(Self::from_secret(StaticSecret::from(bytes)))
+    }
+
+    fn from_secret(secret: StaticSecret) -> Self {
+        Self {
+            private_key: STANDARD.encode(secret.to_bytes()),
+            public_key: STANDARD.encode(PublicKey::from(&secret).as_bytes()),
+        }
+    }
+}
+
+fn decode_key(key: &str) -> Result<[u8; 32], Error> {
+    let bytes = STANDARD.decode(key.trim()).map_err(|e| Error::InvalidKey {
+        reason: e.to_string(),
+    })?;
+    bytes
+        .try_into()
+        .map_err(|bytes: Vec<u8>| Error::InvalidKey {
+            reason: format!("expected 32 bytes, got {}", bytes.len()),
+        })
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Interface {
+    pub private_key: String,
+    pub addresses: Vec<String>,
+    pub listen_port: Option<u16>,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Peer {
+    pub public_key: String,
+    pub endpoint: Option<String>,
+    pub allowed_ips: Vec<String>,
+    pub persistent_keepalive: Option<u16>,
+}
+
+/// A `wg-quick` configuration. `Display` renders it and `FromStr` parses it.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct WireGuardConfig {
+    pub interface: Interface,
+    pub peers: Vec<Peer>,
+}
+
+impl fmt::Display for WireGuardConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "[Interface]")?;
+        writeln!(f, "PrivateKey = {}", self.interface.private_key)?;
+        if !self.interface.addresses.is_empty() {
+            writeln!(f, "Address = {}", self.interface.addresses.join(", "))?;
+        }
+        if let Some(listen_port) = self.interface.listen_port {
+            writeln!(f, "ListenPort = {listen_port}")?;
+        }
+
+        for peer in &self.peers {
+            writeln!(f)?;
+            writeln!(f, "[Peer]")?;
+            writeln!(f, "PublicKey = {}", peer.public_key)?;
+            if let Some(endpoint) = &peer.endpoint {
+                writeln!(f, "Endpoint = {endpoint}")?;
+            }
+            if !peer.allowed_ips.is_empty() {
+                writeln!(f, "AllowedIPs = {}", peer.allowed_ips.join(", "))?;
+            }
+            if let Some(keepalive) = peer.persistent_keepalive {
+                writeln!(f, "PersistentKeepalive = {keepalive}")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+enum Section {
+    None,
+    Interface,
+    Peer,
+}
+
+impl FromStr for WireGuardConfig {
+    type Err = Error;
+
+    fn from_str(contents: &str) -> Result<Self, Self::Err> {
+        let mut private_key = None;
+        let mut interface = Interface::default();
+        let mut peers: Vec<Peer> = Vec::new();
+        let mut section = Section::None;
+
+        for (index, line) in contents.lines().enumerate() {
+            let line_number = index + 1;
+            let line = match line.split_once('#') {
+                Some((before, _)) => before.trim(),
+                None => line.trim(),
+            };
+            if line.is_empty() {
+                continue;
+            }
+
+            if line.starts_with('[') {
+                section = match line.to_ascii_lowercase().as_str() {
+                    "[interface]" => Section::Interface,
+                    "[peer]" => {
+                        peers.push(Peer::default());
+                        Section::Peer
+                    }
+                    _ => {
+                        return Syntax {
+                            line: line_number,
+                            reason: format!("unknown section {line}"),
+                        }
+                        .fail()
+                    }
+                };
+                continue;
+            }
+
+            let (key, value) = line.split_once('=').context(Syntax {
+                line: line_number,
+                reason: "expected key = value",
+            })?;
+            let key = key.trim().to_ascii_lowercase();
+            let value = value.trim().to_string();
+
+            let port = |value: &str| {
+                value.parse::<u16>().map_err(|e| Error::Syntax {
+                    line: line_number,
+                    reason: format!("invalid {key}: {e}"),
+                })
+            };
+
+            match section {
+                Section::None => {
+                    return Syntax {
+                        line: line_number,
+                        reason: "assignment outside of a section",
+                    }
+                    .fail()
+                }
+                Section::Interface => match key.as_str() {
+                    "privatekey" => {
+                        decode_key(&value)?;
+                        private_key = Some(value);
+                    }
+                    "address" => interface.addresses.extend(split_list(&value)),
+                    "listenport" => interface.listen_port = Some(port(&value)?),
+                    _ => {}
+                },
+                Section::Peer => {
+                    // A peer section was pushed when its header was read.
+                    let peer = peers.last_mut().expect("peer section");
+                    match key.as_str() {
+                        "publickey" => {
+                            decode_key(&value)?;
+                            peer.public_key = value;
+                        }
+                        "endpoint" => peer.endpoint = Some(value),
+                        "allowedips" => peer.allowed_ips.extend(split_list(&value)),
+                        "persistentkeepalive" => peer.persistent_keepalive = Some(port(&value)?),
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        interface.private_key = private_key.context(MissingField {
+            field: "Interface.PrivateKey",
+        })?;
+        if peers.iter().any(|peer| peer.public_key.is_empty()) {
+            return MissingField {
+                field: "Peer.PublicKey",
+            }
+            .fail();
+        }
+
+        Ok(Self { interface, peers })
+    }
+}
+
+fn split_list(value: &str) -> impl Iterator<Item = String> + '_ {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(String::from)
+}
+
+/// A tunnel together with the locally generated keypair for its device end.
+#[derive(Debug)]
+pub struct WireGuardTunnel {
+    pub tunnel: Tunnel,
+    /// The device key, whose public half was sent with the tunnel request.
+    pub keypair: Keypair,
+}
+
+impl WireGuardTunnel {
+    /// The device end: listens on the device proxy address and dials the
+    /// tunnel server, which is its only peer.
+    pub fn device_config(&self) -> Result<WireGuardConfig, Error> {
+        let tunnel = &self.tunnel;
+        let server = self.server_peer(vec![host_address(&required(
+            tunnel,
+            "server_proxy_ip_address",
+            &tunnel.server_proxy_ip_address,
+        )?)])?;
+        let device_proxy_ip = required(
+            tunnel,
+            "device_proxy_ip_address",
+            &tunnel.device_proxy_ip_address,
+        )?;
+
+        Ok(WireGuardConfig {
+            interface: Interface {
+                private_key: self.keypair.private_key.clone(),
+                addresses: vec![host_address(&device_proxy_ip)],
+                listen_port: tunnel.device_proxy_port,
+            },
+            peers: vec![server],
+        })
+    }
+
+    /// The `host:port` an operator connects to in order to reach
+    /// `device_tunnel_port` on the device. The tunnel server forwards its
+    /// proxy port through the tunnel.
+    pub fn operator_endpoint(&self) -> Result<String, Error> {
+        let tunnel = &self.tunnel;
+        let ip = required(
+            tunnel,
+            "server_proxy_ip_address",
+            &tunnel.server_proxy_ip_address,
+        )?;
+        let port = required(tunnel, "server_proxy_port", &tunnel.server_proxy_port)?;
+        Ok(if ip.contains(':') {
+            format!("[{ip}]:{port}")
+        } else {
+            format!("{ip}:{port}")
+        })
+    }
+
+    fn server_peer(&self, allowed_ips: Vec<String>) -> Result<Peer, Error> {
+        let tunnel = &self.tunnel;
+        let server_public_key = required(tunnel, "server_public_key", &tunnel.server_public_key)?;
+        let server_ip = required(
+            tunnel,
+            "server_tunnel_ip_address",
+            &tunnel.server_tunnel_ip_address,
+        )?;
+        let server_port = required(tunnel, "server_tunnel_port", &tunnel.server_tunnel_port)?;
+
+        Ok(Peer {
+            public_key: server_public_key,
+            endpoint: Some(format!("{server_ip}:{server_port}")),
+            allowed_ips,
+            persistent_keepalive: Some(PERSISTENT_KEEPALIVE),
+        })
+    }
+}
+
+fn required<T: Clone>(tunnel: &Tunnel, field: &str, value: &Option<T>) -> Result<T, Error> {
+    value.clone().context(MissingTunnelField {
+        prn: &tunnel.prn,
+        field,
+    })
+}
+
+fn host_address(ip: &str) -> String {
+    if ip.contains('/') {
+        ip.to_string()
+    } else if ip.contains(':') {
+        format!("{ip}/128")
+    } else {
+        format!("{ip}/32")
+    }
+}
+
+/// Generates the device keypair and creates the tunnel described by
+/// `params` with its public key. Fails if the server
+/// assigned a different device key.
+pub async fn create(api: &Api, mut params: CreateTunnelParams) -> Result<WireGuardTunnel, Error> {
+    let keypair = Keypair::generate();
+    params.device_public_key = Some(keypair.public_key.clone());
+    let tunnel = api
+        .tunnels()
+        .create(params)
+        .await
+        .context(Request)?
+        .context(MissingResponse {
+            operation: "tunnel create",
+        })?
+        .tunnel;
+
+    if let Some(actual) = &tunnel.device_public_key {
+        if *actual != keypair.public_key {
+            return KeyMismatch {
+                prn: &tunnel.prn,
+                expected: &keypair.public_key,
+                actual,
+            }
+            .fail();
+        }
+    }
+
+    Ok(WireGuardTunnel { tunnel, keypair })
+}
@@ -0,0 +1,61 @@
+use std::fmt::Display;
+use std::future::Future;
+use std::time::Duration;
+
+use tokio::time::{sleep, Instant};
+
+use super::Error;
+
+/// The poll interval grows by doubling up to this multiple of the initial one.
+const MAX_BACKOFF_FACTOR: u32 = 8;
+
+/// Polls `fetch` until the returned state is one of `target_states`.
+///
+/// Fails with [`Error::UnexpectedState`] as soon as one of `terminal_states`
+/// is observed and with [`Error::WaitTimeout`] once `timeout` has elapsed.
+pub(crate) async fn wait_for_state<T, S, F, Fut>(
+    prn: &str,
+    target_states: &[S],
+    terminal_states: &[S],
+    timeout: Duration,
+    poll_interval: Duration,
+    mut fetch: F,
+) -> Result<T, Error>
+where
+    S: PartialEq + Display,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<Option<(T, S)>, Error>>,
+{
+    let deadline = Instant::now() + timeout;
+    let max_interval = poll_interval * MAX_BACKOFF_FACTOR;
+    let mut interval = poll_interval;
+
+    loop {
+        let (model, state) = fetch().await?.ok_or_else(|| Error::EmptyResponse {
+            prn: prn.to_string(),
+        })?;
+
+        if target_states.contains(&state) {
+            return Ok(model);
+        }
+
+        if terminal_states.contains(&state) {
+            return Err(Error::UnexpectedState {
+                prn: prn.to_string(),
+                state: state.to_string(),
+            });
+        }
+
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(Error::WaitTimeout {
+                prn: prn.to_string(),
+                state: state.to_string(),
+                timeout,
+            });
+        }
+
+        sleep(interval.min(deadline - now)).await;
+        interval = (interval * 2).min(max_interval);
+    }
+}
@@ -9,16 +9,34 @@ use crate::Api;
 use super::Error;
 use snafu::ResultExt;
 
+string_enum! {
+    pub enum WebhookState {
+        Enabled => "enabled",
+        Disabled => "disabled",
+    }
+}
+
+string_enum! {
+    /// An event a webhook can be subscribed to.
+    pub enum WebhookEnabledEvent {
+        DeviceCheckedForRelease => "device.checked_for_release",
+        DeviceClaimedRelease => "device.claimed_release",
+        DeviceReleaseChanged => "device.release_changed",
+        WebhookRequestFailed => "webhook.request_failed",
+        WebhookTestFire => "webhook.test_fire",
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Webhook {
     pub description: Option<String>,
-    pub enabled_events: Vec<String>,
+    pub enabled_events: Vec<WebhookEnabledEvent>,
     pub inserted_at: String,
     pub prn: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default)]
     pub secret: Option<String>,
-    pub state: Option<String>,
+    pub state: Option<WebhookState>,
     pub updated_at: String,
     pub url: Option<String>,
 }
@@ -30,7 +48,7 @@ pub struct CreateWebhookParams {
     pub description: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default)]
-    pub enabled_events: Option<Vec<String>>,
+    pub enabled_events: Option<Vec<WebhookEnabledEvent>>,
     pub url: String,
 }
 
@@ -72,10 +90,10 @@ pub struct UpdateWebhookParams {
     pub url: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default)]
-    pub state: Option<String>,
+    pub state: Option<WebhookState>,
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default)]
-    pub enabled_events: Option<Vec<String>>,
+    pub enabled_events: Option<Vec<WebhookEnabledEvent>>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -0,0 +1,399 @@
+//! Local X.509 certificate inspection.
+//!
+//! The API only reports a certificate's serial and validity as strings. This
+//! module parses PEM certificates into [`CertificateInfo`], checks a device
+//! certificate against a CA chain before it is uploaded and joins API list
+//! results with locally known certificates by serial.
+
+pub mod issuance;
+pub mod monitor;
+pub mod registration;
+
+use std::net::IpAddr;
+
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use snafu::{OptionExt, ResultExt, Snafu};
+use x509_parser::certificate::X509Certificate;
+use x509_parser::extensions::GeneralName;
+use x509_parser::pem::Pem;
+use x509_parser::public_key::PublicKey;
+
+use crate::api::ca_certificates::CaCertificate;
+use crate::api::device_certificates::{
+    CreateDeviceCertificateParams, CreateDeviceCertificateResponse, DeviceCertificate,
+};
+use crate::Api;
+
+pub use issuance::{provision, DeviceIdentity, LocalCa, ProvisionedDevice};
+pub use monitor::{ExpiryMonitor, ExpiryReport};
+pub use registration::register_ca;
+
+const OID_ED25519: &str = "1.3.101.112";
+
+#[derive(Debug, Snafu)]
+#[snafu(context(suffix(false)))]
+pub enum Error {
+    #[snafu(display("Certificate API request failed: {}", source))]
+    Request { source: crate::api::Error },
+
+    #[snafu(display("Empty response from {}", operation))]
+    MissingResponse { operation: String },
+
+    #[snafu(display("Certificate generation failed: {}", source))]
+    Generation { source: rcgen::Error },
+
+    #[snafu(display("Failed to write {}: {}", path.display(), source))]
+    Io {
+        path: std::path::PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Invalid PEM: {}", reason))]
+    InvalidPem { reason: String },
+
+    #[snafu(display("No certificate found in PEM"))]
+    NoCertificate,
+
+    #[snafu(display("Invalid certificate: {}", reason))]
+    InvalidCertificate { reason: String },
+
+    #[snafu(display("No certificate in the chain issued {}", subject))]
+    IssuerNotFound { subject: String },
+
+    #[snafu(display("Signature of {} does not verify against {}", subject, issuer))]
+    BadSignature { subject: String, issuer: String },
+
+    #[snafu(display("Issuer {} is not a CA", issuer))]
+    NotCa { issuer: String },
+
+    #[snafu(display(
+        "{} is not valid at {} (valid {} to {})",
+        subject,
+        at,
+        not_before,
+        not_after
+    ))]
+    OutsideValidity {
+        subject: String,
+        at: DateTime<Utc>,
+        not_before: DateTime<Utc>,
+        not_after: DateTime<Utc>,
+    },
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum KeyType {
+    Rsa { bits: usize },
+    Ec { curve: String },
+    Ed25519,
+    Unknown { oid: String },
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct CertificateInfo {
+    pub subject: String,
+    pub issuer: String,
+    /// Lowercase hex without leading zeros.
+    pub serial: String,
+    pub subject_alt_names: Vec<String>,
+    pub key_type: KeyType,
+    pub is_ca: bool,
+    pub not_before: DateTime<Utc>,
+    pub not_after: DateTime<Utc>,
+    pub sha1_fingerprint: String,
+    pub sha256_fingerprint: String,
+}
+
+impl CertificateInfo {
+    pub fn is_valid_at(&self, at: DateTime<Utc>) -> bool {
+        self.not_before <= at && at <= self.not_after
+    }
+
+    pub fn is_self_signed(&self) -> bool {
+        self.subject == self.issuer
+    }
+}
+
+/// Parses the first certificate of a PEM.
+pub fn parse_pem(pem: &str) -> Result<CertificateInfo, Error> {
+    parse_pem_chain(pem)?
+        .into_iter()
+        .next()
+        .context(NoCertificate)
+}
+
+/// Parses every certificate of a PEM, in order.
+pub fn parse_pem_chain(pem: &str) -> Result<Vec<CertificateInfo>, Error> {
+    let pems = read_pems(pem)?;
+    pems.iter()
+        .map(|pem| parse_x509(pem).and_then(|cert| info(pem, &cert)))
+        .collect()
+}
+
+fn read_pems(pem: &str) -> Result<Vec<Pem>, Error> {
+    let pems = Pem::iter_from_buffer(pem.as_bytes())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| Error::InvalidPem {
+            reason: e.to_string(),
+        })?;
+    let pems: Vec<Pem> = pems
+        .into_iter()
+        .filter(|pem| pem.label == "CERTIFICATE")
+        .collect();
+    if pems.is_empty() {
+        return NoCertificate.fail();
+    }
+    Ok(pems)
+}
+
+fn parse_x509(pem: &Pem) -> Result<X509Certificate<'_>, Error> {
+    pem.parse_x509().map_err(|e| Error::InvalidCertificate {
+        reason: e.to_string(),
+    })
+}
+
+fn info(pem: &Pem, cert: &X509Certificate) -> Result<CertificateInfo, Error> {
+    let invalid = |e: x509_parser::error::X509Error| Error::InvalidCertificate {
+        reason: e.to_string(),
+    };
+
+    let subject_alt_names = cert
+        .subject_alternative_name()
+        .map_err(invalid)?
+        .map(|san| {
+            san.value
+                .general_names
+                .iter()
+                .filter_map(general_name)
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let spki = cert.public_key();
+    let key_type = match spki.parsed() {
+        Ok(PublicKey::RSA(rsa)) => KeyType::Rsa {
+            bits: rsa.key_size(),
+        },
+        Ok(PublicKey::EC(_)) => KeyType::Ec {
+            curve: spki
+                .algorithm
+                .parameters
+                .as_ref()
+                .and_then(|params| params.as_oid().ok())
+                .map(|oid| curve_name(&oid.to_id_string()))
+                .unwrap_or_else(|| "unknown".to_string()),
+        },
+        _ if spki.algorithm.algorithm.to_id_string() == OID_ED25519 => KeyType::Ed25519,
+        _ => KeyType::Unknown {
+            oid: spki.algorithm.algorithm.to_id_string(),
+        },
+    };
+
+    let validity = cert.validity();
+    Ok(CertificateInfo {
+        subject: cert.subject().to_string(),
+        issuer: cert.issuer().to_string(),
+        serial: normalize_serial(&hex(cert.raw_serial())),
+        subject_alt_names,
+        key_type,
+        is_ca: cert.is_ca(),
+        not_before: timestamp(validity.not_before.timestamp())?,
+        not_after: timestamp(validity.not_after.timestamp())?,
+        sha1_fingerprint: hex(&Sha1::digest(&pem.contents)),
+        sha256_fingerprint: hex(&Sha256::digest(&pem.contents)),
+    })
+}
+
+fn general_name(name: &GeneralName) -> Option<String> {
+    match name {
+        GeneralName::DNSName(dns) => Some(format!("DNS:{dns}")),
+        GeneralName::RFC822Name(email) => Some(format!("email:{email}")),
+        GeneralName::URI(uri) => Some(format!("URI:{uri}")),
+        GeneralName::IPAddress(bytes) => {
+            let ip = match bytes.len() {
+                4 => IpAddr::from(<[u8; 4]>::try_from(*bytes).ok()?),
+                16 => IpAddr::from(<[u8; 16]>::try_from(*bytes).ok()?),
+                _ => return None,
+            };
+            Some(format!("IP:{ip}"))
+        }
+        _ => None,
+    }
+}
+
+fn curve_name(oid: &str) -> String {
+    match oid {
+        "1.2.840.10045.3.1.7" => "P-256".to_string(),
+        "1.3.132.0.34" => "P-384".to_string(),
+        "1.3.132.0.35" => "P-521".to_string(),
+        other => other.to_string(),
+    }
+}
+
+fn timestamp(seconds: i64) -> Result<DateTime<Utc>, Error> {
+    DateTime::from_timestamp(seconds, 0).context(InvalidCertificate {
+        reason: format!("timestamp {seconds} is out of range"),
+    })
+}
+
+/// Sets the validity of certificates generated with rcgen, which expects
+/// `time` dates.
+fn set_validity(params: &mut rcgen::CertificateParams, from: NaiveDate, to: NaiveDate) {
+    let date =
+        |date: NaiveDate| rcgen::date_time_ymd(date.year(), date.month() as u8, date.day() as u8);
+    params.not_before = date(from);
+    params.not_after = date(to);
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Normalizes a hex serial so `01:AB`, `01ab` and `ab` compare equal.
+pub fn normalize_serial(serial: &str) -> String {
+    let digits: String = serial
+        .chars()
+        .filter(|c| c.is_ascii_hexdigit())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    match digits.trim_start_matches('0') {
+        "" => "0".to_string(),
+        trimmed => trimmed.to_string(),
+    }
+}
+
+/// Checks that the first certificate of `certificate_pem` chains up to the
+/// certificates in `ca_chain_pem` and that every link is valid at `at`.
+///
+/// Every certificate in the chain is trusted, so the walk ends successfully
+/// at a self-signed certificate or at one whose issuer is not in the chain.
+pub fn verify_chain(
+    certificate_pem: &str,
+    ca_chain_pem: &str,
+    at: DateTime<Utc>,
+) -> Result<(), Error> {
+    let leaf_pems = read_pems(certificate_pem)?;
+    let leaf = parse_x509(&leaf_pems[0])?;
+    let chain_pems = read_pems(ca_chain_pem)?;
+    let chain = chain_pems
+        .iter()
+        .map(parse_x509)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    check_validity(&leaf, at)?;
+
+    let mut current = &leaf;
+    // Each certificate of the chain is used at most once, which bounds the
+    // walk even for cyclic chains.
+    for depth in 0..=chain.len() {
+        let Some(issuer) = chain
+            .iter()
+            .find(|candidate| candidate.subject() == current.issuer())
+        else {
+            if depth == 0 {
+                return IssuerNotFound {
+                    subject: leaf.subject().to_string(),
+                }
+                .fail();
+            }
+            return Ok(());
+        };
+
+        if !issuer.is_ca() {
+            return NotCa {
+                issuer: issuer.subject().to_string(),
+            }
+            .fail();
+        }
+        check_validity(issuer, at)?;
+        current
+            .verify_signature(Some(issuer.public_key()))
+            .map_err(|_| Error::BadSignature {
+                subject: current.subject().to_string(),
+                issuer: issuer.subject().to_string(),
+            })?;
+
+        if issuer.subject() == issuer.issuer() {
+            return Ok(());
+        }
+        current = issuer;
+    }
+
+    Ok(())
+}
+
+fn check_validity(cert: &X509Certificate, at: DateTime<Utc>) -> Result<(), Error> {
+    let validity = cert.validity();
+    let not_before = timestamp(validity.not_before.timestamp())?;
+    let not_after = timestamp(validity.not_after.timestamp())?;
+    if at < not_before || at > not_after {
+        return OutsideValidity {
+            subject: cert.subject().to_string(),
+            at,
+            not_before,
+            not_after,
+        }
+        .fail();
+    }
+    Ok(())
+}
+
+/// Verifies `params.certificate` against `ca_chain_pem` before creating the
+/// device certificate.
+pub async fn create_verified_device_certificate(
+    api: &Api,
+    params: CreateDeviceCertificateParams,
+    ca_chain_pem: &str,
+) -> Result<CreateDeviceCertificateResponse, Error> {
+    verify_chain(&params.certificate, ca_chain_pem, Utc::now())?;
+    api.device_certificates()
+        .create(params)
+        .await
+        .context(Request)?
+        .context(MissingResponse {
+            operation: "device certificate create",
+        })
+}
+
+/// A certificate record as returned by the API.
+pub trait CertificateRecord {
+    fn serial(&self) -> &str;
+}
+
+impl CertificateRecord for CaCertificate {
+    fn serial(&self) -> &str {
+        &self.serial
+    }
+}
+
+impl CertificateRecord for DeviceCertificate {
+    fn serial(&self) -> &str {
+        &self.serial
+    }
+}
+
+#[derive(Debug)]
+pub struct Enriched<T> {
+    pub certificate: T,
+    /// The locally known certificate with the same serial, if any.
+    pub info: Option<CertificateInfo>,
+}
+
+/// Pairs each API record with the parsed certificate sharing its serial.
+pub fn enrich<T: CertificateRecord>(
+    records: Vec<T>,
+    known: &[CertificateInfo],
+) -> Vec<Enriched<T>> {
+    records
+        .into_iter()
+        .map(|certificate| {
+            let serial = normalize_serial(certificate.serial());
+            let info = known.iter().find(|info| info.serial == serial).cloned();
+            Enriched { certificate, info }
+        })
+        .collect()
+}
@@ -0,0 +1,288 @@
+//! Device certificate issuance from a local CA.
+//!
+//! [`LocalCa`] signs per-device certificates whose common name is the device
+//! identifier. [`provision`] ties issuance to the API: it creates the device
+//! when it does not exist yet, registers the new certificate and returns the
+//! key, certificate and chain as a [`DeviceIdentity`] ready for flashing.
+
+use std::fs;
+use std::path::Path;
+
+use chrono::{Duration, Utc};
+use rcgen::{
+    CertificateParams, DistinguishedName, DnType, ExtendedKeyUsagePurpose, IsCa, KeyPair,
+    KeyUsagePurpose, SerialNumber,
+};
+use sha2::{Digest, Sha256};
+use snafu::{OptionExt, ResultExt};
+
+use crate::api::device_certificates::{CreateDeviceCertificateParams, DeviceCertificate};
+use crate::api::devices::{CreateDeviceParams, Device, DeviceQuery, ListDeviceParams};
+use crate::api::pagination::list_until;
+use crate::list_params::ListParams;
+use crate::Api;
+
+use super::{
+    parse_pem, set_validity, CertificateInfo, Error, Generation, Io, MissingResponse, NotCa,
+    Request,
+};
+
+const DEFAULT_VALIDITY_DAYS: i64 = 3650;
+
+pub struct LocalCa {
+    cert_pem: String,
+    info: CertificateInfo,
+    key: KeyPair,
+    issuer: rcgen::Certificate,
+    chain_pem: String,
+    validity: Duration,
+}
+
+impl LocalCa {
+    /// Loads the CA from its certificate and PKCS#8 key.
+    pub fn from_pem(ca_cert_pem: &str, ca_key_pem: &str) -> Result<Self, Error> {
+        let info = parse_pem(ca_cert_pem)?;
+        if !info.is_ca {
+            return NotCa {
+                issuer: info.subject,
+            }
+            .fail();
+        }
+
+        let key = KeyPair::from_pem(ca_key_pem).context(Generation)?;
+        let issuer = CertificateParams::from_ca_cert_pem(ca_cert_pem)
+            .context(Generation)?
+            .self_signed(&key)
+            .context(Generation)?;
+
+        Ok(Self {
+            cert_pem: ca_cert_pem.to_string(),
+            info,
+            key,
+            issuer,
+            chain_pem: ca_cert_pem.to_string(),
+            validity: Duration::days(DEFAULT_VALIDITY_DAYS),
+        })
+    }
+
+    /// Appends the certificates above this CA, up to the root, to the chain
+    /// handed out with each device certificate.
+    pub fn with_chain(mut self, chain_pem: &str) -> Self {
+        if !self.chain_pem.ends_with('\n') {
+            self.chain_pem.push('\n');
+        }
+        self.chain_pem.push_str(chain_pem);
+        self
+    }
+
+    /// Validity of issued certificates. They never outlive the CA.
+    pub fn validity(mut self, validity: Duration) -> Self {
+        self.validity = validity;
+        self
+    }
+
+    pub fn certificate_pem(&self) -> &str {
+        &self.cert_pem
+    }
+
+    /// Generates a keypair for `identifier` and signs a client certificate
+    /// for it.
+    pub fn issue(&self, identifier: &str) -> Result<DeviceIdentity, Error> {
+        let key = KeyPair::generate().context(Generation)?;
+
+        let mut params = CertificateParams::new(Vec::new()).context(Generation)?;
+        let mut distinguished_name = DistinguishedName::new();
+        distinguished_name.push(DnType::CommonName, identifier);
+        params.distinguished_name = distinguished_name;
+        params.is_ca = IsCa::ExplicitNoCa;
+        params.key_usages = vec![KeyUsagePurpose::DigitalSignature];
+        params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+        params.use_authority_key_identifier_extension = true;
+        params.serial_number = Some(serial_number(identifier, &key));
+
+        let today = Utc::now().date_naive();
+        let not_after = (today + self.validity).min(self.info.not_after.date_naive());
+        set_validity(&mut params, today, not_after);
+
+        let certificate = params
+            .signed_by(&key, &self.issuer, &self.key)
+            .context(Generation)?;
+
+        Ok(DeviceIdentity {
+            identifier: identifier.to_string(),
+            private_key_pem: key.serialize_pem(),
+            certificate_pem: certificate.pem(),
+            chain_pem: self.chain_pem.clone(),
+        })
+    }
+}
+
+/// A positive 127 bit serial derived from the identifier, the fresh public
+/// key and the current time, unique per issued certificate.
+fn serial_number(identifier: &str, key: &KeyPair) -> SerialNumber {
+    let mut hasher = Sha256::new();
+    hasher.update(identifier.as_bytes());
+    hasher.update(key.public_key_der());
+    hasher.update(
+        Utc::now()
+            .timestamp_nanos_opt()
+            .unwrap_or_default()
+            .to_be_bytes(),
+    );
+    let mut serial = hasher.finalize()[..16].to_vec();
+    serial[0] &= 0x7f;
+    SerialNumber::from_slice(&serial)
+}
+
+/// The material flashed onto a device.
+#[derive(Clone)]
+pub struct DeviceIdentity {
+    pub identifier: String,
+    /// PKCS#8 PEM.
+    pub private_key_pem: String,
+    pub certificate_pem: String,
+    /// The issuing CA followed by the rest of its chain.
+    pub chain_pem: String,
+}
+
+impl std::fmt::Debug for DeviceIdentity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DeviceIdentity")
+            .field("identifier", &self.identifier)
+            .field("certificate_pem", &self.certificate_pem)
+            .finish_non_exhaustive()
+    }
+}
+
+impl DeviceIdentity {
+    /// Writes `device-key.pem`, `device-cert.pem` and `chain.pem` into `dir`,
+    /// creating it if needed. The key is only readable by its owner on Unix.
+    pub fn write_to(&self, dir: impl AsRef<Path>) -> Result<(), Error> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir).context(Io { path: dir })?;
+
+        let key_path = dir.join("device-key.pem");
+        write_private(&key_path, &self.private_key_pem).context(Io { path: &key_path })?;
+        for (name, contents) in [
+            ("device-cert.pem", &self.certificate_pem),
+            ("chain.pem", &self.chain_pem),
+        ] {
+            let path = dir.join(name);
+            fs::write(&path, contents).context(Io { path: &path })?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn write_private(path: &Path, contents: &str) -> std::io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?
+        .write_all(contents.as_bytes())
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &Path, contents: &str) -> std::io::Result<()> {
+    fs::write(path, contents)
+}
+
+#[derive(Debug)]
+pub struct ProvisionedDevice {
+    /// `None` when a dry run only planned creating the device.
+    pub device: Option<Device>,
+    /// Whether the device was created by this call.
+    pub created: bool,
+    /// `None` when a dry run only planned registering the certificate, or
+    /// skipped it because creating the device was only planned.
+    pub device_certificate: Option<DeviceCertificate>,
+    pub identity: DeviceIdentity,
+}
+
+/// Issues a certificate for `params.identifier`, creating the device first
+/// when no device with that identifier exists, and registers it.
+pub async fn provision(
+    api: &Api,
+    ca: &LocalCa,
+    params: CreateDeviceParams,
+) -> Result<ProvisionedDevice, Error> {
+    let identity = ca.issue(&params.identifier)?;
+
+    let (device, created) = match find_device(api, &params.identifier).await? {
+        Some(device) => (device, false),
+        None => match api.devices().create(params).await.context(Request)? {
+            Some(created) => (created.device, true),
+            None if api.is_dry_run() => {
+                return Ok(ProvisionedDevice {
+                    device: None,
+                    created: true,
+                    device_certificate: None,
+                    identity,
+                })
+            }
+            None => {
+                return MissingResponse {
+                    operation: "device create",
+                }
+                .fail()
+            }
+        },
+    };
+
+    let device_certificate = match api
+        .device_certificates()
+        .create(CreateDeviceCertificateParams {
+            certificate: identity.certificate_pem.clone(),
+            device_prn: device.prn.clone(),
+        })
+        .await
+        .context(Request)?
+    {
+        Some(created) => Some(created.device_certificate),
+        None if api.is_dry_run() => None,
+        None => {
+            return MissingResponse {
+                operation: "device certificate create",
+            }
+            .fail()
+        }
+    };
+
+    Ok(ProvisionedDevice {
+        device: Some(device),
+        created,
+        device_certificate,
+        identity,
+    })
+}
+
+async fn find_device(api: &Api, identifier: &str) -> Result<Option<Device>, Error> {
+    let matches = |device: &Device| device.identifier == identifier;
+    let devices = list_until(
+        |page| async move {
+            api.devices()
+                .list(ListDeviceParams {
+                    list: ListParams {
+                        page,
+                        ..DeviceQuery::new().identifier(identifier).into()
+                    },
+                })
+                .await
+        },
+        |devices| devices.iter().any(matches),
+    )
+    .await
+    .context(Request)?
+    .context(MissingResponse {
+        operation: "device list",
+    })?;
+
+    Ok(devices.into_iter().find(matches))
+}
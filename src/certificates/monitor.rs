@@ -0,0 +1,368 @@
+//! Certificate expiry monitoring.
+//!
+//! [`ExpiryMonitor`] pages through all CA and device certificates, classifies
+//! each by time left against a set of warning windows. The resulting
+//! [`ExpiryReport`] renders as JSON, CSV or a text table, and an optional
+//! callback is invoked for every flagged certificate.
+//!
+//! The API does not say which device a device certificate belongs to, so
+//! the monitor is told which device identifier each serial was issued for,
+//! either as a map or from the certificates themselves, whose common name is
+//! the device identifier when issued by [`LocalCa`](super::LocalCa). Mapped
+//! certificates are matched to devices, and through them to cohorts, with a
+//! single device list; the rest are reported by PRN and serial only.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use snafu::{OptionExt, ResultExt};
+
+use crate::api::ca_certificates::{CaCertificate, ListCaCertificateParams};
+use crate::api::device_certificates::{DeviceCertificate, ListDeviceCertificateParams};
+use crate::api::devices::{list_all_devices, Device};
+use crate::api::pagination::list_all;
+use crate::list_params::ListParams;
+use crate::report;
+use crate::Api;
+
+use super::{normalize_serial, parse_pem, Error, MissingResponse, Request};
+
+const CSV_HEADERS: &[&str] = &[
+    "kind",
+    "prn",
+    "serial",
+    "not_after",
+    "days_left",
+    "status",
+    "device_prn",
+    "device_identifier",
+    "cohort_prn",
+    "description",
+];
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CertificateKind {
+    Ca,
+    Device,
+}
+
+impl fmt::Display for CertificateKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CertificateKind::Ca => "ca",
+            CertificateKind::Device => "device",
+        })
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case", tag = "status")]
+pub enum ExpiryStatus {
+    Expired,
+    /// Expires within the smallest window that contains its remaining time.
+    Expiring {
+        window_days: i64,
+    },
+    Valid,
+    /// `not_after` could not be parsed.
+    Unknown,
+}
+
+impl fmt::Display for ExpiryStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExpiryStatus::Expired => f.write_str("expired"),
+            ExpiryStatus::Expiring { window_days } => write!(f, "expiring ({window_days}d)"),
+            ExpiryStatus::Valid => f.write_str("valid"),
+            ExpiryStatus::Unknown => f.write_str("unknown"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CertificateExpiry {
+    pub kind: CertificateKind,
+    pub prn: String,
+    pub serial: String,
+    pub description: Option<String>,
+    pub not_after: String,
+    pub days_left: Option<i64>,
+    #[serde(flatten)]
+    pub status: ExpiryStatus,
+    /// `None` when the certificate isn't mapped to an identifier or no
+    /// device has that identifier.
+    pub device_prn: Option<String>,
+    pub device_identifier: Option<String>,
+    pub cohort_prn: Option<String>,
+}
+
+impl CertificateExpiry {
+    pub fn is_flagged(&self) -> bool {
+        matches!(
+            self.status,
+            ExpiryStatus::Expired | ExpiryStatus::Expiring { .. }
+        )
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ExpiryReport {
+    pub generated_at: DateTime<Utc>,
+    pub certificates: Vec<CertificateExpiry>,
+}
+
+impl ExpiryReport {
+    pub fn flagged(&self) -> impl Iterator<Item = &CertificateExpiry> {
+        self.certificates.iter().filter(|c| c.is_flagged())
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+
+    pub fn to_csv(&self) -> String {
+        report::csv(CSV_HEADERS, &self.rows())
+    }
+
+    pub fn to_table(&self) -> String {
+        report::table(CSV_HEADERS, &self.rows())
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        let opt = |value: &Option<String>| value.clone().unwrap_or_default();
+        self.certificates
+            .iter()
+            .map(|c| {
+                vec![
+                    c.kind.to_string(),
+                    c.prn.clone(),
+                    c.serial.clone(),
+                    c.not_after.clone(),
+                    c.days_left.map(|d| d.to_string()).unwrap_or_default(),
+                    c.status.to_string(),
+                    opt(&c.device_prn),
+                    opt(&c.device_identifier),
+                    opt(&c.cohort_prn),
+                    opt(&c.description),
+                ]
+            })
+            .collect()
+    }
+}
+
+type AlertFn<'a> = Box<dyn FnMut(&CertificateExpiry) + Send + 'a>;
+
+pub struct ExpiryMonitor<'a> {
+    api: &'a Api,
+    windows: Vec<Duration>,
+    now: Option<DateTime<Utc>>,
+    alert: Option<AlertFn<'a>>,
+    /// Device identifier by normalized serial.
+    device_identifiers: HashMap<String, String>,
+}
+
+impl<'a> ExpiryMonitor<'a> {
+    /// A monitor warning 7, 30 and 90 days ahead.
+    pub fn new(api: &'a Api) -> Self {
+        Self {
+            api,
+            windows: vec![Duration::days(7), Duration::days(30), Duration::days(90)],
+            now: None,
+            alert: None,
+            device_identifiers: HashMap::new(),
+        }
+    }
+
+    pub fn windows(mut self, mut windows: Vec<Duration>) -> Self {
+        windows.sort();
+        self.windows = windows;
+        self
+    }
+
+    /// Evaluates expiry as of `now` instead of the current time.
+    pub fn at(mut self, now: DateTime<Utc>) -> Self {
+        self.now = Some(now);
+        self
+    }
+
+    /// Maps device certificates to the identifiers of the devices they were
+    /// issued for, by serial.
+    pub fn device_identifiers(
+        mut self,
+        identifiers: impl IntoIterator<Item = (String, String)>,
+    ) -> Self {
+        self.device_identifiers.extend(
+            identifiers
+                .into_iter()
+                .map(|(serial, identifier)| (normalize_serial(&serial), identifier)),
+        );
+        self
+    }
+
+    /// Maps each certificate in `pems` to the device named by its common
+    /// name.
+    pub fn device_certificate_pems(
+        self,
+        pems: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> Result<Self, Error> {
+        let mut identifiers = Vec::new();
+        for pem in pems {
+            let info = parse_pem(pem.as_ref())?;
+            if let Some(common_name) = common_name(&info.subject) {
+                identifiers.push((info.serial, common_name.to_string()));
+            }
+        }
+        Ok(self.device_identifiers(identifiers))
+    }
+
+    /// Called once for every expired or expiring certificate.
+    pub fn on_alert(mut self, alert: impl FnMut(&CertificateExpiry) + Send + 'a) -> Self {
+        self.alert = Some(Box::new(alert));
+        self
+    }
+
+    pub async fn run(&mut self) -> Result<ExpiryReport, Error> {
+        let now = self.now.unwrap_or_else(Utc::now);
+        let mut certificates = Vec::new();
+
+        for ca in self.ca_certificates().await? {
+            let mut expiry =
+                self.classify(CertificateKind::Ca, &ca.prn, &ca.serial, &ca.not_after, now);
+            expiry.description = ca.description;
+            certificates.push(expiry);
+        }
+
+        let device_certificates = self.device_certificates().await?;
+        let mapped = device_certificates.iter().any(|c| {
+            self.device_identifiers
+                .contains_key(&normalize_serial(&c.serial))
+        });
+        let devices: HashMap<String, Device> = if mapped {
+            list_all_devices(self.api)
+                .await
+                .context(Request)?
+                .context(MissingResponse {
+                    operation: "device list",
+                })?
+                .into_iter()
+                .map(|device| (device.identifier.clone(), device))
+                .collect()
+        } else {
+            HashMap::new()
+        };
+
+        for certificate in device_certificates {
+            let mut expiry = self.classify(
+                CertificateKind::Device,
+                &certificate.prn,
+                &certificate.serial,
+                &certificate.not_after,
+                now,
+            );
+            let identifier = self
+                .device_identifiers
+                .get(&normalize_serial(&certificate.serial));
+            if let Some(device) = identifier.and_then(|identifier| devices.get(identifier)) {
+                expiry.device_prn = Some(device.prn.clone());
+                expiry.cohort_prn = device.cohort_prn.clone();
+            }
+            expiry.device_identifier = identifier.cloned();
+            certificates.push(expiry);
+        }
+
+        if let Some(alert) = self.alert.as_mut() {
+            for expiry in certificates.iter().filter(|c| c.is_flagged()) {
+                alert(expiry);
+            }
+        }
+
+        Ok(ExpiryReport {
+            generated_at: now,
+            certificates,
+        })
+    }
+
+    fn classify(
+        &self,
+        kind: CertificateKind,
+        prn: &str,
+        serial: &str,
+        not_after: &str,
+        now: DateTime<Utc>,
+    ) -> CertificateExpiry {
+        let left = DateTime::parse_from_rfc3339(not_after)
+            .ok()
+            .map(|not_after| not_after.with_timezone(&Utc) - now);
+
+        let status = match left {
+            None => ExpiryStatus::Unknown,
+            Some(left) if left <= Duration::zero() => ExpiryStatus::Expired,
+            Some(left) => match self.windows.iter().find(|window| left <= **window) {
+                Some(window) => ExpiryStatus::Expiring {
+                    window_days: window.num_days(),
+                },
+                None => ExpiryStatus::Valid,
+            },
+        };
+
+        CertificateExpiry {
+            kind,
+            prn: prn.to_string(),
+            serial: serial.to_string(),
+            description: None,
+            not_after: not_after.to_string(),
+            days_left: left.map(|left| left.num_days()),
+            status,
+            device_prn: None,
+            device_identifier: None,
+            cohort_prn: None,
+        }
+    }
+
+    async fn ca_certificates(&self) -> Result<Vec<CaCertificate>, Error> {
+        let api = self.api;
+        list_all(|page| async move {
+            api.ca_certificates()
+                .list(ListCaCertificateParams {
+                    list: ListParams {
+                        page,
+                        ..Default::default()
+                    },
+                })
+                .await
+        })
+        .await
+        .context(Request)?
+        .context(MissingResponse {
+            operation: "CA certificate list",
+        })
+    }
+
+    async fn device_certificates(&self) -> Result<Vec<DeviceCertificate>, Error> {
+        let api = self.api;
+        list_all(|page| async move {
+            api.device_certificates()
+                .list(ListDeviceCertificateParams {
+                    list: ListParams {
+                        page,
+                        ..Default::default()
+                    },
+                })
+                .await
+        })
+        .await
+        .context(Request)?
+        .context(MissingResponse {
+            operation: "device certificate list",
+        })
+    }
+}
+
+fn common_name(subject: &str) -> Option<&str> {
+    subject
+        .split(',')
+        .find_map(|part| part.trim().strip_prefix("CN="))
+}
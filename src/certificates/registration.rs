@@ -0,0 +1,98 @@
+//! CA registration with proof of possession.
+//!
+//! Registering a CA requires a verification certificate whose common name is
+//! a code issued by the API and which is signed by the CA key. The
+//! verification certificate is generated locally and never leaves this
+//! process except as part of the registration request.
+
+use chrono::{Duration, Utc};
+use rcgen::{CertificateParams, DistinguishedName, DnType, KeyPair};
+use snafu::{OptionExt, ResultExt};
+
+use crate::api::ca_certificates::{
+    CaCertificate, CreateCaCertificateParams, CreateVerificationCodeParams,
+};
+use crate::Api;
+
+use super::{
+    parse_pem, set_validity, verify_chain, Error, Generation, MissingResponse, NotCa, Request,
+};
+
+/// Validity of the verification certificate. It is only checked once, at
+/// registration.
+const VERIFICATION_VALIDITY_DAYS: i64 = 30;
+
+/// Registers the CA in `ca_cert_pem`, proving possession of `ca_key_pem`.
+///
+/// The key must be a PKCS#8 PEM (`BEGIN PRIVATE KEY`).
+pub async fn register_ca(
+    api: &Api,
+    ca_cert_pem: &str,
+    ca_key_pem: &str,
+    description: Option<String>,
+) -> Result<CaCertificate, Error> {
+    let ca = parse_pem(ca_cert_pem)?;
+    if !ca.is_ca {
+        return NotCa { issuer: ca.subject }.fail();
+    }
+    let ca_key = KeyPair::from_pem(ca_key_pem).context(Generation)?;
+
+    let verification_code = api
+        .ca_certificates()
+        .create_verification_code(CreateVerificationCodeParams {})
+        .await
+        .context(Request)?
+        .context(MissingResponse {
+            operation: "verification code create",
+        })?
+        .verification_code;
+
+    let verification_certificate =
+        verification_certificate(ca_cert_pem, &ca_key, &verification_code)?;
+    // A key that does not belong to the CA yields a certificate that does not
+    // verify; catch that before the server does.
+    verify_chain(&verification_certificate, ca_cert_pem, Utc::now())?;
+
+    Ok(api
+        .ca_certificates()
+        .create(CreateCaCertificateParams {
+            certificate: ca_cert_pem.to_string(),
+            verification_certificate,
+            description,
+        })
+        .await
+        .context(Request)?
+        .context(MissingResponse {
+            operation: "CA certificate create",
+        })?
+        .ca_certificate)
+}
+
+/// Generates a certificate with common name `verification_code`, signed by
+/// the CA.
+pub fn verification_certificate(
+    ca_cert_pem: &str,
+    ca_key: &KeyPair,
+    verification_code: &str,
+) -> Result<String, Error> {
+    let ca_params = CertificateParams::from_ca_cert_pem(ca_cert_pem).context(Generation)?;
+    let ca_cert = ca_params.self_signed(ca_key).context(Generation)?;
+
+    let mut params = CertificateParams::new(Vec::new()).context(Generation)?;
+    let mut distinguished_name = DistinguishedName::new();
+    distinguished_name.push(DnType::CommonName, verification_code);
+    params.distinguished_name = distinguished_name;
+
+    let today = Utc::now().date_naive();
+    set_validity(
+        &mut params,
+        today,
+        today + Duration::days(VERIFICATION_VALIDITY_DAYS),
+    );
+
+    let key = KeyPair::generate().context(Generation)?;
+    Ok(params
+        .signed_by(&key, &ca_cert, ca_key)
+        .context(Generation)?
+        .pem())
+}
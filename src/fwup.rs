@@ -0,0 +1,249 @@
+//! Reading fwup firmware archives.
+//!
+//! A `.fw` file is a zip archive whose `meta.conf` entry carries the firmware
+//! metadata as `meta-*` assignments. [`read_firmware`] extracts and validates
+//! that metadata into [`FirmwareMetadata`] and measures the archive so the
+//! artifact version and binary for it can be created straight from the file.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use snafu::{OptionExt, ResultExt, Snafu};
+
+use crate::api::artifact_versions::CreateArtifactVersionParams;
+use crate::api::binaries::CreateBinaryParams;
+use crate::api::devices::FirmwareMetadata;
+
+const META_CONF: &str = "meta.conf";
+
+#[derive(Debug, Snafu)]
+#[snafu(context(suffix(false)))]
+pub enum Error {
+    #[snafu(display("Failed to read firmware {}: {}", path.display(), source))]
+    Io { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Firmware {} is not a valid archive: {}", path.display(), source))]
+    Archive {
+        path: PathBuf,
+        source: zip::result::ZipError,
+    },
+
+    #[snafu(display("Firmware {} has no {}", path.display(), META_CONF))]
+    MissingMetaConf { path: PathBuf },
+
+    #[snafu(display("Invalid {} line {}: {}", META_CONF, line, reason))]
+    Syntax { line: usize, reason: String },
+
+    #[snafu(display("Firmware metadata is missing {}", field))]
+    MissingField { field: String },
+
+    #[snafu(display("Firmware metadata field {} is invalid: {}", field, reason))]
+    InvalidField { field: String, reason: String },
+}
+
+/// A firmware archive's metadata together with the digest and size of the
+/// archive itself.
+#[derive(Debug, Eq, PartialEq)]
+pub struct Firmware {
+    pub metadata: FirmwareMetadata,
+    /// Lowercase hex SHA-256 of the archive.
+    pub hash: String,
+    pub size: u64,
+}
+
+impl Firmware {
+    /// Parameters for the artifact version of this firmware.
+    pub fn artifact_version_params(
+        &self,
+        artifact_prn: impl Into<String>,
+    ) -> CreateArtifactVersionParams {
+        CreateArtifactVersionParams {
+            artifact_prn: artifact_prn.into(),
+            custom_metadata: Some(self.custom_metadata()),
+            id: None,
+            description: self.metadata.description.clone(),
+            version: self.metadata.version.clone(),
+        }
+    }
+
+    /// Parameters for the binary of this firmware. The target defaults to the
+    /// fwup platform.
+    pub fn binary_params(
+        &self,
+        artifact_version_prn: impl Into<String>,
+        target: Option<String>,
+    ) -> CreateBinaryParams {
+        CreateBinaryParams {
+            artifact_version_prn: artifact_version_prn.into(),
+            custom_metadata: Some(self.custom_metadata()),
+            description: self.metadata.description.clone(),
+            hash: self.hash.clone(),
+            id: None,
+            size: self.size,
+            target: target.unwrap_or_else(|| self.metadata.platform.clone()),
+        }
+    }
+
+    /// The metadata as stored in `custom_metadata`, nested under `fwup`.
+    pub fn custom_metadata(&self) -> Map<String, Value> {
+        let mut fwup = Map::new();
+        let mut put = |key: &str, value: &Option<String>| {
+            if let Some(value) = value {
+                fwup.insert(key.to_string(), Value::String(value.clone()));
+            }
+        };
+        put("architecture", &Some(self.metadata.architecture.clone()));
+        put("author", &self.metadata.author);
+        put("description", &self.metadata.description);
+        put("fwup_version", &self.metadata.fwup_version);
+        put("misc", &self.metadata.misc);
+        put("platform", &Some(self.metadata.platform.clone()));
+        put("product", &Some(self.metadata.product.clone()));
+        put("uuid", &Some(self.metadata.uuid.clone()));
+        put("vcs_identifiers", &self.metadata.vcs_identifiers);
+        put("version", &Some(self.metadata.version.clone()));
+
+        let mut custom_metadata = Map::new();
+        custom_metadata.insert("fwup".to_string(), Value::Object(fwup));
+        custom_metadata
+    }
+}
+
+/// Reads and validates the fwup archive at `path`.
+pub fn read_firmware(path: impl AsRef<Path>) -> Result<Firmware, Error> {
+    let path = path.as_ref();
+
+    let mut file = File::open(path).context(Io { path })?;
+    let mut hasher = Sha256::new();
+    let size = io::copy(&mut file, &mut hasher).context(Io { path })?;
+    let hash = format!("{:x}", hasher.finalize());
+
+    let file = File::open(path).context(Io { path })?;
+    let mut archive = zip::ZipArchive::new(file).context(Archive { path })?;
+    let mut contents = String::new();
+    match archive.by_name(META_CONF) {
+        Ok(mut entry) => entry.read_to_string(&mut contents).context(Io { path })?,
+        Err(zip::result::ZipError::FileNotFound) => return MissingMetaConf { path }.fail(),
+        Err(e) => return Err(e).context(Archive { path }),
+    };
+
+    Ok(Firmware {
+        metadata: parse_meta_conf(&contents)?,
+        hash,
+        size,
+    })
+}
+
+/// Extracts and validates the `meta-*` assignments of a `meta.conf`.
+pub fn parse_meta_conf(contents: &str) -> Result<FirmwareMetadata, Error> {
+    let mut architecture = None;
+    let mut author = None;
+    let mut description = None;
+    let mut fwup_version = None;
+    let mut misc = None;
+    let mut platform = None;
+    let mut product = None;
+    let mut uuid = None;
+    let mut vcs_identifiers = None;
+    let mut version = None;
+
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        let Some(rest) = line.strip_prefix("meta-") else {
+            continue;
+        };
+        let Some((key, value)) = rest.split_once('=') else {
+            continue;
+        };
+
+        let value = unquote(value.trim()).map_err(|reason| Error::Syntax {
+            line: index + 1,
+            reason,
+        })?;
+        let value = (!value.is_empty()).then_some(value);
+
+        match key.trim() {
+            "architecture" => architecture = value,
+            "author" => author = value,
+            "description" => description = value,
+            "fwup-version" => fwup_version = value,
+            "misc" => misc = value,
+            "platform" => platform = value,
+            "product" => product = value,
+            "uuid" => uuid = value,
+            "vcs-identifier" => vcs_identifiers = value,
+            "version" => version = value,
+            _ => {}
+        }
+    }
+
+    let version = version.context(MissingField { field: "version" })?;
+    if let Err(e) = semver::Version::parse(&version) {
+        return InvalidField {
+            field: "version",
+            reason: e.to_string(),
+        }
+        .fail();
+    }
+
+    let uuid = uuid.context(MissingField { field: "uuid" })?;
+    if !is_uuid(&uuid) {
+        return InvalidField {
+            field: "uuid",
+            reason: format!("'{uuid}' is not a UUID"),
+        }
+        .fail();
+    }
+
+    Ok(FirmwareMetadata {
+        architecture: architecture.context(MissingField {
+            field: "architecture",
+        })?,
+        author,
+        description,
+        fwup_version,
+        misc,
+        platform: platform.context(MissingField { field: "platform" })?,
+        product: product.context(MissingField { field: "product" })?,
+        uuid,
+        vcs_identifiers,
+        version,
+    })
+}
+
+fn unquote(value: &str) -> Result<String, String> {
+    let Some(inner) = value.strip_prefix('"') else {
+        return Ok(value.to_string());
+    };
+    let Some(inner) = inner.strip_suffix('"') else {
+        return Err("unterminated string".to_string());
+    };
+
+    let mut out = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some(other) => out.push(other),
+            None => return Err("dangling escape".to_string()),
+        }
+    }
+    Ok(out)
+}
+
+fn is_uuid(value: &str) -> bool {
+    let groups: Vec<&str> = value.split('-').collect();
+    groups.len() == 5
+        && groups
+            .iter()
+            .zip([8, 4, 4, 4, 12])
+            .all(|(group, len)| group.len() == len && group.chars().all(|c| c.is_ascii_hexdigit()))
+}
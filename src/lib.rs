@@ -1,5 +1,10 @@
 pub mod api;
+pub mod certificates;
+pub mod fwup;
 pub mod list_params;
+mod report;
+#[cfg(feature = "testing")]
+pub mod testing;
 pub mod validators;
 
 pub use api::{Api, ApiOptions};
@@ -26,3 +26,44 @@ impl ListParams {
         query_params
     }
 }
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Direction {
+    Asc,
+    Desc,
+}
+
+impl Direction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Direction::Asc => "asc",
+            Direction::Desc => "desc",
+        }
+    }
+}
+
+/// Filters of a typed query, rendered in the `search` syntax: `field:'value'`
+/// terms joined with `and`.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct SearchTerms(Vec<(&'static str, String)>);
+
+impl SearchTerms {
+    pub(crate) fn push(&mut self, field: &'static str, value: String) {
+        self.0.push((field, value));
+    }
+
+    pub(crate) fn render(&self) -> Option<String> {
+        if self.0.is_empty() {
+            return None;
+        }
+        let terms: Vec<String> = self
+            .0
+            .iter()
+            .map(|(field, value)| {
+                let value = value.replace('\\', "\\\\").replace('\'', "\\'");
+                format!("{field}:'{value}'")
+            })
+            .collect();
+        Some(terms.join(" and "))
+    }
+}
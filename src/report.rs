@@ -0,0 +1,44 @@
+//! Rendering of tabular reports as CSV and aligned text tables.
+
+/// Renders `rows` as CSV with a header line.
+pub(crate) fn csv(headers: &[&str], rows: &[Vec<String>]) -> String {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    // Writing to memory only fails on invalid UTF-8, which a `String` can't
+    // contain.
+    writer.write_record(headers).expect("writing CSV to memory");
+    for row in rows {
+        writer.write_record(row).expect("writing CSV to memory");
+    }
+    let bytes = writer.into_inner().expect("flushing CSV to memory");
+    String::from_utf8(bytes).expect("CSV of strings is UTF-8")
+}
+
+/// Renders `rows` as a table with columns padded to their widest cell.
+pub(crate) fn table(headers: &[&str], rows: &[Vec<String>]) -> String {
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.chars().count()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let line = |cells: Vec<&str>| {
+        let padded: Vec<String> = cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{cell:<width$}"))
+            .collect();
+        padded.join("  ").trim_end().to_string()
+    };
+
+    let mut out = line(headers.to_vec());
+    out.push('\n');
+    let separator: Vec<String> = widths.iter().map(|w| "-".repeat(*w)).collect();
+    out.push_str(&line(separator.iter().map(String::as_str).collect()));
+    out.push('\n');
+    for row in rows {
+        out.push_str(&line(row.iter().map(String::as_str).collect()));
+        out.push('\n');
+    }
+    out
+}
@@ -0,0 +1,172 @@
+//! An in-memory fake of the admin API for tests, enabled by the `testing`
+//! feature.
+//!
+//! [`FakeServer`] listens on a local port and keeps products, cohorts,
+//! artifacts, artifact versions, binaries, bundles, releases, bundle
+//! overrides and their devices, devices, tunnels, CA and device
+//! certificates, webhooks and signing keys in memory, so a test can run a
+//! multi-step flow through [`Api`] without network access:
+//!
+//! - records get PRNs of the form `prn:1:<organization>:<type>:<uuid>`, with
+//!   UUIDs numbered in creation order, or the `id` given on create;
+//! - lists honour `search`, `order` and `limit` and page with `next_page`;
+//! - invalid requests fail with the shapes of
+//!   [`ApiError`](crate::api::error::ApiError): `401` with a status,
+//!   `404` with a detail and `422` with errors per field;
+//! - binaries start `uploadable` and only move along
+//!   [`BinaryState::can_transition_to`](crate::api::binaries::BinaryState::can_transition_to);
+//! - checking a device for an update resolves the stored releases and
+//!   bundle overrides with
+//!   [`ReleaseResolver`](crate::api::releases::resolver::ReleaseResolver),
+//!   and with `write` stores what the device reported;
+//! - a CA certificate needs a verification certificate for a code from
+//!   `/ca_certificates/verification_codes`, and a device certificate must be
+//!   issued by a registered CA;
+//! - tunnels open straight away, expire after their `ttl` and stay closed
+//!   or expired once they are.
+//!
+//! Any other route answers `404`.
+
+mod store;
+
+use std::convert::Infallible;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use http_body_util::{BodyExt, Full};
+use hyper::body::{Bytes, Incoming};
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Request, Response};
+use hyper_util::rt::TokioIo;
+use serde_json::Value;
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+
+use crate::{Api, ApiOptions};
+
+use store::Store;
+
+pub struct FakeServer {
+    address: SocketAddr,
+    store: Arc<Mutex<Store>>,
+    task: JoinHandle<()>,
+}
+
+impl FakeServer {
+    /// Starts a server with empty state on a free local port. It stops when
+    /// dropped.
+    pub async fn start() -> io::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await?;
+        let address = listener.local_addr()?;
+        let store = Arc::new(Mutex::new(Store::new()));
+
+        let task = tokio::spawn({
+            let store = store.clone();
+            async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    let store = store.clone();
+                    tokio::spawn(async move {
+                        let service = service_fn(move |request| handle(store.clone(), request));
+                        // A client hanging up mid-request only ends its connection.
+                        let _ = http1::Builder::new()
+                            .serve_connection(TokioIo::new(stream), service)
+                            .await;
+                    });
+                }
+            }
+        });
+
+        Ok(Self {
+            address,
+            store,
+            task,
+        })
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}", self.address)
+    }
+
+    /// An [`Api`] pointed at this server.
+    pub fn api(&self) -> Api {
+        Api::new(ApiOptions {
+            api_key: "fake-api-key".to_string(),
+            endpoint: Some(self.url()),
+            ca_bundle_path: None,
+            api_version: 1,
+        })
+    }
+
+    pub fn organization_prn(&self) -> String {
+        self.store().organization_prn()
+    }
+
+    /// Records returned per page when a list doesn't pass `limit`. Defaults
+    /// to 20.
+    pub fn set_page_size(&self, page_size: usize) {
+        self.store().page_size = page_size.max(1);
+    }
+
+    /// The stored records of `collection`, e.g. `"devices"`, in creation
+    /// order.
+    pub fn records(&self, collection: &str) -> Vec<Value> {
+        self.store().records(collection)
+    }
+
+    fn store(&self) -> MutexGuard<'_, Store> {
+        self.store
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Drop for FakeServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn handle(
+    store: Arc<Mutex<Store>>,
+    request: Request<Incoming>,
+) -> Result<Response<Full<Bytes>>, Infallible> {
+    let method = request.method().to_string();
+    let uri = request.uri().clone();
+    let authorized = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Token "))
+        .is_some_and(|token| !token.trim().is_empty());
+    let body = match request.into_body().collect().await {
+        Ok(body) => body.to_bytes(),
+        Err(_) => Bytes::new(),
+    };
+    let query: Vec<(String, String)> = reqwest::Url::parse(&format!("http://fake{uri}"))
+        .map(|url| url.query_pairs().into_owned().collect())
+        .unwrap_or_default();
+
+    let response = if authorized {
+        store
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .handle(&method, uri.path(), &query, &body)
+    } else {
+        store::Response::unauthorized()
+    };
+
+    let body = match response.body {
+        Some(body) => Bytes::from(body.to_string()),
+        None => Bytes::new(),
+    };
+    let mut builder = Response::builder().status(response.status);
+    if !body.is_empty() {
+        builder = builder.header(CONTENT_TYPE, "application/json");
+    }
+    Ok(builder
+        .body(Full::new(body))
+        .expect("status and headers are valid"))
+}
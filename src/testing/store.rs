@@ -0,0 +1,1333 @@
+//! In-memory state and request handling behind [`FakeServer`](super::FakeServer).
+
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+
+use crate::api::binaries::BinaryState;
+use crate::api::bundle_overrides::BundleOverride;
+use crate::api::devices::Device;
+use crate::api::events::{Event, EventType, TestFireEvent, WebhookEvent, WebhookEventType};
+use crate::api::releases::resolver::{DeviceState, Outcome, OverrideMembership, ReleaseResolver};
+use crate::api::releases::Release;
+use crate::api::tunnels::TunnelState;
+use crate::certificates::{parse_pem, verify_chain};
+
+type Record = Map<String, Value>;
+type FieldErrors = BTreeMap<String, Vec<String>>;
+
+/// A response before it is encoded as HTTP.
+pub(super) struct Response {
+    pub(super) status: u16,
+    pub(super) body: Option<Value>,
+}
+
+impl Response {
+    fn ok(status: u16, body: Value) -> Self {
+        Self {
+            status,
+            body: Some(body),
+        }
+    }
+
+    /// The `Standard` shape of `ApiError`.
+    pub(super) fn detail(status: u16, detail: impl Into<String>) -> Self {
+        Self::ok(status, json!({ "errors": { "detail": detail.into() } }))
+    }
+
+    /// The `Status` shape of `ApiError`.
+    pub(super) fn unauthorized() -> Self {
+        Self::ok(401, json!({ "status": "unauthorized" }))
+    }
+
+    /// The `Validation` shape of `ApiError`.
+    fn invalid(errors: FieldErrors) -> Self {
+        Self::ok(422, json!({ "errors": errors }))
+    }
+}
+
+struct Resource {
+    collection: &'static str,
+    singular: &'static str,
+    prn_type: &'static str,
+    /// Must be present and not blank on create.
+    required: &'static [&'static str],
+    /// Stored as `null` unless given on create.
+    optional: &'static [&'static str],
+    /// May be changed with a `PATCH`.
+    updatable: &'static [&'static str],
+    /// Fields holding the PRN of a record in another collection.
+    references: &'static [(&'static str, &'static str)],
+    /// Fields whose combined values must be unique within the collection.
+    unique: &'static [&'static str],
+    searchable: &'static [&'static str],
+}
+
+const RESOURCES: &[Resource] = &[
+    Resource {
+        collection: "products",
+        singular: "product",
+        prn_type: "product",
+        required: &["name"],
+        optional: &["archived"],
+        updatable: &["name", "archived"],
+        references: &[],
+        unique: &["name"],
+        searchable: &["name", "archived"],
+    },
+    Resource {
+        collection: "cohorts",
+        singular: "cohort",
+        prn_type: "cohort",
+        required: &["name", "product_prn"],
+        optional: &["description"],
+        updatable: &["name", "description"],
+        references: &[("product_prn", "products")],
+        unique: &["product_prn", "name"],
+        searchable: &["product_prn", "name"],
+    },
+    Resource {
+        collection: "artifacts",
+        singular: "artifact",
+        prn_type: "artifact",
+        required: &["name"],
+        optional: &["description", "custom_metadata"],
+        updatable: &["name", "description", "custom_metadata"],
+        references: &[],
+        unique: &["name"],
+        searchable: &["name"],
+    },
+    Resource {
+        collection: "artifact_versions",
+        singular: "artifact_version",
+        prn_type: "artifact_version",
+        required: &["artifact_prn", "version"],
+        optional: &["description", "custom_metadata"],
+        updatable: &["description", "custom_metadata"],
+        references: &[("artifact_prn", "artifacts")],
+        unique: &["artifact_prn", "version"],
+        searchable: &["artifact_prn", "version"],
+    },
+    Resource {
+        collection: "binaries",
+        singular: "binary",
+        prn_type: "binary",
+        required: &["artifact_version_prn", "target"],
+        optional: &["description", "custom_metadata", "hash", "size"],
+        updatable: &["description", "custom_metadata", "state", "hash", "size"],
+        references: &[("artifact_version_prn", "artifact_versions")],
+        unique: &[],
+        searchable: &["artifact_version_prn", "target", "state"],
+    },
+    Resource {
+        collection: "devices",
+        singular: "device",
+        prn_type: "device",
+        required: &["identifier", "product_prn"],
+        optional: &[
+            "cohort_prn",
+            "description",
+            "quarantined",
+            "tags",
+            "target",
+            "last_connected_at",
+            "reported_bundle_prn",
+            "reported_release_prn",
+            "reported_release_version",
+        ],
+        updatable: &[
+            "cohort_prn",
+            "description",
+            "product_prn",
+            "quarantined",
+            "tags",
+            "target",
+        ],
+        references: &[("product_prn", "products"), ("cohort_prn", "cohorts")],
+        unique: &["identifier"],
+        searchable: &[
+            "cohort_prn",
+            "product_prn",
+            "identifier",
+            "target",
+            "tags",
+            "quarantined",
+        ],
+    },
+    // V1 bundles are created from `artifact_version_prns`, V2 bundles from
+    // `binaries`; see `Store::check_bundle`.
+    Resource {
+        collection: "bundles",
+        singular: "bundle",
+        prn_type: "bundle",
+        required: &[],
+        optional: &["name", "artifact_version_prns", "binaries"],
+        updatable: &["name"],
+        references: &[],
+        unique: &[],
+        searchable: &["name"],
+    },
+    Resource {
+        collection: "releases",
+        singular: "release",
+        prn_type: "release",
+        required: &["bundle_prn", "cohort_prn", "name", "schedule_date"],
+        optional: &[
+            "description",
+            "disabled",
+            "next_release_prn",
+            "phase_mode",
+            "phase_tags",
+            "phase_value",
+            "required",
+            "version",
+            "version_requirement",
+        ],
+        updatable: &[
+            "bundle_prn",
+            "description",
+            "disabled",
+            "name",
+            "next_release_prn",
+            "phase_mode",
+            "phase_tags",
+            "phase_value",
+            "required",
+            "schedule_date",
+            "version",
+            "version_requirement",
+        ],
+        references: &[
+            ("bundle_prn", "bundles"),
+            ("cohort_prn", "cohorts"),
+            ("next_release_prn", "releases"),
+            ("previous_release_prn", "releases"),
+        ],
+        unique: &["cohort_prn", "name"],
+        searchable: &["bundle_prn", "cohort_prn", "name", "version", "disabled"],
+    },
+    // Devices are added with `/bundle_overrides/{prn}/devices`.
+    Resource {
+        collection: "bundle_overrides",
+        singular: "bundle_override",
+        prn_type: "bundle_override",
+        required: &["name", "bundle_prn", "starts_at"],
+        optional: &["description", "ends_at"],
+        updatable: &["name", "description", "bundle_prn", "starts_at", "ends_at"],
+        references: &[("bundle_prn", "bundles")],
+        unique: &["name"],
+        searchable: &["name", "bundle_prn"],
+    },
+    // Tunnels open straight away and expire after their `ttl`; see
+    // `Store::update_tunnel`.
+    Resource {
+        collection: "tunnels",
+        singular: "tunnel",
+        prn_type: "tunnel",
+        required: &["device_prn", "device_tunnel_port"],
+        optional: &["cidr_block_allowlist", "device_public_key"],
+        updatable: &["state"],
+        references: &[("device_prn", "devices")],
+        unique: &[],
+        searchable: &["device_prn", "state"],
+    },
+    // The serial and validity are read from the certificate, which must be
+    // verified with a code from `/ca_certificates/verification_codes`.
+    Resource {
+        collection: "ca_certificates",
+        singular: "ca_certificate",
+        prn_type: "ca_certificate",
+        required: &["certificate", "verification_certificate"],
+        optional: &["description", "serial", "not_before", "not_after"],
+        updatable: &["description"],
+        references: &[],
+        unique: &["serial"],
+        searchable: &["serial", "description"],
+    },
+    // The certificate must be issued by a registered CA certificate.
+    Resource {
+        collection: "device_certificates",
+        singular: "device_certificate",
+        prn_type: "device_certificate",
+        required: &["certificate", "device_prn"],
+        optional: &["serial", "not_before", "not_after"],
+        updatable: &[],
+        references: &[("device_prn", "devices")],
+        unique: &["serial"],
+        searchable: &["device_prn", "serial"],
+    },
+    Resource {
+        collection: "webhooks",
+        singular: "webhook",
+        prn_type: "webhook",
+        required: &["url"],
+        optional: &["description", "enabled_events"],
+        updatable: &["description", "url", "state", "enabled_events"],
+        references: &[],
+        unique: &[],
+        searchable: &["url", "state"],
+    },
+    // The `keyid` is derived from the value.
+    Resource {
+        collection: "signing_keys",
+        singular: "signing_key",
+        prn_type: "signing_key",
+        required: &["name", "value"],
+        optional: &["keyid"],
+        updatable: &["name", "value"],
+        references: &[],
+        unique: &["name"],
+        searchable: &["name", "keyid"],
+    },
+];
+
+pub(super) struct Store {
+    organization_id: String,
+    next_id: u64,
+    pub(super) page_size: usize,
+    records: HashMap<&'static str, Vec<Record>>,
+    /// Devices of each bundle override, by bundle override PRN.
+    override_devices: HashMap<String, Vec<Record>>,
+    /// Verification codes handed out and not yet used.
+    verification_codes: Vec<String>,
+}
+
+impl Store {
+    pub(super) fn new() -> Self {
+        Self {
+            organization_id: uuid(0),
+            next_id: 1,
+            page_size: 20,
+            records: HashMap::new(),
+            override_devices: HashMap::new(),
+            verification_codes: Vec::new(),
+        }
+    }
+
+    pub(super) fn organization_prn(&self) -> String {
+        format!("prn:1:{}", self.organization_id)
+    }
+
+    pub(super) fn records(&self, collection: &str) -> Vec<Value> {
+        self.records
+            .get(collection)
+            .map(|records| records.iter().cloned().map(Value::Object).collect())
+            .unwrap_or_default()
+    }
+
+    pub(super) fn handle(
+        &mut self,
+        method: &str,
+        path: &str,
+        query: &[(String, String)],
+        body: &[u8],
+    ) -> Response {
+        self.expire_tunnels();
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+        match (method, segments.as_slice()) {
+            ("POST", ["devices", prn, "update"]) => match parse_body(body) {
+                Ok(body) => self.device_update(prn, body),
+                Err(response) => response,
+            },
+            ("POST", ["ca_certificates", "verification_codes"]) => {
+                let code = self.next_uuid().replace('-', "");
+                self.verification_codes.push(code.clone());
+                Response::ok(201, json!({ "verification_code": code }))
+            }
+            ("GET", ["bundle_overrides", prn, "devices"]) => self.override_devices(prn, query),
+            ("POST", ["bundle_overrides", prn, "devices"]) => match parse_body(body) {
+                Ok(body) => self.add_override_device(prn, body),
+                Err(response) => response,
+            },
+            ("DELETE", ["bundle_overrides", prn, "devices", device_prn]) => {
+                let removed = self.override_devices.get_mut(*prn).and_then(|devices| {
+                    let index = devices
+                        .iter()
+                        .position(|d| d["device_prn"] == *device_prn)?;
+                    Some(devices.remove(index))
+                });
+                match removed {
+                    Some(_) => Response {
+                        status: 204,
+                        body: None,
+                    },
+                    None => Response::detail(404, "Not Found"),
+                }
+            }
+            ("POST", ["webhooks", prn, "roll_secret"]) => self.roll_secret(prn),
+            ("POST", ["webhooks", prn, "test_fire"]) => self.test_fire(prn),
+            _ => self.handle_resource(method, path, &segments, query, body),
+        }
+    }
+
+    /// The create, read, update, delete and list routes of [`RESOURCES`].
+    fn handle_resource(
+        &mut self,
+        method: &str,
+        path: &str,
+        segments: &[&str],
+        query: &[(String, String)],
+        body: &[u8],
+    ) -> Response {
+        let resource = RESOURCES.iter().find(|r| r.collection == segments[0]);
+
+        match (method, resource, &segments[1..]) {
+            ("GET", Some(resource), []) => self.list(resource, query),
+            ("POST", Some(resource), []) => match parse_body(body) {
+                Ok(body) => self.create(resource, body),
+                Err(response) => response,
+            },
+            ("GET", Some(resource), [prn]) => match self.find(resource, prn) {
+                Some(index) => Response::ok(
+                    200,
+                    json!({ resource.singular: self.records[resource.collection][index] }),
+                ),
+                None => Response::detail(404, "Not Found"),
+            },
+            ("PATCH", Some(resource), [prn]) => match parse_body(body) {
+                Ok(body) => self.update(resource, prn, body),
+                Err(response) => response,
+            },
+            ("DELETE", Some(resource), [prn]) => match self.find(resource, prn) {
+                Some(index) => {
+                    self.collection(resource).remove(index);
+                    self.override_devices.remove(*prn);
+                    for devices in self.override_devices.values_mut() {
+                        devices.retain(|device| device["device_prn"] != *prn);
+                    }
+                    Response {
+                        status: 204,
+                        body: None,
+                    }
+                }
+                None => Response::detail(404, "Not Found"),
+            },
+            _ => Response::detail(404, format!("The fake server has no route {method} {path}")),
+        }
+    }
+
+    fn create(&mut self, resource: &Resource, mut body: Record) -> Response {
+        let mut errors = FieldErrors::new();
+        for field in resource.required {
+            if is_blank(body.get(*field)) {
+                add(&mut errors, field, "can't be blank");
+            }
+        }
+        self.derive(resource, &mut body, &mut errors);
+        self.check_references(resource, &body, &mut errors);
+        self.check_unique(resource, &body, None, &mut errors);
+        if !errors.is_empty() {
+            return Response::invalid(errors);
+        }
+
+        let id = match body.remove("id") {
+            Some(Value::String(id)) => id,
+            _ => self.next_uuid(),
+        };
+        let now = now();
+        let previous_release_prn = body.remove("previous_release_prn");
+        let ttl = body.get("ttl").and_then(Value::as_i64).unwrap_or(3600);
+        let mut record = Record::new();
+        for field in resource.required.iter().chain(resource.optional) {
+            let value = body.remove(*field).unwrap_or(Value::Null);
+            record.insert(field.to_string(), value);
+        }
+        match resource.collection {
+            "products" => default(&mut record, "archived", json!(false)),
+            "devices" => default(&mut record, "quarantined", json!(false)),
+            "binaries" => {
+                record.insert(
+                    "state".to_string(),
+                    json!(BinaryState::Uploadable.to_string()),
+                );
+                record.insert("revision".to_string(), json!(1));
+                record.insert("signatures".to_string(), json!([]));
+            }
+            "bundles" => bundle_record(&mut record),
+            "releases" => {
+                default(&mut record, "disabled", json!(false));
+                default(&mut record, "required", json!(false));
+                record.insert("phase_type".to_string(), Value::Null);
+            }
+            "tunnels" => {
+                let key = Sha256::digest(id.as_bytes());
+                for (field, value) in [
+                    ("state", json!(TunnelState::Open.to_string())),
+                    ("expires_at", json!(in_seconds(ttl))),
+                    ("device_proxy_ip_address", json!("10.0.0.2")),
+                    ("device_proxy_port", json!(51820)),
+                    ("server_proxy_ip_address", json!("10.0.0.1")),
+                    ("server_proxy_port", json!(51821)),
+                    ("server_public_key", json!(STANDARD.encode(key))),
+                    ("server_tunnel_ip_address", json!("127.0.0.1")),
+                    ("server_tunnel_port", json!(51822)),
+                ] {
+                    record.insert(field.to_string(), value);
+                }
+            }
+            "ca_certificates" => {
+                record.remove("verification_certificate");
+            }
+            "webhooks" => {
+                default(&mut record, "enabled_events", json!([]));
+                record.insert("state".to_string(), json!("disabled"));
+                record.insert("secret".to_string(), json!(self.next_secret()));
+            }
+            _ => {}
+        }
+        record.insert(
+            "organization_prn".to_string(),
+            json!(self.organization_prn()),
+        );
+        record.insert(
+            "prn".to_string(),
+            json!(format!(
+                "prn:1:{}:{}:{id}",
+                self.organization_id, resource.prn_type
+            )),
+        );
+        record.insert("inserted_at".to_string(), json!(now));
+        record.insert("updated_at".to_string(), json!(now));
+        if resource.collection == "releases" {
+            schedule(&mut record);
+        }
+
+        self.collection(resource).push(record.clone());
+        // The previous release now leads to the created one.
+        if let Some(Value::String(previous)) = previous_release_prn {
+            if let Some(index) = self.find(resource, &previous) {
+                self.collection(resource)[index]
+                    .insert("next_release_prn".to_string(), record["prn"].clone());
+            }
+        }
+        Response::ok(201, json!({ resource.singular: record }))
+    }
+
+    fn update(&mut self, resource: &Resource, prn: &str, mut body: Record) -> Response {
+        let Some(index) = self.find(resource, prn) else {
+            return Response::detail(404, "Not Found");
+        };
+        body.remove("prn");
+        if resource.collection == "tunnels" {
+            return self.update_tunnel(index, body);
+        }
+
+        let mut errors = FieldErrors::new();
+        for field in body.keys() {
+            if !resource.updatable.contains(&field.as_str()) {
+                add(&mut errors, field, "can't be changed");
+            }
+        }
+        self.derive(resource, &mut body, &mut errors);
+        for field in resource.required {
+            if body.contains_key(*field) && is_blank(body.get(*field)) {
+                add(&mut errors, field, "can't be blank");
+            }
+        }
+        self.check_references(resource, &body, &mut errors);
+
+        let mut record = self.records[resource.collection][index].clone();
+        let current_state = record.get("state").cloned();
+        record.extend(body.clone());
+        self.check_unique(resource, &record, Some(index), &mut errors);
+        if let (Some(Value::String(from)), Some(to), "binaries") =
+            (current_state, body.get("state"), resource.collection)
+        {
+            check_transition(&from, to, &record, &mut errors);
+        }
+        if !errors.is_empty() {
+            return Response::invalid(errors);
+        }
+
+        record.insert("updated_at".to_string(), json!(now()));
+        if resource.collection == "releases" {
+            schedule(&mut record);
+        }
+        self.collection(resource)[index] = record.clone();
+        Response::ok(200, json!({ resource.singular: record }))
+    }
+
+    /// Renews a tunnel for its `ttl` or closes it. Once closed or expired a
+    /// tunnel stays so, and renewing it returns it unchanged.
+    fn update_tunnel(&mut self, index: usize, mut body: Record) -> Response {
+        let resource = resource("tunnels");
+        let ttl = body.remove("ttl");
+        let mut record = self.records[resource.collection][index].clone();
+
+        let mut errors = FieldErrors::new();
+        for field in body.keys() {
+            if field != "state" {
+                add(&mut errors, field, "can't be changed");
+            }
+        }
+        let terminal =
+            ["closed", "expired"].contains(&record["state"].as_str().unwrap_or_default());
+        match body.get("state").and_then(Value::as_str) {
+            None => {}
+            Some("closed") if !terminal => {
+                record.insert("state".to_string(), json!(TunnelState::Closed.to_string()));
+            }
+            Some(state) if record["state"] == state => {}
+            Some(state) => add(
+                &mut errors,
+                "state",
+                &format!("cannot transition from {} to {state}", record["state"]),
+            ),
+        }
+        match ttl {
+            None => {}
+            Some(Value::Number(ttl)) if !terminal => {
+                let ttl = ttl.as_i64().unwrap_or_default();
+                record.insert("expires_at".to_string(), json!(in_seconds(ttl)));
+            }
+            Some(Value::Number(_)) => {}
+            Some(_) => add(&mut errors, "ttl", "must be a number"),
+        }
+        if !errors.is_empty() {
+            return Response::invalid(errors);
+        }
+
+        record.insert("updated_at".to_string(), json!(now()));
+        self.collection(resource)[index] = record.clone();
+        Response::ok(200, json!({ resource.singular: record }))
+    }
+
+    fn expire_tunnels(&mut self) {
+        let now = Utc::now();
+        for tunnel in self.records.get_mut("tunnels").into_iter().flatten() {
+            let expired = tunnel["expires_at"]
+                .as_str()
+                .and_then(|expires_at| DateTime::parse_from_rfc3339(expires_at).ok())
+                .is_some_and(|expires_at| expires_at <= now);
+            let live =
+                ["requested", "open"].contains(&tunnel["state"].as_str().unwrap_or_default());
+            if expired && live {
+                tunnel.insert("state".to_string(), json!(TunnelState::Expired.to_string()));
+            }
+        }
+    }
+
+    fn override_devices(&self, prn: &str, query: &[(String, String)]) -> Response {
+        if self.find(resource("bundle_overrides"), prn).is_none() {
+            return Response::detail(404, "Not Found");
+        }
+        let mut errors = FieldErrors::new();
+        let (offset, limit) = self.paging(query, &mut errors);
+        if !errors.is_empty() {
+            return Response::invalid(errors);
+        }
+
+        let devices = self
+            .override_devices
+            .get(prn)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let next_page = (offset + limit < devices.len()).then(|| encode_page(offset + limit));
+        let page: Vec<&Record> = devices.iter().skip(offset).take(limit).collect();
+        Response::ok(200, json!({ "devices": page, "next_page": next_page }))
+    }
+
+    fn add_override_device(&mut self, prn: &str, body: Record) -> Response {
+        if self.find(resource("bundle_overrides"), prn).is_none() {
+            return Response::detail(404, "Not Found");
+        }
+        let mut errors = FieldErrors::new();
+        let device_prn = match body.get("device_prn") {
+            Some(Value::String(device_prn)) if !device_prn.trim().is_empty() => device_prn.clone(),
+            _ => {
+                add(&mut errors, "device_prn", "can't be blank");
+                return Response::invalid(errors);
+            }
+        };
+        if self.find(resource("devices"), &device_prn).is_none() {
+            add(&mut errors, "device_prn", "does not exist");
+        }
+        let devices = self.override_devices.entry(prn.to_string()).or_default();
+        if devices
+            .iter()
+            .any(|d| d["device_prn"] == device_prn.as_str())
+        {
+            add(&mut errors, "device_prn", "has already been taken");
+        }
+        if !errors.is_empty() {
+            return Response::invalid(errors);
+        }
+
+        let now = now();
+        let mut device = Record::new();
+        device.insert("device_prn".to_string(), json!(device_prn));
+        device.insert("inserted_at".to_string(), json!(now));
+        device.insert("updated_at".to_string(), json!(now));
+        devices.push(device.clone());
+        Response::ok(201, json!({ "device": device }))
+    }
+
+    fn roll_secret(&mut self, prn: &str) -> Response {
+        let webhooks = resource("webhooks");
+        let Some(index) = self.find(webhooks, prn) else {
+            return Response::detail(404, "Not Found");
+        };
+        let secret = self.next_secret();
+        let record = &mut self.collection(webhooks)[index];
+        record.insert("secret".to_string(), json!(secret));
+        record.insert("updated_at".to_string(), json!(now()));
+        Response::ok(200, json!({ webhooks.singular: record }))
+    }
+
+    fn test_fire(&mut self, prn: &str) -> Response {
+        if self.find(resource("webhooks"), prn).is_none() {
+            return Response::detail(404, "Not Found");
+        }
+        let id = self.next_uuid();
+        let event = Event {
+            data: EventType::Webhook(WebhookEvent {
+                data: WebhookEventType::TestFire(TestFireEvent {
+                    webhook_prn: prn.to_string(),
+                }),
+            }),
+            inserted_at: now(),
+            prn: format!("prn:1:{}:event:{id}", self.organization_id),
+            version: 1,
+        };
+        match serde_json::to_value(event) {
+            Ok(event) => Response::ok(201, json!({ "event": event })),
+            Err(error) => Response::detail(500, error.to_string()),
+        }
+    }
+
+    /// Checks a bundle has either artifact versions (V1) or binaries (V2),
+    /// and that they exist.
+    fn check_bundle(&self, body: &Record, errors: &mut FieldErrors) {
+        let prns = |field: &str| match body.get(field) {
+            Some(Value::Array(items)) if !items.is_empty() => Some(items.clone()),
+            _ => None,
+        };
+        let (field, collection, items) = match (prns("artifact_version_prns"), prns("binaries")) {
+            (Some(items), None) => ("artifact_version_prns", "artifact_versions", items),
+            (None, Some(items)) => ("binaries", "binaries", items),
+            (Some(_), Some(_)) => {
+                add(
+                    errors,
+                    "binaries",
+                    "can't be given with artifact_version_prns",
+                );
+                return;
+            }
+            (None, None) => {
+                add(errors, "binaries", "can't be blank");
+                return;
+            }
+        };
+        for item in items {
+            let prn = match &item {
+                Value::Object(binary) => binary.get("prn").and_then(Value::as_str),
+                other => other.as_str(),
+            };
+            let exists = prn.is_some_and(|prn| {
+                self.records
+                    .get(collection)
+                    .is_some_and(|records| records.iter().any(|r| r["prn"] == prn))
+            });
+            if !exists {
+                add(errors, field, "does not exist");
+            }
+        }
+    }
+
+    /// Resolves the update for a device from the stored releases. With
+    /// `write` the reported release and bundle are stored on the device.
+    fn device_update(&mut self, prn: &str, body: Record) -> Response {
+        let devices = resource("devices");
+        let Some(index) = self.find(devices, prn) else {
+            return Response::detail(404, "Not Found");
+        };
+        let device: Device = match serde_json::from_value(Value::Object(
+            self.records[devices.collection][index].clone(),
+        )) {
+            Ok(device) => device,
+            Err(error) => return Response::detail(500, error.to_string()),
+        };
+        let releases: Vec<Release> = match self
+            .records("releases")
+            .into_iter()
+            .map(serde_json::from_value)
+            .collect()
+        {
+            Ok(releases) => releases,
+            Err(error) => return Response::detail(500, error.to_string()),
+        };
+
+        let field = |name: &str| body.get(name).and_then(Value::as_str).map(str::to_string);
+        let release_prn = field("release_prn");
+        let mut state = DeviceState::from(&device);
+        if let Some(version) = field("release_version").or_else(|| {
+            let release_prn = release_prn.as_deref()?;
+            releases
+                .iter()
+                .find(|release| release.prn == release_prn)?
+                .version
+                .clone()
+        }) {
+            state.release_version = Some(version);
+        }
+
+        let overrides: Vec<BundleOverride> = match self
+            .records("bundle_overrides")
+            .into_iter()
+            .map(serde_json::from_value)
+            .collect()
+        {
+            Ok(overrides) => overrides,
+            Err(error) => return Response::detail(500, error.to_string()),
+        };
+        let mut resolver = ReleaseResolver::new(&releases);
+        for bundle_override in &overrides {
+            resolver = resolver.with_override(OverrideMembership {
+                bundle_override,
+                device_prns: self
+                    .override_devices
+                    .get(&bundle_override.prn)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|d| d["device_prn"].as_str().map(str::to_string))
+                    .collect(),
+            });
+        }
+
+        let resolution = match resolver.resolve(&state) {
+            Ok(resolution) => resolution,
+            Err(error) => {
+                let mut errors = FieldErrors::new();
+                add(&mut errors, "release_version", &error.to_string());
+                return Response::invalid(errors);
+            }
+        };
+
+        if body.get("write") == Some(&Value::Bool(true)) {
+            let record = &mut self.collection(devices)[index];
+            for (from, to) in [
+                ("release_prn", "reported_release_prn"),
+                ("release_version", "reported_release_version"),
+                ("bundle_prn", "reported_bundle_prn"),
+            ] {
+                if let Some(value) = body.get(from) {
+                    record.insert(to.to_string(), value.clone());
+                }
+            }
+            record.insert("last_connected_at".to_string(), json!(now()));
+        }
+
+        let update = match resolution.outcome {
+            Outcome::Release {
+                release_prn,
+                bundle_prn,
+                ..
+            } => json!({
+                "status": "update",
+                "bundle_prn": bundle_prn,
+                "source_prn": release_prn,
+                "source_type": "release",
+                "manifest": self.manifest(&bundle_prn),
+            }),
+            Outcome::BundleOverride {
+                bundle_override_prn,
+                bundle_prn,
+            } => json!({
+                "status": "update",
+                "bundle_prn": bundle_prn,
+                "source_prn": bundle_override_prn,
+                "source_type": "bundle_override",
+                "manifest": self.manifest(&bundle_prn),
+            }),
+            Outcome::NoUpdate => json!({
+                "status": "no_update",
+                "bundle_prn": null,
+                "source_prn": null,
+                "source_type": null,
+                "manifest": null,
+            }),
+        };
+        Response::ok(200, update)
+    }
+
+    /// The binaries of a V2 bundle as listed in an update.
+    fn manifest(&self, bundle_prn: &str) -> Value {
+        let find = |collection: &str, prn: &Value| {
+            self.records
+                .get(collection)
+                .and_then(|records| records.iter().find(|r| r["prn"] == *prn))
+        };
+        let Some(Value::Array(binaries)) =
+            find("bundles", &json!(bundle_prn)).and_then(|bundle| bundle.get("binaries"))
+        else {
+            return json!([]);
+        };
+
+        let entries: Vec<Value> = binaries
+            .iter()
+            .filter_map(|entry| {
+                let binary = find("binaries", &entry["prn"])?;
+                let artifact_version = find("artifact_versions", &binary["artifact_version_prn"]);
+                let custom_metadata = match &entry["custom_metadata"] {
+                    Value::Null => binary["custom_metadata"].clone(),
+                    custom_metadata => custom_metadata.clone(),
+                };
+                Some(json!({
+                    "prn": binary["prn"],
+                    "artifact_version_prn": binary["artifact_version_prn"],
+                    "artifact_prn": artifact_version.map(|v| v["artifact_prn"].clone()),
+                    "custom_metadata": custom_metadata,
+                    "hash": binary["hash"],
+                    "size": binary["size"],
+                    "target": binary["target"],
+                    "signatures": [],
+                    "url": null,
+                }))
+            })
+            .collect();
+        Value::Array(entries)
+    }
+
+    fn list(&self, resource: &Resource, query: &[(String, String)]) -> Response {
+        let param = |name: &str| {
+            query
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.as_str())
+        };
+
+        let mut errors = FieldErrors::new();
+        let terms = match param("search").map(parse_search).transpose() {
+            Ok(terms) => terms.unwrap_or_default(),
+            Err(message) => {
+                add(&mut errors, "search", &message);
+                Vec::new()
+            }
+        };
+        for (field, _) in &terms {
+            if !resource.searchable.contains(&field.as_str()) {
+                add(&mut errors, "search", &format!("{field} is not searchable"));
+            }
+        }
+        let order = param("order").map(|order| {
+            let (field, direction) = order.split_once(':').unwrap_or((order, "asc"));
+            (field.to_string(), direction == "desc")
+        });
+        if let Some((field, _)) = &order {
+            let sortable = ["inserted_at", "updated_at"].contains(&field.as_str())
+                || resource.required.contains(&field.as_str())
+                || resource.optional.contains(&field.as_str());
+            if !sortable {
+                add(&mut errors, "order", &format!("{field} is not sortable"));
+            }
+        }
+        let (offset, limit) = self.paging(query, &mut errors);
+        if !errors.is_empty() {
+            return Response::invalid(errors);
+        }
+
+        let mut records: Vec<&Record> = self
+            .records
+            .get(resource.collection)
+            .map(|records| records.iter().collect())
+            .unwrap_or_default();
+        records.retain(|record| {
+            terms
+                .iter()
+                .all(|(field, value)| matches(record.get(field), value))
+        });
+        if let Some((field, descending)) = &order {
+            records.sort_by(|a, b| {
+                let ordering = compare(a.get(field), b.get(field));
+                if *descending {
+                    ordering.reverse()
+                } else {
+                    ordering
+                }
+            });
+        }
+
+        let next_page = (offset + limit < records.len()).then(|| encode_page(offset + limit));
+        let page: Vec<&Record> = records.into_iter().skip(offset).take(limit).collect();
+        Response::ok(
+            200,
+            json!({ resource.collection: page, "next_page": next_page }),
+        )
+    }
+
+    /// The offset and size of the page a list asks for.
+    fn paging(&self, query: &[(String, String)], errors: &mut FieldErrors) -> (usize, usize) {
+        let param = |name: &str| {
+            query
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.as_str())
+        };
+        let limit = match param("limit").map(str::parse::<usize>) {
+            None => self.page_size,
+            Some(Ok(limit)) if limit > 0 => limit,
+            Some(_) => {
+                add(errors, "limit", "must be a positive number");
+                0
+            }
+        };
+        let offset = match param("page").map(decode_page) {
+            None => 0,
+            Some(Some(offset)) => offset,
+            Some(None) => {
+                add(errors, "page", "is invalid");
+                0
+            }
+        };
+        (offset, limit)
+    }
+
+    /// Fills in the fields the server works out from the body, and checks
+    /// what the generic rules of [`Resource`] can't express.
+    fn derive(&mut self, resource: &Resource, body: &mut Record, errors: &mut FieldErrors) {
+        match resource.collection {
+            "bundles" => self.check_bundle(body, errors),
+            "ca_certificates" => {
+                let (Some(Value::String(certificate)), Some(Value::String(verification))) = (
+                    body.get("certificate"),
+                    body.get("verification_certificate"),
+                ) else {
+                    return;
+                };
+                let info = match parse_pem(certificate) {
+                    Ok(info) if info.is_ca => info,
+                    Ok(_) => return add(errors, "certificate", "is not a CA certificate"),
+                    Err(_) => return add(errors, "certificate", "is invalid"),
+                };
+                let code = parse_pem(verification).ok().and_then(|verification| {
+                    verification.subject.strip_prefix("CN=").map(str::to_string)
+                });
+                match code.and_then(|code| self.verification_codes.iter().position(|c| *c == code))
+                {
+                    Some(index) if verify_chain(verification, certificate, Utc::now()).is_ok() => {
+                        self.verification_codes.remove(index);
+                    }
+                    Some(_) => add(
+                        errors,
+                        "verification_certificate",
+                        "is not signed by the certificate",
+                    ),
+                    None => add(
+                        errors,
+                        "verification_certificate",
+                        "is not for an issued verification code",
+                    ),
+                }
+                insert_validity(body, &info.serial, info.not_before, info.not_after);
+            }
+            "device_certificates" => {
+                let Some(Value::String(certificate)) = body.get("certificate") else {
+                    return;
+                };
+                let info = match parse_pem(certificate) {
+                    Ok(info) => info,
+                    Err(_) => return add(errors, "certificate", "is invalid"),
+                };
+                let issued = self.records.get("ca_certificates").is_some_and(|cas| {
+                    cas.iter().any(|ca| {
+                        ca["certificate"]
+                            .as_str()
+                            .is_some_and(|ca| verify_chain(certificate, ca, Utc::now()).is_ok())
+                    })
+                });
+                if !issued {
+                    add(
+                        errors,
+                        "certificate",
+                        "is not issued by a registered CA certificate",
+                    );
+                }
+                insert_validity(body, &info.serial, info.not_before, info.not_after);
+            }
+            "signing_keys" => {
+                if let Some(Value::String(value)) = body.get("value") {
+                    let keyid = format!("{:x}", Sha256::digest(value.as_bytes()));
+                    body.insert("keyid".to_string(), json!(keyid));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn check_references(&self, resource: &Resource, body: &Record, errors: &mut FieldErrors) {
+        for (field, collection) in resource.references {
+            if let Some(Value::String(prn)) = body.get(*field) {
+                let exists = self
+                    .records
+                    .get(collection)
+                    .is_some_and(|records| records.iter().any(|r| r["prn"] == prn.as_str()));
+                if !exists {
+                    add(errors, field, "does not exist");
+                }
+            }
+        }
+    }
+
+    fn check_unique(
+        &self,
+        resource: &Resource,
+        record: &Record,
+        skip: Option<usize>,
+        errors: &mut FieldErrors,
+    ) {
+        let Some(first) = resource.unique.first() else {
+            return;
+        };
+        let key = |r: &Record| {
+            resource
+                .unique
+                .iter()
+                .map(|field| r.get(*field).cloned().unwrap_or(Value::Null))
+                .collect::<Vec<_>>()
+        };
+        let taken = self
+            .records
+            .get(resource.collection)
+            .is_some_and(|records| {
+                records
+                    .iter()
+                    .enumerate()
+                    .any(|(i, other)| Some(i) != skip && key(other) == key(record))
+            });
+        if taken {
+            add(errors, first, "has already been taken");
+        }
+    }
+
+    fn find(&self, resource: &Resource, prn: &str) -> Option<usize> {
+        self.records
+            .get(resource.collection)?
+            .iter()
+            .position(|record| record["prn"] == prn)
+    }
+
+    fn collection(&mut self, resource: &Resource) -> &mut Vec<Record> {
+        self.records.entry(resource.collection).or_default()
+    }
+
+    fn next_uuid(&mut self) -> String {
+        let id = uuid(self.next_id);
+        self.next_id += 1;
+        id
+    }
+
+    fn next_secret(&mut self) -> String {
+        format!("whsec_{}", self.next_uuid().replace('-', ""))
+    }
+}
+
+fn resource(collection: &str) -> &'static Resource {
+    RESOURCES
+        .iter()
+        .find(|resource| resource.collection == collection)
+        .expect("resource is defined")
+}
+
+/// Turns the create body of a bundle into its V1 or V2 record.
+fn bundle_record(record: &mut Record) {
+    match record.remove("artifact_version_prns") {
+        Some(artifact_versions @ Value::Array(_)) => {
+            record.remove("binaries");
+            record.insert("artifact_versions".to_string(), artifact_versions);
+        }
+        _ => {
+            let binaries: Vec<Value> = match record.get("binaries") {
+                Some(Value::Array(binaries)) => binaries
+                    .iter()
+                    .map(|binary| {
+                        json!({
+                            "prn": binary["prn"],
+                            "custom_metadata": binary.get("custom_metadata").cloned().unwrap_or(Value::Null),
+                        })
+                    })
+                    .collect(),
+                _ => Vec::new(),
+            };
+            let mut hasher = Sha256::new();
+            for binary in &binaries {
+                hasher.update(binary["prn"].as_str().unwrap_or_default());
+            }
+            record.insert("binaries".to_string(), Value::Array(binaries));
+            record.insert(
+                "hash".to_string(),
+                json!(format!("{:x}", hasher.finalize())),
+            );
+        }
+    }
+}
+
+/// Sets `schedule_complete` of a release from its schedule date as of now.
+fn schedule(record: &mut Record) {
+    let complete = match record.get("schedule_date") {
+        Some(Value::String(schedule_date)) => DateTime::parse_from_rfc3339(schedule_date)
+            .is_ok_and(|schedule_date| schedule_date <= Utc::now()),
+        _ => false,
+    };
+    record.insert("schedule_complete".to_string(), json!(complete));
+}
+
+/// A version 4 shaped UUID numbered `n`, so PRNs are predictable in tests.
+fn uuid(n: u64) -> String {
+    format!("00000000-0000-4000-8000-{n:012x}")
+}
+
+fn now() -> String {
+    Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+fn in_seconds(seconds: i64) -> String {
+    (Utc::now() + Duration::seconds(seconds)).to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+fn insert_validity(
+    body: &mut Record,
+    serial: &str,
+    not_before: DateTime<Utc>,
+    not_after: DateTime<Utc>,
+) {
+    body.insert("serial".to_string(), json!(serial));
+    for (field, at) in [("not_before", not_before), ("not_after", not_after)] {
+        body.insert(
+            field.to_string(),
+            json!(at.to_rfc3339_opts(SecondsFormat::Secs, true)),
+        );
+    }
+}
+
+fn add(errors: &mut FieldErrors, field: &str, message: &str) {
+    errors
+        .entry(field.to_string())
+        .or_default()
+        .push(message.to_string());
+}
+
+fn default(record: &mut Record, field: &str, value: Value) {
+    if record.get(field).is_none_or(Value::is_null) {
+        record.insert(field.to_string(), value);
+    }
+}
+
+fn is_blank(value: Option<&Value>) -> bool {
+    match value {
+        None | Some(Value::Null) => true,
+        Some(Value::String(s)) => s.trim().is_empty(),
+        Some(_) => false,
+    }
+}
+
+fn parse_body(body: &[u8]) -> Result<Record, Response> {
+    match serde_json::from_slice(body) {
+        Ok(Value::Object(record)) => Ok(record),
+        _ => Err(Response::detail(
+            400,
+            "The request body must be a JSON object",
+        )),
+    }
+}
+
+fn check_transition(from: &str, to: &Value, record: &Record, errors: &mut FieldErrors) {
+    let (Ok(from), Some(Ok(to))) = (
+        BinaryState::from_str(from),
+        to.as_str().map(BinaryState::from_str),
+    ) else {
+        add(errors, "state", "is invalid");
+        return;
+    };
+    if from == to {
+        return;
+    }
+    if !from.can_transition_to(&to) {
+        add(
+            errors,
+            "state",
+            &format!("cannot transition from {from} to {to}"),
+        );
+        return;
+    }
+    for field in to.required_fields() {
+        if is_blank(record.get(*field)) {
+            add(errors, field, &format!("can't be blank when {to}"));
+        }
+    }
+}
+
+/// Parses `field:'value'` terms joined with `and`, where `\` escapes the next
+/// character of a value.
+fn parse_search(search: &str) -> Result<Vec<(String, String)>, String> {
+    let mut terms = Vec::new();
+    let mut rest = search.trim();
+    while !rest.is_empty() {
+        let (field, after) = rest
+            .split_once(":'")
+            .ok_or_else(|| format!("expected field:'value' at {rest:?}"))?;
+        let mut value = String::new();
+        let mut chars = after.char_indices();
+        let end = loop {
+            match chars.next() {
+                Some((_, '\\')) => match chars.next() {
+                    Some((_, c)) => value.push(c),
+                    None => return Err("unterminated value".to_string()),
+                },
+                Some((i, '\'')) => break i,
+                Some((_, c)) => value.push(c),
+                None => return Err("unterminated value".to_string()),
+            }
+        };
+        terms.push((field.trim().to_string(), value));
+
+        rest = after[end + 1..].trim_start();
+        if !rest.is_empty() {
+            rest = rest
+                .strip_prefix("and ")
+                .ok_or_else(|| format!("expected and at {rest:?}"))?
+                .trim_start();
+        }
+    }
+    Ok(terms)
+}
+
+fn matches(value: Option<&Value>, expected: &str) -> bool {
+    match value {
+        Some(Value::String(s)) => s == expected,
+        Some(Value::Array(items)) => items.iter().any(|item| item.as_str() == Some(expected)),
+        Some(Value::Null) | None => false,
+        Some(other) => serde_json::from_str::<Value>(expected).is_ok_and(|v| v == *other),
+    }
+}
+
+fn compare(a: Option<&Value>, b: Option<&Value>) -> std::cmp::Ordering {
+    match (a, b) {
+        (Some(Value::Number(a)), Some(Value::Number(b))) => a
+            .as_f64()
+            .partial_cmp(&b.as_f64())
+            .unwrap_or(std::cmp::Ordering::Equal),
+        (Some(Value::String(a)), Some(Value::String(b))) => a.cmp(b),
+        (a, b) => a
+            .is_some_and(|a| !a.is_null())
+            .cmp(&b.is_some_and(|b| !b.is_null())),
+    }
+}
+
+fn encode_page(offset: usize) -> String {
+    URL_SAFE_NO_PAD.encode(format!("offset:{offset}"))
+}
+
+fn decode_page(page: &str) -> Option<usize> {
+    let decoded = URL_SAFE_NO_PAD.decode(page).ok()?;
+    String::from_utf8(decoded)
+        .ok()?
+        .strip_prefix("offset:")?
+        .parse()
+        .ok()
+}
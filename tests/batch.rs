@@ -0,0 +1,262 @@
+mod common;
+
+use common::API_KEY;
+use mockito::{Matcher, Server};
+use serde_json::json;
+
+use peridio_sdk::api::batch::{
+    BatchExecutor, BatchOperation, Error, FailurePolicy, Operation, OperationStatus,
+};
+use peridio_sdk::api::binary_signatures::{
+    BinarySignaturesCommand, Command, CreateBinarySignatureParams, CreateCommand,
+};
+use peridio_sdk::api::devices::{CreateDeviceParams, DeleteDeviceParams, UpdateDeviceParams};
+use peridio_sdk::api::Api;
+use peridio_sdk::api::ApiOptions;
+
+fn create_device(identifier: &str) -> Operation {
+    Operation::CreateDevice(CreateDeviceParams {
+        product_prn: "product_prn".to_string(),
+        description: None,
+        quarantined: None,
+        identifier: identifier.to_string(),
+        tags: None,
+        target: None,
+        cohort_prn: None,
+    })
+}
+
+fn update_device(prn: &str) -> Operation {
+    Operation::UpdateDevice(UpdateDeviceParams {
+        product_prn: None,
+        cohort_prn: None,
+        prn: prn.to_string(),
+        description: Some("updated".to_string()),
+        quarantined: None,
+        tags: None,
+        target: None,
+    })
+}
+
+fn delete_device(prn: &str) -> Operation {
+    Operation::DeleteDevice(DeleteDeviceParams {
+        prn: prn.to_string(),
+    })
+}
+
+#[tokio::test]
+async fn batch_runs_dependencies_and_continues_past_failures() {
+    let mut server = Server::new_async().await;
+
+    let api = Api::new(ApiOptions {
+        api_key: API_KEY.into(),
+        endpoint: Some(server.url()),
+        ca_bundle_path: None,
+        api_version: 1,
+    });
+
+    let create = server
+        .mock("POST", "/devices")
+        .with_status(201)
+        .with_header("content-type", "application/json")
+        .with_body_from_file("tests/fixtures/devices-create-201.json")
+        .expect(1)
+        .create_async()
+        .await;
+    let update = server
+        .mock("PATCH", "/devices/device_prn")
+        .match_body(Matcher::PartialJson(json!({ "description": "updated" })))
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body_from_file("tests/fixtures/devices-update-200.json")
+        .expect(1)
+        .create_async()
+        .await;
+    let missing = server
+        .mock("DELETE", "/devices/missing_prn")
+        .with_status(404)
+        .with_header("content-type", "application/json")
+        .with_body(json!({ "message": "not found" }).to_string())
+        .expect(1)
+        .create_async()
+        .await;
+    let signature = server
+        .mock("POST", "/binary_signatures")
+        .with_status(201)
+        .with_header("content-type", "application/json")
+        .with_body_from_file("tests/fixtures/binary-signatures-create-201.json")
+        .expect(1)
+        .create_async()
+        .await;
+
+    let sign = Operation::BinarySignature(BinarySignaturesCommand::Create(Box::new(Command {
+        inner: CreateCommand {
+            params: CreateBinarySignatureParams {
+                binary_prn: "prn:1:o:abcd:b:binary-123".to_string(),
+                signing_key_prn: Some("prn:1:o:abcd:sk:signing-key-123".to_string()),
+                signature: "signature-abc123".to_string(),
+                signing_key_keyid: None,
+            },
+        },
+        metadata: Some("release 1.2.0".to_string()),
+    })));
+
+    // Listed before what it depends on, so it has to wait.
+    let operations = vec![
+        BatchOperation::new("update", update_device("device_prn")).after("create"),
+        BatchOperation::new("create", create_device("sn1234")),
+        BatchOperation::new("delete", delete_device("missing_prn")),
+        BatchOperation::new("recreate", create_device("sn5678")).after("delete"),
+        BatchOperation::new("skipped", update_device("other_prn")).after("recreate"),
+        BatchOperation::new("sign", sign),
+    ];
+
+    let report = BatchExecutor::new()
+        .concurrency(2)
+        .run(&api, operations)
+        .await
+        .unwrap();
+
+    let statuses: Vec<_> = report
+        .results
+        .iter()
+        .map(|r| (r.id.as_str(), r.operation.as_str(), r.status))
+        .collect();
+    assert_eq!(
+        statuses,
+        vec![
+            ("update", "device.update", OperationStatus::Succeeded),
+            ("create", "device.create", OperationStatus::Succeeded),
+            ("delete", "device.delete", OperationStatus::Failed),
+            ("recreate", "device.create", OperationStatus::Skipped),
+            ("skipped", "device.update", OperationStatus::Skipped),
+            (
+                "sign",
+                "binary_signature.create",
+                OperationStatus::Succeeded
+            ),
+        ]
+    );
+    assert_eq!(
+        report.get("create").unwrap().response.as_ref().unwrap()["device"]["identifier"],
+        "sn1234"
+    );
+    assert!(report
+        .get("recreate")
+        .unwrap()
+        .error
+        .as_deref()
+        .unwrap()
+        .contains("delete"));
+    assert_eq!(
+        report.get("sign").unwrap().metadata.as_deref(),
+        Some("release 1.2.0")
+    );
+    assert_eq!(report.count(OperationStatus::Skipped), 2);
+    assert!(!report.is_success());
+
+    let json: serde_json::Value = serde_json::from_str(&report.to_json().unwrap()).unwrap();
+    assert_eq!(json["results"][2]["status"], "failed");
+
+    create.assert_async().await;
+    update.assert_async().await;
+    missing.assert_async().await;
+    signature.assert_async().await;
+}
+
+#[tokio::test]
+async fn batch_fail_fast_stops_starting_operations() {
+    let mut server = Server::new_async().await;
+
+    let api = Api::new(ApiOptions {
+        api_key: API_KEY.into(),
+        endpoint: Some(server.url()),
+        ca_bundle_path: None,
+        api_version: 1,
+    });
+
+    let missing = server
+        .mock("DELETE", "/devices/missing_prn")
+        .with_status(404)
+        .with_header("content-type", "application/json")
+        .with_body(json!({ "message": "not found" }).to_string())
+        .expect(1)
+        .create_async()
+        .await;
+    let create = server
+        .mock("POST", "/devices")
+        .expect(0)
+        .create_async()
+        .await;
+
+    let report = BatchExecutor::new()
+        .concurrency(1)
+        .policy(FailurePolicy::FailFast)
+        .run(
+            &api,
+            vec![
+                BatchOperation::new("delete", delete_device("missing_prn")),
+                BatchOperation::new("create", create_device("sn1234")),
+            ],
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(report.results[0].status, OperationStatus::Failed);
+    assert_eq!(report.results[1].status, OperationStatus::Skipped);
+    assert_eq!(
+        report.results[1].error.as_deref(),
+        Some("batch stopped after a failure")
+    );
+
+    missing.assert_async().await;
+    create.assert_async().await;
+}
+
+#[tokio::test]
+async fn batch_rejects_invalid_plans() {
+    let api = Api::new(ApiOptions {
+        api_key: API_KEY.into(),
+        endpoint: Some("http://localhost:1".to_string()),
+        ca_bundle_path: None,
+        api_version: 1,
+    });
+    let executor = BatchExecutor::new();
+
+    assert!(matches!(
+        executor
+            .run(
+                &api,
+                vec![
+                    BatchOperation::new("a", delete_device("a")),
+                    BatchOperation::new("a", delete_device("b")),
+                ],
+            )
+            .await,
+        Err(Error::DuplicateId { .. })
+    ));
+    assert!(matches!(
+        executor
+            .run(
+                &api,
+                vec![BatchOperation::new("a", delete_device("a")).after("b")],
+            )
+            .await,
+        Err(Error::UnknownDependency { .. })
+    ));
+    match executor
+        .run(
+            &api,
+            vec![
+                BatchOperation::new("a", delete_device("a")).after("c"),
+                BatchOperation::new("b", delete_device("b")).after("a"),
+                BatchOperation::new("c", delete_device("c")).after("b"),
+                BatchOperation::new("d", delete_device("d")),
+            ],
+        )
+        .await
+    {
+        Err(Error::DependencyCycle { ids }) => assert_eq!(ids, vec!["a", "b", "c"]),
+        other => panic!("expected a cycle, got {other:?}"),
+    }
+}
@@ -5,12 +5,14 @@ use mockito::Server;
 
 use peridio_sdk::api::binaries::{
     BinaryState, CreateBinaryParams, DeleteBinaryParams, GetBinaryDownloadUrlParams,
-    GetBinaryParams, UpdateBinaryParams,
+    GetBinaryParams, MarkBinaryHashableParams, MarkBinarySignableParams, UpdateBinaryParams,
+    WaitForBinaryStateParams,
 };
 
 use peridio_sdk::api::Api;
 use peridio_sdk::api::ApiOptions;
 use serde_json::json;
+use std::time::Duration;
 
 #[tokio::test]
 async fn create_binary() {
@@ -317,3 +319,232 @@ async fn get_binary_download_url() {
 
     m.assert_async().await;
 }
+
+fn binary_in_state(state: &str) -> String {
+    let fixture = std::fs::read_to_string("tests/fixtures/binaries-get-200.json").unwrap();
+    fixture.replace("\"uploadable\"", &format!("\"{state}\""))
+}
+
+#[tokio::test]
+async fn wait_for_binary_state() {
+    let mut server = Server::new_async().await;
+
+    let api = Api::new(ApiOptions {
+        api_key: API_KEY.into(),
+        endpoint: Some(server.url()),
+        ca_bundle_path: None,
+        api_version: 1,
+    });
+
+    let hashing = server
+        .mock("GET", "/binaries/prn")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(binary_in_state("hashing"))
+        .expect(2)
+        .create_async()
+        .await;
+    let signable = server
+        .mock("GET", "/binaries/prn")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(binary_in_state("signable"))
+        .expect(1)
+        .create_async()
+        .await;
+
+    let binary = api
+        .binaries()
+        .wait_for_state(WaitForBinaryStateParams {
+            prn: "prn".to_string(),
+            target_states: vec![BinaryState::Signable, BinaryState::Signed],
+            timeout: Duration::from_secs(5),
+            poll_interval: Duration::from_millis(1),
+        })
+        .await
+        .unwrap();
+
+    assert_eq!(binary.state, BinaryState::Signable);
+
+    hashing.assert_async().await;
+    signable.assert_async().await;
+}
+
+#[tokio::test]
+async fn wait_for_binary_state_fails() {
+    let mut server = Server::new_async().await;
+
+    let api = Api::new(ApiOptions {
+        api_key: API_KEY.into(),
+        endpoint: Some(server.url()),
+        ca_bundle_path: None,
+        api_version: 1,
+    });
+
+    let destroyed = server
+        .mock("GET", "/binaries/destroyed_prn")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(binary_in_state("destroyed"))
+        .expect(1)
+        .create_async()
+        .await;
+    let hashing = server
+        .mock("GET", "/binaries/hashing_prn")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(binary_in_state("hashing"))
+        .expect_at_least(1)
+        .create_async()
+        .await;
+
+    let params = |prn: &str| WaitForBinaryStateParams {
+        prn: prn.to_string(),
+        target_states: vec![BinaryState::Signed],
+        timeout: Duration::from_millis(20),
+        poll_interval: Duration::from_millis(1),
+    };
+
+    match api.binaries().wait_for_state(params("destroyed_prn")).await {
+        Err(peridio_sdk::api::Error::UnexpectedState { prn, state }) => {
+            assert_eq!(prn, "destroyed_prn");
+            assert_eq!(state, "destroyed");
+        }
+        other => panic!("expected UnexpectedState, got {other:?}"),
+    }
+
+    match api.binaries().wait_for_state(params("hashing_prn")).await {
+        Err(peridio_sdk::api::Error::WaitTimeout { state, .. }) => assert_eq!(state, "hashing"),
+        other => panic!("expected WaitTimeout, got {other:?}"),
+    }
+
+    destroyed.assert_async().await;
+    hashing.assert_async().await;
+}
+
+#[test]
+fn binary_state_transitions() {
+    assert!(BinaryState::Uploadable.can_transition_to(&BinaryState::Hashable));
+    assert!(BinaryState::Hashable.can_transition_to(&BinaryState::Hashing));
+    assert!(BinaryState::Hashing.can_transition_to(&BinaryState::Signable));
+    assert!(BinaryState::Signable.can_transition_to(&BinaryState::Signed));
+    assert!(BinaryState::Signed.can_transition_to(&BinaryState::Destroyed));
+
+    assert!(!BinaryState::Uploadable.can_transition_to(&BinaryState::Signed));
+    assert!(!BinaryState::Signed.can_transition_to(&BinaryState::Uploadable));
+    assert!(!BinaryState::Destroyed.can_transition_to(&BinaryState::Destroyed));
+
+    assert!(BinaryState::Uploadable.required_fields().is_empty());
+    assert_eq!(BinaryState::Signable.required_fields(), &["hash", "size"]);
+}
+
+#[tokio::test]
+async fn mark_binary_hashable() {
+    let mut server = Server::new_async().await;
+
+    let api = Api::new(ApiOptions {
+        api_key: API_KEY.into(),
+        endpoint: Some(server.url()),
+        ca_bundle_path: None,
+        api_version: 1,
+    });
+
+    let get = server
+        .mock("GET", "/binaries/prn")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(
+            binary_in_state("uploadable")
+                .replace("\"hash\": \"hash\"", "\"hash\": null")
+                .replace("\"size\": 10", "\"size\": null"),
+        )
+        .expect(2)
+        .create_async()
+        .await;
+    let update = server
+        .mock("PATCH", "/binaries/prn")
+        .match_body(mockito::Matcher::PartialJson(json!({
+            "state": "hashable",
+            "hash": "hash",
+            "size": 10
+        })))
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(binary_in_state("hashable"))
+        .expect(1)
+        .create_async()
+        .await;
+
+    let missing = api
+        .binaries()
+        .mark_hashable(MarkBinaryHashableParams {
+            prn: "prn".to_string(),
+            hash: Some("hash".to_string()),
+            size: None,
+        })
+        .await;
+    match missing {
+        Err(peridio_sdk::api::Error::MissingTransitionField { field, .. }) => {
+            assert_eq!(field, "size")
+        }
+        other => panic!("expected MissingTransitionField, got {other:?}"),
+    }
+
+    let binary = api
+        .binaries()
+        .mark_hashable(MarkBinaryHashableParams {
+            prn: "prn".to_string(),
+            hash: Some("hash".to_string()),
+            size: Some(10),
+        })
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(binary.binary.state, BinaryState::Hashable);
+
+    get.assert_async().await;
+    update.assert_async().await;
+}
+
+#[tokio::test]
+async fn mark_binary_signable_rejects_invalid_transition() {
+    let mut server = Server::new_async().await;
+
+    let api = Api::new(ApiOptions {
+        api_key: API_KEY.into(),
+        endpoint: Some(server.url()),
+        ca_bundle_path: None,
+        api_version: 1,
+    });
+
+    let get = server
+        .mock("GET", "/binaries/prn")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(binary_in_state("uploadable"))
+        .expect(1)
+        .create_async()
+        .await;
+    let update = server
+        .mock("PATCH", "/binaries/prn")
+        .expect(0)
+        .create_async()
+        .await;
+
+    let result = api
+        .binaries()
+        .mark_signable(MarkBinarySignableParams {
+            prn: "prn".to_string(),
+        })
+        .await;
+    match result {
+        Err(peridio_sdk::api::Error::InvalidStateTransition { from, to, .. }) => {
+            assert_eq!(from, "uploadable");
+            assert_eq!(to, "signable");
+        }
+        other => panic!("expected InvalidStateTransition, got {other:?}"),
+    }
+
+    get.assert_async().await;
+    update.assert_async().await;
+}
@@ -0,0 +1,168 @@
+mod common;
+
+use common::API_KEY;
+use mockito::{Matcher, Server, ServerGuard};
+use serde_json::json;
+
+use peridio_sdk::api::bundles::builder::Error;
+use peridio_sdk::api::bundles::Bundle;
+use peridio_sdk::api::Api;
+use peridio_sdk::api::ApiOptions;
+
+fn api(server: &ServerGuard) -> Api {
+    Api::new(ApiOptions {
+        api_key: API_KEY.into(),
+        endpoint: Some(server.url()),
+        ca_bundle_path: None,
+        api_version: 1,
+    })
+}
+
+async fn mock_lookups(server: &mut ServerGuard) -> Vec<mockito::Mock> {
+    let mut mocks = Vec::new();
+    for (path, fixture) in [
+        ("/artifacts", "tests/fixtures/artifacts-list-200.json"),
+        (
+            "/artifact_versions",
+            "tests/fixtures/artifact-versions-list-200.json",
+        ),
+        ("/binaries", "tests/fixtures/binaries-list-200.json"),
+    ] {
+        mocks.push(
+            server
+                .mock("GET", path)
+                .match_query(Matcher::Any)
+                .with_status(200)
+                .with_header("content-type", "application/json")
+                .with_body_from_file(fixture)
+                .create_async()
+                .await,
+        );
+    }
+    mocks
+}
+
+#[tokio::test]
+async fn build_bundle() {
+    let mut server = Server::new_async().await;
+    let api = api(&server);
+    let lookups = mock_lookups(&mut server).await;
+
+    let m = server
+        .mock("POST", "/bundles")
+        .match_body(Matcher::PartialJson(json!({
+            "name": "v2_bundle",
+            "binaries": [{ "prn": "binary_prn_1", "custom_metadata": { "install": "flash" } }]
+        })))
+        .with_status(201)
+        .with_header("content-type", "application/json")
+        .with_body_from_file("tests/fixtures/bundles-v2-create-201.json")
+        .create_async()
+        .await;
+
+    let built = api
+        .bundles()
+        .builder()
+        .name("v2_bundle")
+        .binary("firmware", "1.0.0", "arm64")
+        .create()
+        .await
+        .unwrap();
+
+    assert!(matches!(built.bundle, Some(Bundle::V2(_))));
+    assert_eq!(built.manifest.len(), 1);
+    let entry = &built.manifest[0];
+    assert_eq!(entry.artifact_prn, "artifact_prn");
+    assert_eq!(entry.artifact_version_prn, "artifact_version_prn");
+    assert_eq!(entry.binary_prn, "binary_prn_1");
+    assert_eq!(entry.size, Some(10));
+
+    for lookup in lookups {
+        lookup.assert_async().await;
+    }
+    m.assert_async().await;
+}
+
+#[tokio::test]
+async fn unsigned_binary_is_rejected() {
+    let mut server = Server::new_async().await;
+    let api = api(&server);
+    let _lookups = mock_lookups(&mut server).await;
+
+    let m = server
+        .mock("POST", "/bundles")
+        .expect(0)
+        .create_async()
+        .await;
+
+    let result = api
+        .bundles()
+        .builder()
+        .binary("firmware", "1.0.0", "arm64")
+        .binary("firmware", "1.0.0", "x86_64")
+        .create()
+        .await;
+
+    match result {
+        Err(Error::BinaryNotSigned { prn, .. }) => assert_eq!(prn, "binary_prn_2"),
+        other => panic!("expected BinaryNotSigned, got {other:?}"),
+    }
+
+    m.assert_async().await;
+}
+
+#[tokio::test]
+async fn missing_binary_and_duplicate_targets_are_rejected() {
+    let mut server = Server::new_async().await;
+    let api = api(&server);
+    let _lookups = mock_lookups(&mut server).await;
+
+    let result = api
+        .bundles()
+        .builder()
+        .binary("firmware", "1.0.0", "riscv64")
+        .resolve()
+        .await;
+    assert!(matches!(result, Err(Error::BinaryNotFound { .. })));
+
+    let result = api
+        .bundles()
+        .builder()
+        .binary("firmware", "2.0.0", "arm64")
+        .resolve()
+        .await;
+    assert!(matches!(result, Err(Error::ArtifactVersionNotFound { .. })));
+
+    let result = api
+        .bundles()
+        .builder()
+        .binary("firmware", "1.0.0", "arm64")
+        .binary("firmware", "1.0.0", "arm64")
+        .resolve()
+        .await;
+    assert!(matches!(result, Err(Error::DuplicateTarget { .. })));
+
+    // Targets must be unique across the bundle, not only per artifact.
+    let result = api
+        .bundles()
+        .builder()
+        .binary("firmware", "1.0.0", "arm64")
+        .binary("bootloader", "1.0.0", "x86_64")
+        .binary("config", "1.0.0", "arm64")
+        .resolve()
+        .await;
+    match result {
+        Err(Error::DuplicateTarget {
+            target,
+            first,
+            second,
+        }) => assert_eq!(
+            (target.as_str(), first.as_str(), second.as_str()),
+            ("arm64", "firmware", "config")
+        ),
+        other => panic!("expected DuplicateTarget, got {other:?}"),
+    }
+
+    let result = api.bundles().builder().resolve().await;
+    assert!(matches!(result, Err(Error::Empty)));
+}
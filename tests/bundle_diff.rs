@@ -0,0 +1,271 @@
+mod common;
+
+use std::collections::BTreeSet;
+
+use common::API_KEY;
+use mockito::{Server, ServerGuard};
+use serde_json::{json, Value};
+
+use peridio_sdk::api::bundles;
+use peridio_sdk::api::bundles::diff::{BundleDiff, FieldChange, ResolvedBinary};
+use peridio_sdk::api::Api;
+use peridio_sdk::api::ApiOptions;
+
+fn resolved(artifact: &str, target: &str, version: &str, hash: &str) -> ResolvedBinary {
+    ResolvedBinary {
+        artifact_prn: format!("{artifact}_prn"),
+        artifact_name: artifact.to_string(),
+        artifact_version_prn: format!("{artifact}_{version}_prn"),
+        version: version.to_string(),
+        target: target.to_string(),
+        binary_prn: format!("{artifact}_{version}_{target}_prn"),
+        hash: Some(hash.to_string()),
+        size: Some(10),
+        custom_metadata: None,
+        signing_key_prns: BTreeSet::from(["signing_key_prn".to_string()]),
+    }
+}
+
+#[test]
+fn diff_between_resolved_bundles() {
+    let mut metadata_changed = resolved("rootfs", "arm64", "1.0.0", "h1");
+    metadata_changed.custom_metadata = json!({ "install": "flash", "slot": "a" })
+        .as_object()
+        .cloned();
+    let from = vec![
+        resolved("firmware", "arm64", "1.0.0", "h1"),
+        resolved("firmware", "x86_64", "1.0.0", "h2"),
+        metadata_changed,
+        resolved("bootloader", "arm64", "1.0.0", "h3"),
+    ];
+
+    let mut upgraded = resolved("firmware", "arm64", "1.1.0", "h4");
+    upgraded.size = Some(20);
+    upgraded.signing_key_prns = BTreeSet::from(["other_key_prn".to_string()]);
+    let mut metadata_changed = resolved("rootfs", "arm64", "1.0.0", "h1");
+    metadata_changed.custom_metadata = json!({ "install": "copy", "reboot": true })
+        .as_object()
+        .cloned();
+    let to = vec![
+        upgraded,
+        metadata_changed,
+        resolved("bootloader", "arm64", "1.0.0", "h3"),
+        resolved("config", "arm64", "2.0.0", "h5"),
+    ];
+
+    let diff = BundleDiff::between("a", from, "b", to);
+
+    assert_eq!(diff.added.len(), 1);
+    assert_eq!(diff.added[0].artifact_name, "config");
+    assert_eq!(diff.removed.len(), 1);
+    assert_eq!(diff.removed[0].target, "x86_64");
+    assert_eq!(diff.unchanged.len(), 1);
+    assert_eq!(diff.changed.len(), 2);
+
+    let firmware = diff
+        .changed
+        .iter()
+        .find(|c| c.artifact_name == "firmware")
+        .unwrap();
+    assert!(firmware.changes.contains(&FieldChange::Version {
+        from: "1.0.0".to_string(),
+        to: "1.1.0".to_string()
+    }));
+    assert!(firmware.changes.contains(&FieldChange::Size {
+        from: Some(10),
+        to: Some(20)
+    }));
+    assert!(firmware.changes.contains(&FieldChange::Signatures {
+        added: BTreeSet::from(["other_key_prn".to_string()]),
+        removed: BTreeSet::from(["signing_key_prn".to_string()]),
+    }));
+
+    let rootfs = diff
+        .changed
+        .iter()
+        .find(|c| c.artifact_name == "rootfs")
+        .unwrap();
+    match &rootfs.changes[..] {
+        [FieldChange::CustomMetadata {
+            added,
+            removed,
+            changed,
+        }] => {
+            assert_eq!(added.get("reboot"), Some(&Value::Bool(true)));
+            assert!(removed.contains_key("slot"));
+            assert_eq!(
+                changed.get("install"),
+                Some(&(json!("flash"), json!("copy")))
+            );
+        }
+        other => panic!("unexpected changes {other:?}"),
+    }
+
+    let text = diff.render_text();
+    assert!(text.contains("+ config 2.0.0 (arm64)"));
+    assert!(text.contains("- firmware 1.0.0 (x86_64)"));
+    assert!(text.contains("version: 1.0.0 -> 1.1.0"));
+
+    let markdown = diff.render_markdown();
+    assert!(markdown.contains("| added | config | arm64 | 2.0.0 |"));
+    assert!(markdown.contains("| changed | firmware | arm64 |"));
+
+    let unchanged = BundleDiff::between("a", vec![], "b", vec![]);
+    assert!(unchanged.is_empty());
+    assert!(unchanged.render_text().contains("no changes"));
+}
+
+async fn mock_get(server: &mut ServerGuard, path: &str, body: Value) -> mockito::Mock {
+    server
+        .mock("GET", path)
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(body.to_string())
+        .create_async()
+        .await
+}
+
+fn bundle(prn: &str, binaries: &[&str]) -> Value {
+    json!({
+        "bundle": {
+            "binaries": binaries
+                .iter()
+                .map(|prn| json!({ "prn": prn, "custom_metadata": null }))
+                .collect::<Vec<_>>(),
+            "organization_prn": "organization_prn",
+            "prn": prn,
+            "inserted_at": "2000-01-01T00:00:00Z",
+            "updated_at": "2000-01-01T00:00:00Z",
+            "name": null,
+            "hash": "hash"
+        }
+    })
+}
+
+fn binary(prn: &str, artifact_version_prn: &str, hash: &str) -> Value {
+    json!({
+        "binary": {
+            "artifact_version_prn": artifact_version_prn,
+            "custom_metadata": null,
+            "description": null,
+            "hash": hash,
+            "organization_prn": "organization_prn",
+            "prn": prn,
+            "inserted_at": "2000-01-01T00:00:00Z",
+            "revision": 0,
+            "signatures": [{
+                "signature": "signature",
+                "signing_key_prn": "signing_key_prn",
+                "keyid": "keyid"
+            }],
+            "size": 10,
+            "state": "signed",
+            "target": "arm64",
+            "updated_at": "2000-01-01T00:00:00Z"
+        }
+    })
+}
+
+fn artifact_version(prn: &str, version: &str) -> Value {
+    json!({
+        "artifact_version": {
+            "artifact_prn": "artifact_prn",
+            "custom_metadata": null,
+            "description": null,
+            "inserted_at": "2000-01-01T00:00:00Z",
+            "organization_prn": "organization_prn",
+            "prn": prn,
+            "version": version,
+            "updated_at": "2000-01-01T00:00:00Z"
+        }
+    })
+}
+
+#[tokio::test]
+async fn diff_two_bundles() {
+    let mut server = Server::new_async().await;
+    let api = Api::new(ApiOptions {
+        api_key: API_KEY.into(),
+        endpoint: Some(server.url()),
+        ca_bundle_path: None,
+        api_version: 1,
+    });
+
+    let mocks = vec![
+        mock_get(
+            &mut server,
+            "/bundles/bundle_a",
+            bundle("bundle_a", &["binary_1"]),
+        )
+        .await,
+        mock_get(
+            &mut server,
+            "/bundles/bundle_b",
+            bundle("bundle_b", &["binary_2"]),
+        )
+        .await,
+        mock_get(
+            &mut server,
+            "/binaries/binary_1",
+            binary("binary_1", "av_1", "h1"),
+        )
+        .await,
+        mock_get(
+            &mut server,
+            "/binaries/binary_2",
+            binary("binary_2", "av_2", "h2"),
+        )
+        .await,
+        mock_get(
+            &mut server,
+            "/artifact_versions/av_1",
+            artifact_version("av_1", "1.0.0"),
+        )
+        .await,
+        mock_get(
+            &mut server,
+            "/artifact_versions/av_2",
+            artifact_version("av_2", "1.1.0"),
+        )
+        .await,
+    ];
+    let artifact = server
+        .mock("GET", "/artifacts/artifact_prn")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body_from_file("tests/fixtures/artifacts-get-200.json")
+        .expect(1)
+        .create_async()
+        .await;
+
+    let diff = bundles::diff(&api, "bundle_a", "bundle_b").await.unwrap();
+
+    assert!(diff.added.is_empty());
+    assert!(diff.removed.is_empty());
+    assert_eq!(diff.changed.len(), 1);
+    let change = &diff.changed[0];
+    assert_eq!(change.artifact_name, "a");
+    assert_eq!(change.target, "arm64");
+    assert_eq!(
+        change.changes,
+        vec![
+            FieldChange::Version {
+                from: "1.0.0".to_string(),
+                to: "1.1.0".to_string()
+            },
+            FieldChange::Binary {
+                from: "binary_1".to_string(),
+                to: "binary_2".to_string()
+            },
+            FieldChange::Hash {
+                from: Some("h1".to_string()),
+                to: Some("h2".to_string())
+            },
+        ]
+    );
+
+    for m in mocks {
+        m.assert_async().await;
+    }
+    artifact.assert_async().await;
+}
@@ -0,0 +1,274 @@
+mod common;
+
+use common::API_KEY;
+use mockito::{Matcher, Server, ServerGuard};
+use serde_json::json;
+
+use peridio_sdk::api::bundles::migration::{BundleMigrator, MigrationOptions};
+use peridio_sdk::api::bundles::BundleV1;
+use peridio_sdk::api::Api;
+use peridio_sdk::api::ApiOptions;
+
+const V1_BUNDLE_PRN: &str =
+    "prn:1:099195e1-6810-46c1-9195-8c34f57744e9:bundle:7217e151-2cbe-4a6a-b763-52f7c8302707";
+const OVERRIDE_PRN: &str =
+    "prn:1:099195e1-6810-46c1-9195-8c34f57744e9:bundle_override:61a5518b-7afb-4707-a611-b1a5c75904dc";
+
+fn api(server: &ServerGuard) -> Api {
+    Api::new(ApiOptions {
+        api_key: API_KEY.into(),
+        endpoint: Some(server.url()),
+        ca_bundle_path: None,
+        api_version: 1,
+    })
+}
+
+async fn mock_list(server: &mut ServerGuard, path: &str, fixture: &str) -> mockito::Mock {
+    server
+        .mock("GET", path)
+        .match_query(Matcher::Any)
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body_from_file(fixture)
+        .create_async()
+        .await
+}
+
+#[tokio::test]
+async fn migrate_all_v1_bundles() {
+    let mut server = Server::new_async().await;
+    let api = api(&server);
+
+    let bundles = mock_list(
+        &mut server,
+        "/bundles",
+        "tests/fixtures/bundles-list-200.json",
+    )
+    .await;
+    let binaries = mock_list(
+        &mut server,
+        "/binaries",
+        "tests/fixtures/binaries-list-200.json",
+    )
+    .await;
+    let releases = mock_list(
+        &mut server,
+        "/releases",
+        "tests/fixtures/releases-list-200.json",
+    )
+    .await;
+    let overrides = mock_list(
+        &mut server,
+        "/bundle_overrides",
+        "tests/fixtures/bundle-overrides-list-200.json",
+    )
+    .await;
+
+    // Only the signed arm64 binary is carried over.
+    let create = server
+        .mock("POST", "/bundles")
+        .match_body(Matcher::Json(json!({
+            "binaries": [{ "prn": "binary_prn_1", "custom_metadata": { "install": "flash" } }],
+            "name": "v1_bundle"
+        })))
+        .with_status(201)
+        .with_header("content-type", "application/json")
+        .with_body_from_file("tests/fixtures/bundles-v2-create-201.json")
+        .expect(1)
+        .create_async()
+        .await;
+
+    let repoint_release = server
+        .mock("PATCH", "/releases/release_prn_1")
+        .match_body(Matcher::Json(
+            json!({ "prn": "release_prn_1", "bundle_prn": "prn" }),
+        ))
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body_from_file("tests/fixtures/releases-update-200.json")
+        .expect(1)
+        .create_async()
+        .await;
+
+    let repoint_override = server
+        .mock("PATCH", &*format!("/bundle_overrides/{OVERRIDE_PRN}"))
+        .match_body(Matcher::PartialJson(json!({ "bundle_prn": "prn" })))
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body_from_file("tests/fixtures/bundle-overrides-update-200.json")
+        .expect(1)
+        .create_async()
+        .await;
+
+    let mut migrator = BundleMigrator::new(
+        &api,
+        MigrationOptions {
+            targets: None,
+            repoint_releases: true,
+            repoint_bundle_overrides: true,
+        },
+    );
+    let report = migrator.migrate_all().await.unwrap();
+
+    assert_eq!(report.bundles.len(), 1);
+    let migration = &report.bundles[0];
+    assert_eq!(migration.error, None);
+    assert_eq!(migration.v1_bundle_prn, V1_BUNDLE_PRN);
+    assert_eq!(migration.v2_bundle_prn.as_deref(), Some("prn"));
+    assert!(!migration.reused);
+    assert_eq!(migration.binaries.len(), 1);
+    assert_eq!(migration.binaries[0].target, "arm64");
+    assert_eq!(migration.repointed_releases, vec!["release_prn_1"]);
+    assert_eq!(migration.repointed_bundle_overrides, vec![OVERRIDE_PRN]);
+    assert_eq!(
+        report.mapping().get(V1_BUNDLE_PRN).map(String::as_str),
+        Some("prn")
+    );
+
+    for m in [
+        bundles,
+        binaries,
+        releases,
+        overrides,
+        create,
+        repoint_release,
+        repoint_override,
+    ] {
+        m.assert_async().await;
+    }
+}
+
+#[tokio::test]
+async fn rerun_reuses_migrated_bundle() {
+    let mut server = Server::new_async().await;
+    let api = api(&server);
+
+    // A previous run created the V2 bundle but failed before repointing.
+    let bundles = server
+        .mock("GET", "/bundles")
+        .match_query(Matcher::Any)
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(
+            json!({
+                "bundles": [
+                    {
+                        "artifact_versions": ["artifact_version_prn"],
+                        "organization_prn": "organization_prn",
+                        "prn": V1_BUNDLE_PRN,
+                        "inserted_at": "2000-01-01T00:00:00Z",
+                        "updated_at": "2000-01-01T00:00:00Z",
+                        "name": "v1_bundle"
+                    },
+                    {
+                        "binaries": [{ "prn": "binary_prn_1", "custom_metadata": null }],
+                        "organization_prn": "organization_prn",
+                        "prn": "migrated_prn",
+                        "inserted_at": "2000-01-02T00:00:00Z",
+                        "updated_at": "2000-01-02T00:00:00Z",
+                        "name": "v1_bundle",
+                        "hash": "sha256:1234567890abcdef"
+                    }
+                ],
+                "next_page": null
+            })
+            .to_string(),
+        )
+        .expect(1)
+        .create_async()
+        .await;
+    let binaries = mock_list(
+        &mut server,
+        "/binaries",
+        "tests/fixtures/binaries-list-200.json",
+    )
+    .await;
+    let releases = mock_list(
+        &mut server,
+        "/releases",
+        "tests/fixtures/releases-list-200.json",
+    )
+    .await;
+    let create = server
+        .mock("POST", "/bundles")
+        .expect(0)
+        .create_async()
+        .await;
+    let repoint_release = server
+        .mock("PATCH", "/releases/release_prn_1")
+        .match_body(Matcher::Json(
+            json!({ "prn": "release_prn_1", "bundle_prn": "migrated_prn" }),
+        ))
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body_from_file("tests/fixtures/releases-update-200.json")
+        .expect(1)
+        .create_async()
+        .await;
+
+    let mut migrator = BundleMigrator::new(
+        &api,
+        MigrationOptions {
+            repoint_releases: true,
+            ..Default::default()
+        },
+    );
+    let report = migrator.migrate_all().await.unwrap();
+
+    assert_eq!(report.bundles.len(), 1);
+    let migration = &report.bundles[0];
+    assert_eq!(migration.error, None);
+    assert!(migration.reused);
+    assert_eq!(migration.v2_bundle_prn.as_deref(), Some("migrated_prn"));
+    assert_eq!(migration.repointed_releases, vec!["release_prn_1"]);
+
+    for m in [bundles, binaries, releases, create, repoint_release] {
+        m.assert_async().await;
+    }
+}
+
+#[tokio::test]
+async fn failures_are_reported_per_bundle() {
+    let mut server = Server::new_async().await;
+    let api = api(&server);
+
+    let _binaries = mock_list(
+        &mut server,
+        "/binaries",
+        "tests/fixtures/binaries-list-200.json",
+    )
+    .await;
+    let create = server
+        .mock("POST", "/bundles")
+        .expect(0)
+        .create_async()
+        .await;
+
+    let bundle: BundleV1 = serde_json::from_value(json!({
+        "artifact_versions": ["artifact_version_prn"],
+        "organization_prn": "organization_prn",
+        "prn": "bundle_prn",
+        "inserted_at": "2000-01-01T00:00:00Z",
+        "updated_at": "2000-01-01T00:00:00Z",
+        "name": null
+    }))
+    .unwrap();
+
+    let mut migrator = BundleMigrator::new(
+        &api,
+        MigrationOptions {
+            targets: Some(vec!["riscv64".to_string()]),
+            ..Default::default()
+        },
+    );
+    let report = migrator.migrate(&[bundle]).await;
+
+    assert_eq!(report.failures().count(), 1);
+    assert!(report.bundles[0]
+        .error
+        .as_deref()
+        .unwrap()
+        .contains("no signed binaries"));
+    assert!(report.mapping().is_empty());
+    create.assert_async().await;
+}
@@ -0,0 +1,184 @@
+mod common;
+
+use common::API_KEY;
+use mockito::Server;
+use serde_json::json;
+
+use peridio_sdk::api::cassette::{Cassette, Matching};
+use peridio_sdk::api::products::{CreateProductParams, GetProductParams};
+use peridio_sdk::api::Api;
+use peridio_sdk::api::ApiOptions;
+use peridio_sdk::api::Error;
+
+const PRODUCT_PRN: &str =
+    "prn:1:be4d30b4-de6b-47cd-85ea-a75e23fd63ef:product:b3f1f699-3bc8-4c77-bda2-b974595d5e5f";
+
+fn api(endpoint: String) -> Api {
+    Api::new(ApiOptions {
+        api_key: API_KEY.into(),
+        endpoint: Some(endpoint),
+        ca_bundle_path: None,
+        api_version: 1,
+    })
+}
+
+fn cassette_path(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!(
+        "peridio-cassette-{}-{}.json",
+        name,
+        std::process::id()
+    ))
+}
+
+fn product() -> serde_json::Value {
+    json!({
+        "product": {
+            "archived": false,
+            "inserted_at": "2018-01-01T00:00:00Z",
+            "name": "gateway",
+            "organization_prn": "prn:1:be4d30b4-de6b-47cd-85ea-a75e23fd63ef",
+            "prn": PRODUCT_PRN,
+            "updated_at": "2018-01-01T00:00:00Z"
+        }
+    })
+}
+
+fn create_params() -> CreateProductParams {
+    CreateProductParams {
+        archived: None,
+        name: "gateway".to_string(),
+    }
+}
+
+fn get_params() -> GetProductParams {
+    GetProductParams {
+        prn: PRODUCT_PRN.to_string(),
+    }
+}
+
+async fn record(path: &std::path::Path) {
+    let mut server = Server::new_async().await;
+    let create = server
+        .mock("POST", "/products")
+        .with_status(201)
+        .with_header("content-type", "application/json")
+        .with_body(product().to_string())
+        .create_async()
+        .await;
+    let get = server
+        .mock("GET", &*format!("/products/{PRODUCT_PRN}"))
+        .with_status(404)
+        .with_header("content-type", "application/json")
+        .with_body(json!({ "errors": { "detail": "Not Found" } }).to_string())
+        .create_async()
+        .await;
+
+    let api = api(server.url()).record(path);
+    api.products().create(create_params()).await.unwrap();
+    api.products().get(get_params()).await.unwrap_err();
+    create.assert_async().await;
+    get.assert_async().await;
+}
+
+#[tokio::test]
+async fn record_and_replay() {
+    let path = cassette_path("record");
+    record(&path).await;
+
+    let cassette = Cassette::read(&path).await.unwrap();
+    assert_eq!(cassette.interactions.len(), 2);
+    let create = &cassette.interactions[0];
+    assert_eq!(create.request.method, "POST");
+    assert_eq!(create.request.path, "/products");
+    assert_eq!(create.request.headers["authorization"], "[REDACTED]");
+    assert_eq!(
+        create.request.body.as_deref(),
+        Some(r#"{"name":"gateway"}"#)
+    );
+    assert_eq!(create.response.status, 201);
+    assert!(!std::fs::read_to_string(&path).unwrap().contains(API_KEY));
+
+    // Nothing listens here, so any request reaching the network fails.
+    let api = api("http://127.0.0.1:9".to_string())
+        .replay(&path, Matching::Strict)
+        .await
+        .unwrap();
+    let created = api
+        .products()
+        .create(create_params())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(created.product.prn, PRODUCT_PRN);
+    let error = api.products().get(get_params()).await.unwrap_err();
+    assert!(matches!(error, Error::StructuredError { status: 404, .. }));
+
+    let error = api.products().get(get_params()).await.unwrap_err();
+    assert!(matches!(error, Error::UnmatchedRequest { .. }));
+    assert!(error
+        .to_string()
+        .contains("all 2 recorded interactions were replayed"));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn replay_matching() {
+    let path = cassette_path("matching");
+    record(&path).await;
+
+    let strict = api("http://127.0.0.1:9".to_string())
+        .replay(&path, Matching::Strict)
+        .await
+        .unwrap();
+    let error = strict.products().get(get_params()).await.unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        format!(
+            "No recorded interaction for GET /products/{PRODUCT_PRN}: interaction 0: the next \
+             recorded request is POST /products"
+        )
+    );
+    let error = strict
+        .products()
+        .create(CreateProductParams {
+            archived: Some(true),
+            name: "gateway".to_string(),
+        })
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("the body differs"));
+
+    let lenient = api("http://127.0.0.1:9".to_string())
+        .replay(&path, Matching::Lenient)
+        .await
+        .unwrap();
+    lenient.products().get(get_params()).await.unwrap_err();
+    lenient
+        .products()
+        .create(CreateProductParams {
+            archived: Some(true),
+            name: "gateway".to_string(),
+        })
+        .await
+        .unwrap();
+    let error = lenient
+        .products()
+        .create(create_params())
+        .await
+        .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "No recorded interaction for POST /products: no unused interaction has this method and \
+         path (2 of 2 replayed)"
+    );
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn replay_missing_cassette() {
+    let error = api("http://127.0.0.1:9".to_string())
+        .replay("does-not-exist.json", Matching::Strict)
+        .await
+        .unwrap_err();
+    assert!(matches!(error, Error::CassetteIo { .. }));
+}
@@ -0,0 +1,272 @@
+mod common;
+
+use common::API_KEY;
+use mockito::{Matcher, Server, ServerGuard};
+use serde_json::{json, Value};
+
+use peridio_sdk::api::cohorts::migration::{
+    CohortRollback, DeviceMigrationStatus, DeviceSelector, Error, MigrateDevicesParams,
+};
+use peridio_sdk::api::devices::Device;
+use peridio_sdk::api::Api;
+use peridio_sdk::api::ApiOptions;
+
+fn device(prn: &str, identifier: &str, tags: &[&str], version: Option<&str>) -> Value {
+    json!({
+        "product_prn": "product_prn",
+        "cohort_prn": "from_prn",
+        "description": null,
+        "quarantined": false,
+        "identifier": identifier,
+        "inserted_at": "2019-08-24T14:15:22Z",
+        "last_connected_at": null,
+        "prn": prn,
+        "tags": tags,
+        "target": "arm64",
+        "reported_release_prn": null,
+        "reported_release_version": version,
+        "reported_bundle_prn": null,
+        "updated_at": "2019-08-24T14:15:22Z"
+    })
+}
+
+async fn mock_cohort(server: &mut ServerGuard, prn: &str, product_prn: &str) -> mockito::Mock {
+    server
+        .mock("GET", &*format!("/cohorts/{prn}"))
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(
+            json!({
+                "cohort": {
+                    "description": null,
+                    "name": prn,
+                    "organization_prn": "organization_prn",
+                    "product_prn": product_prn,
+                    "prn": prn,
+                    "inserted_at": "2019-08-24T14:15:22Z",
+                    "updated_at": "2019-08-24T14:15:22Z"
+                }
+            })
+            .to_string(),
+        )
+        .create_async()
+        .await
+}
+
+fn selector() -> DeviceSelector {
+    DeviceSelector {
+        tags: vec!["canary".to_string()],
+        identifier_pattern: Some("sn-*".to_string()),
+        release_version: Some("< 2.0.0".parse().unwrap()),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn select_devices() {
+    let matches = |value: Value, selector: &DeviceSelector| {
+        let device: Device = serde_json::from_value(value).unwrap();
+        selector.matches(&device)
+    };
+
+    assert!(matches(
+        device("1", "sn-0001", &["canary", "lab"], Some("1.4.0")),
+        &selector()
+    ));
+    assert!(!matches(
+        device("2", "sn-0002", &["lab"], Some("1.4.0")),
+        &selector()
+    ));
+    assert!(!matches(
+        device("3", "dev-0003", &["canary"], Some("1.4.0")),
+        &selector()
+    ));
+    assert!(!matches(
+        device("4", "sn-0004", &["canary"], Some("2.1.0")),
+        &selector()
+    ));
+    assert!(!matches(
+        device("5", "sn-0005", &["canary"], None),
+        &selector()
+    ));
+
+    let pattern = |pattern: &str| DeviceSelector {
+        identifier_pattern: Some(pattern.to_string()),
+        ..Default::default()
+    };
+    let value = || device("6", "sn-12ab", &[], None);
+    assert!(matches(value(), &pattern("sn-??ab")));
+    assert!(matches(value(), &pattern("*2*b")));
+    assert!(matches(value(), &pattern("*")));
+    assert!(!matches(value(), &pattern("sn-?ab")));
+    assert!(!matches(value(), &pattern("*a")));
+    assert!(matches(value(), &DeviceSelector::default()));
+    assert!(!matches(
+        value(),
+        &DeviceSelector {
+            target: Some("x86_64".to_string()),
+            ..Default::default()
+        }
+    ));
+}
+
+#[tokio::test]
+async fn migrate_devices_and_roll_back() {
+    let mut server = Server::new_async().await;
+
+    let api = Api::new(ApiOptions {
+        api_key: API_KEY.into(),
+        endpoint: Some(server.url()),
+        ca_bundle_path: None,
+        api_version: 1,
+    });
+
+    mock_cohort(&mut server, "from_prn", "product_prn").await;
+    mock_cohort(&mut server, "to_prn", "product_prn").await;
+    let list = server
+        .mock("GET", "/devices")
+        .match_query(Matcher::UrlEncoded(
+            "search".into(),
+            "cohort_prn:'from_prn'".into(),
+        ))
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(
+            json!({
+                "devices": [
+                    device("d1", "sn-0001", &["canary"], Some("1.0.0")),
+                    device("d2", "sn-0002", &[], Some("1.0.0")),
+                    device("d3", "sn-0003", &["canary"], Some("1.5.0")),
+                ],
+                "next_page": null
+            })
+            .to_string(),
+        )
+        .expect(2)
+        .create_async()
+        .await;
+
+    let mut moves = Vec::new();
+    for prn in ["d1", "d3"] {
+        moves.push(
+            server
+                .mock("PATCH", &*format!("/devices/{prn}"))
+                .match_body(Matcher::Json(json!({ "prn": prn, "cohort_prn": "to_prn" })))
+                .with_status(200)
+                .with_header("content-type", "application/json")
+                .with_body(json!({ "device": device(prn, prn, &[], None) }).to_string())
+                .expect(1)
+                .create_async()
+                .await,
+        );
+    }
+
+    let rollback_path = std::env::temp_dir().join(format!(
+        "peridio-cohort-rollback-{}.json",
+        std::process::id()
+    ));
+    let params = |dry_run: bool| MigrateDevicesParams {
+        from: "from_prn".to_string(),
+        to: "to_prn".to_string(),
+        selector: selector(),
+        dry_run,
+        concurrency: 2,
+        rollback_path: Some(rollback_path.clone()),
+    };
+
+    let plan = api.cohorts().migrate_devices(params(true)).await.unwrap();
+    assert!(plan.dry_run);
+    let planned: Vec<_> = plan
+        .devices
+        .iter()
+        .map(|d| (d.prn.as_str(), d.status))
+        .collect();
+    assert_eq!(
+        planned,
+        vec![
+            ("d1", DeviceMigrationStatus::Planned),
+            ("d3", DeviceMigrationStatus::Planned),
+        ]
+    );
+    assert!(!rollback_path.exists());
+
+    let report = api.cohorts().migrate_devices(params(false)).await.unwrap();
+    assert!(report
+        .devices
+        .iter()
+        .all(|d| d.status == DeviceMigrationStatus::Migrated));
+    assert_eq!(report.failures().count(), 0);
+    for m in &moves {
+        m.assert_async().await;
+    }
+    list.assert_async().await;
+
+    let rollback = CohortRollback::read(&rollback_path).await.unwrap();
+    std::fs::remove_file(&rollback_path).unwrap();
+    assert_eq!(rollback.devices.len(), 2);
+    assert_eq!(rollback.devices[0].cohort_prn, "from_prn");
+
+    let mut restores = Vec::new();
+    for prn in ["d1", "d3"] {
+        restores.push(
+            server
+                .mock("PATCH", &*format!("/devices/{prn}"))
+                .match_body(Matcher::Json(
+                    json!({ "prn": prn, "cohort_prn": "from_prn" }),
+                ))
+                .with_status(200)
+                .with_header("content-type", "application/json")
+                .with_body(json!({ "device": device(prn, prn, &[], None) }).to_string())
+                .expect(1)
+                .create_async()
+                .await,
+        );
+    }
+
+    let restored = api.cohorts().rollback_devices(rollback, 2).await.unwrap();
+    assert_eq!(restored.from, "to_prn");
+    assert_eq!(restored.to, "from_prn");
+    assert_eq!(restored.failures().count(), 0);
+    for r in restores {
+        r.assert_async().await;
+    }
+}
+
+#[tokio::test]
+async fn migrate_devices_requires_same_product() {
+    let mut server = Server::new_async().await;
+
+    let api = Api::new(ApiOptions {
+        api_key: API_KEY.into(),
+        endpoint: Some(server.url()),
+        ca_bundle_path: None,
+        api_version: 1,
+    });
+
+    mock_cohort(&mut server, "from_prn", "product_prn").await;
+    mock_cohort(&mut server, "to_prn", "other_product_prn").await;
+    let list = server
+        .mock("GET", "/devices")
+        .expect(0)
+        .create_async()
+        .await;
+
+    let params = |to: &str| MigrateDevicesParams {
+        from: "from_prn".to_string(),
+        to: to.to_string(),
+        selector: DeviceSelector::default(),
+        dry_run: false,
+        concurrency: 1,
+        rollback_path: None,
+    };
+
+    assert!(matches!(
+        api.cohorts().migrate_devices(params("to_prn")).await,
+        Err(Error::ProductMismatch { .. })
+    ));
+    assert!(matches!(
+        api.cohorts().migrate_devices(params("from_prn")).await,
+        Err(Error::SameCohort { .. })
+    ));
+    list.assert_async().await;
+}
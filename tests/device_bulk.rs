@@ -0,0 +1,352 @@
+mod common;
+
+use common::API_KEY;
+use mockito::{Matcher, Server};
+use serde_json::{json, Value};
+
+use peridio_sdk::api::devices::bulk::{self, DeviceRecord, Format, ImportAction, ImportOptions};
+use peridio_sdk::api::Api;
+use peridio_sdk::api::ApiOptions;
+
+const PRODUCT_PRN: &str =
+    "prn:1:be4d30b4-de6b-47cd-85ea-a75e23fd63ef:product:b3f1f699-3bc8-4c77-bda2-b974595d5e3f";
+const COHORT_PRN: &str =
+    "prn:1:be4d30b4-de6b-47cd-85ea-a75e23fd63ef:cohort:b3f1f699-3bc8-4c77-bda2-b974595d5e3f";
+
+fn device(identifier: &str, prn: &str, tags: &[&str]) -> Value {
+    json!({
+        "product_prn": PRODUCT_PRN,
+        "cohort_prn": COHORT_PRN,
+        "description": null,
+        "quarantined": false,
+        "identifier": identifier,
+        "inserted_at": "2019-08-24T14:15:22Z",
+        "last_connected_at": null,
+        "prn": prn,
+        "tags": tags,
+        "target": null,
+        "reported_release_prn": null,
+        "reported_release_version": null,
+        "reported_bundle_prn": null,
+        "updated_at": "2019-08-24T14:15:22Z"
+    })
+}
+
+fn record(identifier: &str) -> DeviceRecord {
+    DeviceRecord {
+        identifier: identifier.to_string(),
+        product_prn: PRODUCT_PRN.to_string(),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn parse_render_and_validate_rows() {
+    let certificate = std::fs::read_to_string("tests/files/certificates/device.pem").unwrap();
+    let records = vec![
+        DeviceRecord {
+            cohort_prn: Some(COHORT_PRN.to_string()),
+            tags: Some(vec!["a".to_string(), "b".to_string()]),
+            quarantined: Some(true),
+            certificate: Some(certificate),
+            ..record("sn1234")
+        },
+        record("sn5678"),
+    ];
+
+    for format in [Format::Csv, Format::Json] {
+        let rendered = bulk::render(&records, format).unwrap();
+        assert_eq!(bulk::parse(&rendered, format).unwrap(), records);
+    }
+
+    let csv = format!(
+        "identifier,product_prn,cohort_prn,tags,target,description,quarantined,certificate\n\
+         sn1,{PRODUCT_PRN},,x; y,,,false,\n"
+    );
+    let parsed = bulk::parse(&csv, Format::Csv).unwrap();
+    assert_eq!(
+        parsed,
+        vec![DeviceRecord {
+            tags: Some(vec!["x".to_string(), "y".to_string()]),
+            quarantined: Some(false),
+            ..record("sn1")
+        }]
+    );
+    assert!(bulk::parse("identifier\nsn1\n", Format::Csv).is_err());
+    assert_eq!(
+        Format::from_path("devices.CSV".as_ref()).unwrap(),
+        Format::Csv
+    );
+    assert!(Format::from_path("devices.txt".as_ref()).is_err());
+
+    assert!(records[0].validate().is_ok());
+    let invalid = DeviceRecord {
+        identifier: "sn 1".to_string(),
+        product_prn: COHORT_PRN.to_string(),
+        certificate: Some("garbage".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(invalid.validate().unwrap_err().len(), 3);
+}
+
+#[tokio::test]
+async fn import_creates_updates_and_skips() {
+    let mut server = Server::new_async().await;
+
+    let api = Api::new(ApiOptions {
+        api_key: API_KEY.into(),
+        endpoint: Some(server.url()),
+        ca_bundle_path: None,
+        api_version: 1,
+    });
+
+    let list = server
+        .mock("GET", "/devices")
+        .match_query(Matcher::Any)
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(
+            json!({
+                "devices": [
+                    device("sn1234", "device-1", &["tag-1"]),
+                    device("sn2345", "device-2", &[]),
+                ],
+                "next_page": null
+            })
+            .to_string(),
+        )
+        .expect(1)
+        .create_async()
+        .await;
+    let certificates = server
+        .mock("GET", "/device_certificates")
+        .match_query(Matcher::Any)
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(
+            json!({
+                "device_certificates": [{
+                    "not_after": "then",
+                    "not_before": "now",
+                    "prn": "certificate-1",
+                    "serial": "0A:0B"
+                }],
+                "next_page": null
+            })
+            .to_string(),
+        )
+        .expect(1)
+        .create_async()
+        .await;
+    let create = server
+        .mock("POST", "/devices")
+        .match_body(Matcher::PartialJson(json!({ "identifier": "sn5678" })))
+        .with_status(201)
+        .with_header("content-type", "application/json")
+        .with_body(json!({ "device": device("sn5678", "device-3", &[]) }).to_string())
+        .expect(1)
+        .create_async()
+        .await;
+    let update = server
+        .mock("PATCH", "/devices/device-2")
+        .match_body(Matcher::Json(
+            json!({ "prn": "device-2", "tags": ["tag-2"] }),
+        ))
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(json!({ "device": device("sn2345", "device-2", &["tag-2"]) }).to_string())
+        .expect(1)
+        .create_async()
+        .await;
+    let register = server
+        .mock("POST", "/device_certificates")
+        .match_body(Matcher::PartialJson(json!({ "device_prn": "device-3" })))
+        .with_status(201)
+        .with_header("content-type", "application/json")
+        .with_body_from_file("tests/fixtures/device-certificates-create-201.json")
+        .expect(1)
+        .create_async()
+        .await;
+
+    let certificate = std::fs::read_to_string("tests/files/certificates/device.pem").unwrap();
+    let records = vec![
+        DeviceRecord {
+            tags: Some(vec!["tag-1".to_string()]),
+            ..record("sn1234")
+        },
+        DeviceRecord {
+            tags: Some(vec!["tag-2".to_string()]),
+            ..record("sn2345")
+        },
+        DeviceRecord {
+            certificate: Some(certificate),
+            ..record("sn5678")
+        },
+        DeviceRecord {
+            product_prn: "product".to_string(),
+            ..record("sn9999")
+        },
+        record("twice"),
+        record("twice"),
+    ];
+
+    let report = bulk::import(&api, records, ImportOptions { concurrency: 2 })
+        .await
+        .unwrap();
+
+    let actions: Vec<_> = report
+        .results
+        .iter()
+        .map(|r| (r.row, r.action, r.certificate_registered))
+        .collect();
+    assert_eq!(
+        actions,
+        vec![
+            (1, ImportAction::Unchanged, false),
+            (2, ImportAction::Updated, false),
+            (3, ImportAction::Created, true),
+            (4, ImportAction::Invalid, false),
+            (5, ImportAction::Invalid, false),
+            (6, ImportAction::Invalid, false),
+        ]
+    );
+    assert_eq!(report.results[2].device_prn.as_deref(), Some("device-3"));
+    assert_eq!(report.count(ImportAction::Invalid), 3);
+    assert!(!report.is_success());
+    assert!(report
+        .to_table()
+        .contains("identifier appears more than once"));
+
+    list.assert_async().await;
+    certificates.assert_async().await;
+    create.assert_async().await;
+    update.assert_async().await;
+    register.assert_async().await;
+}
+
+#[tokio::test]
+async fn import_rerun_skips_registered_certificates() {
+    let mut server = Server::new_async().await;
+
+    let api = Api::new(ApiOptions {
+        api_key: API_KEY.into(),
+        endpoint: Some(server.url()),
+        ca_bundle_path: None,
+        api_version: 1,
+    });
+
+    // The state left behind by a first run: the device exists and its
+    // certificate is registered. Certificates are listed as the API returns
+    // them, without a device.
+    let list = server
+        .mock("GET", "/devices")
+        .match_query(Matcher::Any)
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(
+            json!({ "devices": [device("sn5678", "device-3", &[])], "next_page": null })
+                .to_string(),
+        )
+        .expect(1)
+        .create_async()
+        .await;
+    let certificates = server
+        .mock("GET", "/device_certificates")
+        .match_query(Matcher::Any)
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(
+            json!({
+                "device_certificates": [{
+                    "not_after": "2999-01-01T00:00:00Z",
+                    "not_before": "2000-01-01T00:00:00Z",
+                    "prn": "certificate-1",
+                    "serial": "1f2e3d"
+                }],
+                "next_page": null
+            })
+            .to_string(),
+        )
+        .expect(1)
+        .create_async()
+        .await;
+    let mut mutations = Vec::new();
+    for (method, path) in [
+        ("POST", "/devices"),
+        ("PATCH", "/devices/device-3"),
+        ("POST", "/device_certificates"),
+    ] {
+        mutations.push(server.mock(method, path).expect(0).create_async().await);
+    }
+
+    let certificate = std::fs::read_to_string("tests/files/certificates/device.pem").unwrap();
+    let records = vec![DeviceRecord {
+        certificate: Some(certificate),
+        ..record("sn5678")
+    }];
+    let report = bulk::import(&api, records, ImportOptions { concurrency: 2 })
+        .await
+        .unwrap();
+
+    assert_eq!(report.results[0].action, ImportAction::Unchanged);
+    assert!(!report.results[0].certificate_registered);
+    assert!(report.is_success());
+
+    list.assert_async().await;
+    certificates.assert_async().await;
+    for mutation in mutations {
+        mutation.assert_async().await;
+    }
+}
+
+#[tokio::test]
+async fn export_pages_through_devices() {
+    let mut server = Server::new_async().await;
+
+    let api = Api::new(ApiOptions {
+        api_key: API_KEY.into(),
+        endpoint: Some(server.url()),
+        ca_bundle_path: None,
+        api_version: 1,
+    });
+
+    let first = server
+        .mock("GET", "/devices")
+        .match_query(Matcher::Missing)
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(
+            json!({ "devices": [device("sn1", "device-1", &["a", "b"])], "next_page": "2" })
+                .to_string(),
+        )
+        .expect(1)
+        .create_async()
+        .await;
+    let second = server
+        .mock("GET", "/devices")
+        .match_query(Matcher::UrlEncoded("page".into(), "2".into()))
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(
+            json!({ "devices": [device("sn2", "device-2", &[])], "next_page": null }).to_string(),
+        )
+        .expect(1)
+        .create_async()
+        .await;
+
+    let records = bulk::export(&api).await.unwrap();
+    assert_eq!(records.len(), 2);
+    assert_eq!(
+        records[0].tags,
+        Some(vec!["a".to_string(), "b".to_string()])
+    );
+    assert_eq!(records[1].cohort_prn.as_deref(), Some(COHORT_PRN));
+
+    let csv = bulk::render(&records, Format::Csv).unwrap();
+    assert!(csv.contains("sn1,"));
+    assert!(csv.contains("a;b"));
+    assert_eq!(bulk::parse(&csv, Format::Csv).unwrap(), records);
+
+    first.assert_async().await;
+    second.assert_async().await;
+}
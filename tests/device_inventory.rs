@@ -0,0 +1,159 @@
+mod common;
+
+use chrono::{TimeZone, Utc};
+use common::API_KEY;
+use mockito::{Matcher, Server};
+use serde_json::{json, Value};
+
+use peridio_sdk::api::devices::inventory::{Dimension, Inventory, InventoryOptions};
+use peridio_sdk::api::Api;
+use peridio_sdk::api::ApiOptions;
+
+fn device(
+    prn: &str,
+    release: Option<(&str, &str)>,
+    bundle_prn: Option<&str>,
+    last_connected_at: Option<&str>,
+    quarantined: bool,
+) -> Value {
+    json!({
+        "product_prn": "product_prn",
+        "cohort_prn": "cohort_prn",
+        "description": null,
+        "quarantined": quarantined,
+        "identifier": prn,
+        "inserted_at": "2019-08-24T14:15:22Z",
+        "last_connected_at": last_connected_at,
+        "prn": prn,
+        "tags": null,
+        "target": "arm64",
+        "reported_release_prn": release.map(|(prn, _)| prn),
+        "reported_release_version": release.map(|(_, version)| version),
+        "reported_bundle_prn": bundle_prn,
+        "updated_at": "2019-08-24T14:15:22Z"
+    })
+}
+
+#[tokio::test]
+async fn fleet_inventory() {
+    let mut server = Server::new_async().await;
+
+    let api = Api::new(ApiOptions {
+        api_key: API_KEY.into(),
+        endpoint: Some(server.url()),
+        ca_bundle_path: None,
+        api_version: 1,
+    });
+
+    let list = server
+        .mock("GET", "/devices")
+        .match_query(Matcher::Any)
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(
+            json!({
+                "devices": [
+                    device(
+                        "d1",
+                        Some(("release_prn", "1.0.0")),
+                        Some("bundle_prn"),
+                        Some("2025-01-01T11:30:00Z"),
+                        false
+                    ),
+                    device(
+                        "d2",
+                        Some(("release_prn", "1.0.0")),
+                        Some("deleted_bundle_prn"),
+                        Some("2024-12-20T00:00:00Z"),
+                        false
+                    ),
+                    device("d3", None, None, None, true),
+                ],
+                "next_page": null
+            })
+            .to_string(),
+        )
+        .expect(1)
+        .create_async()
+        .await;
+    let release = server
+        .mock("GET", "/releases/release_prn")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body_from_file("tests/fixtures/releases-get-200.json")
+        .expect(1)
+        .create_async()
+        .await;
+    let bundle = server
+        .mock("GET", "/bundles/bundle_prn")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body_from_file("tests/fixtures/bundles-get-200.json")
+        .expect(1)
+        .create_async()
+        .await;
+    let deleted = server
+        .mock("GET", "/bundles/deleted_bundle_prn")
+        .with_status(404)
+        .with_header("content-type", "application/json")
+        .with_body(json!({ "message": "not found" }).to_string())
+        .expect(1)
+        .create_async()
+        .await;
+
+    let inventory = Inventory::collect(
+        &api,
+        InventoryOptions {
+            now: Some(Utc.with_ymd_and_hms(2025, 1, 1, 12, 0, 0).unwrap()),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(inventory.devices.len(), 3);
+    assert_eq!(inventory.devices[0].release_name.as_deref(), Some("name"));
+    assert_eq!(inventory.devices[0].bundle_name.as_deref(), Some("a"));
+    assert_eq!(inventory.devices[1].bundle_name, None);
+    let staleness: Vec<_> = inventory
+        .devices
+        .iter()
+        .map(|d| d.staleness.as_str())
+        .collect();
+    assert_eq!(staleness, vec!["<= 1h", "<= 30d", "never"]);
+
+    let report = inventory.group_by(&[Dimension::ReleaseVersion]);
+    let groups: Vec<_> = report
+        .groups
+        .iter()
+        .map(|g| (g.values.clone(), g.count))
+        .collect();
+    assert_eq!(
+        groups,
+        vec![
+            (vec!["1.0.0".to_string()], 2),
+            (vec!["none".to_string()], 1),
+        ]
+    );
+    assert_eq!(report.total, 3);
+
+    let report = inventory.group_by(&[Dimension::Bundle, Dimension::Quarantined]);
+    assert_eq!(
+        report.to_csv(),
+        "bundle,quarantined,devices\n\
+         a,false,1\n\
+         deleted_bundle_prn,false,1\n\
+         none,true,1\n"
+    );
+    let table = report.to_table();
+    assert!(table.starts_with("bundle              quarantined  devices\n"));
+
+    let json: Value = serde_json::from_str(&report.to_json().unwrap()).unwrap();
+    assert_eq!(json["dimensions"], json!(["bundle", "quarantined"]));
+    assert_eq!(json["groups"][0]["device_prns"], json!(["d1"]));
+
+    list.assert_async().await;
+    release.assert_async().await;
+    bundle.assert_async().await;
+    deleted.assert_async().await;
+}
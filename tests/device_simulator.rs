@@ -0,0 +1,294 @@
+mod common;
+
+use common::API_KEY;
+use mockito::{Matcher, Server};
+use serde_json::{json, Value};
+
+use peridio_sdk::api::devices::simulator::{
+    Error, Expectation, Flag, Simulator, SimulatorOptions, VirtualDevice,
+};
+use peridio_sdk::api::Api;
+use peridio_sdk::api::ApiOptions;
+
+fn device(prn: &str, tags: &[&str]) -> Value {
+    json!({
+        "product_prn": "product_prn",
+        "cohort_prn": "cohort_prn",
+        "description": null,
+        "quarantined": false,
+        "identifier": prn,
+        "inserted_at": "2019-08-24T14:15:22Z",
+        "last_connected_at": null,
+        "prn": prn,
+        "tags": tags,
+        "target": "arm64",
+        "reported_release_prn": null,
+        "reported_release_version": null,
+        "reported_bundle_prn": null,
+        "updated_at": "2019-08-24T14:15:22Z"
+    })
+}
+
+fn virtual_device(identifier: &str, tags: &[&str], expectation: Expectation) -> VirtualDevice {
+    VirtualDevice {
+        identifier: identifier.to_string(),
+        cohort_prn: Some("cohort_prn".to_string()),
+        tags: tags.iter().map(|tag| tag.to_string()).collect(),
+        release_version: Some("1.0.0".to_string()),
+        expectation,
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn simulate_device_check_ins() {
+    let mut server = Server::new_async().await;
+
+    let api = Api::new(ApiOptions {
+        api_key: API_KEY.into(),
+        endpoint: Some(server.url()),
+        ca_bundle_path: None,
+        api_version: 1,
+    });
+
+    let list = server
+        .mock("GET", "/devices")
+        .match_query(Matcher::Any)
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(
+            json!({
+                "devices": [
+                    device("d1", &["canary"]),
+                    device("d2", &["canary"]),
+                    device("d3", &[]),
+                ],
+                "next_page": null
+            })
+            .to_string(),
+        )
+        .expect(1)
+        .create_async()
+        .await;
+
+    let update = |bundle_prn: Option<&str>| {
+        json!({
+            "status": if bundle_prn.is_some() { "update" } else { "no_update" },
+            "bundle_prn": bundle_prn,
+            "manifest": bundle_prn.map(|_| json!([{ "size": 10, "custom_metadata": {} }]))
+        })
+        .to_string()
+    };
+    let mut check_ins = Vec::new();
+    for (prn, bundle_prn) in [
+        ("d1", Some("bundle_a")),
+        ("d2", Some("bundle_b")),
+        ("d3", None),
+    ] {
+        check_ins.push(
+            server
+                .mock("POST", &*format!("/devices/{prn}/update"))
+                .match_body(Matcher::PartialJson(
+                    json!({ "release_version": "1.0.0", "write": false }),
+                ))
+                .with_status(200)
+                .with_header("content-type", "application/json")
+                .with_body(update(bundle_prn))
+                .expect(1)
+                .create_async()
+                .await,
+        );
+    }
+    let writes = server
+        .mock("PATCH", Matcher::Any)
+        .expect(0)
+        .create_async()
+        .await;
+
+    let bundle = |prn: &str| Expectation::Bundle {
+        bundle_prn: prn.to_string(),
+    };
+    let report = Simulator::new(
+        &api,
+        SimulatorOptions {
+            concurrency: 2,
+            ..Default::default()
+        },
+    )
+    .run(vec![
+        virtual_device("d1", &["canary"], bundle("bundle_a")),
+        virtual_device("d2", &["canary"], bundle("bundle_a")),
+        virtual_device("d3", &["canary"], Expectation::Any),
+        virtual_device("d4", &[], Expectation::NoUpdate),
+    ])
+    .await
+    .unwrap();
+
+    list.assert_async().await;
+    for check_in in check_ins {
+        check_in.assert_async().await;
+    }
+    writes.assert_async().await;
+
+    let identifiers: Vec<_> = report.results.iter().map(|r| &*r.identifier).collect();
+    assert_eq!(identifiers, vec!["d1", "d2", "d3", "d4"]);
+    assert!(report.results[0].flags.is_empty());
+    assert_eq!(
+        report.results[1].flags,
+        vec![Flag::Unexpected {
+            expected: Some("bundle_a".to_string()),
+            actual: Some("bundle_b".to_string()),
+        }]
+    );
+    assert_eq!(
+        report.results[2].flags,
+        vec![
+            Flag::StateMismatch {
+                field: "tags".to_string()
+            },
+            Flag::NoUpdate,
+        ]
+    );
+    assert_eq!(report.results[3].flags, vec![Flag::NotFound]);
+    assert_eq!(report.flagged().count(), 3);
+
+    let groups = report.by_bundle();
+    assert_eq!(groups[&Some("bundle_a".to_string())], vec!["d1"]);
+    assert_eq!(groups[&Some("bundle_b".to_string())], vec!["d2"]);
+    assert_eq!(groups[&None], vec!["d3", "d4"]);
+
+    let json: Value = serde_json::from_str(&report.to_json().unwrap()).unwrap();
+    assert_eq!(json["results"][1]["flags"][0]["flag"], "unexpected");
+}
+
+#[tokio::test]
+async fn simulate_creates_missing_devices() {
+    let mut server = Server::new_async().await;
+
+    let api = Api::new(ApiOptions {
+        api_key: API_KEY.into(),
+        endpoint: Some(server.url()),
+        ca_bundle_path: None,
+        api_version: 1,
+    });
+
+    let list = server
+        .mock("GET", "/devices")
+        .match_query(Matcher::Any)
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(json!({ "devices": [device("d1", &[])], "next_page": null }).to_string())
+        .expect(1)
+        .create_async()
+        .await;
+    let writes = server
+        .mock("PATCH", Matcher::Any)
+        .expect(0)
+        .create_async()
+        .await;
+    let create = server
+        .mock("POST", "/devices")
+        .match_body(Matcher::PartialJson(json!({
+            "identifier": "d2",
+            "product_prn": "product_prn",
+            "cohort_prn": "cohort_prn"
+        })))
+        .with_status(201)
+        .with_header("content-type", "application/json")
+        .with_body(json!({ "device": device("d2", &["canary"]) }).to_string())
+        .expect(1)
+        .create_async()
+        .await;
+    let check_ins = server
+        .mock(
+            "POST",
+            Matcher::Regex(r"^/devices/d[12]/update$".to_string()),
+        )
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body_from_file("tests/fixtures/devices-get-update-200.json")
+        .expect(2)
+        .create_async()
+        .await;
+
+    let teardown = server
+        .mock("DELETE", "/devices/d2")
+        .with_status(204)
+        .expect(1)
+        .create_async()
+        .await;
+
+    let simulator = Simulator::new(
+        &api,
+        SimulatorOptions {
+            sync: true,
+            product_prn: Some("product_prn".to_string()),
+            ..Default::default()
+        },
+    );
+    let report = simulator
+        .run(vec![
+            virtual_device("d1", &["canary"], Expectation::Any),
+            virtual_device("d2", &["canary"], Expectation::Any),
+        ])
+        .await
+        .unwrap();
+
+    list.assert_async().await;
+    writes.assert_async().await;
+    create.assert_async().await;
+    check_ins.assert_async().await;
+
+    // The existing device is reported, not changed.
+    assert_eq!(
+        report.results[0].flags,
+        vec![Flag::StateMismatch {
+            field: "tags".to_string()
+        }]
+    );
+    assert!(!report.results[0].created);
+    assert!(report.results[1].flags.is_empty());
+    assert!(report.results[1].created);
+    assert_eq!(report.created().collect::<Vec<_>>(), vec!["d2"]);
+
+    let summary = simulator.teardown(&report).await;
+    teardown.assert_async().await;
+    assert_eq!(summary.deleted, vec!["d2"]);
+    assert!(summary.failures.is_empty());
+}
+
+#[tokio::test]
+async fn sync_requires_product_prn() {
+    let mut server = Server::new_async().await;
+
+    let api = Api::new(ApiOptions {
+        api_key: API_KEY.into(),
+        endpoint: Some(server.url()),
+        ca_bundle_path: None,
+        api_version: 1,
+    });
+
+    let list = server
+        .mock("GET", "/devices")
+        .match_query(Matcher::Any)
+        .expect(0)
+        .create_async()
+        .await;
+
+    for product_prn in [None, Some(String::new())] {
+        let error = Simulator::new(
+            &api,
+            SimulatorOptions {
+                sync: true,
+                product_prn,
+                ..Default::default()
+            },
+        )
+        .run(vec![virtual_device("d1", &[], Expectation::Any)])
+        .await
+        .unwrap_err();
+        assert!(matches!(error, Error::MissingProductPrn));
+    }
+
+    list.assert_async().await;
+}
@@ -1,15 +1,18 @@
 mod common;
 
 use common::API_KEY;
-use mockito::Server;
+use mockito::{Matcher, Server};
+use serde_json::{json, Value};
 
 use peridio_sdk::api::devices::{
-    CreateDeviceParams, DeleteDeviceParams, GetDeviceParams, GetUpdateDeviceParams,
-    ListDeviceParams, UpdateDeviceParams,
+    CreateDeviceParams, DeleteDeviceParams, DeviceUpdateStatus, GetDeviceParams,
+    GetUpdateDeviceParams, ListDeviceParams, TagChange, UpdateDeviceParams, UpdateDeviceTagsParams,
+    UpdateMatchingDeviceTagsParams,
 };
 
 use peridio_sdk::api::Api;
 use peridio_sdk::api::ApiOptions;
+use peridio_sdk::api::Error;
 
 #[tokio::test]
 async fn create_device() {
@@ -239,7 +242,7 @@ async fn get_update_device() {
     let bundle_prn = "bun-1";
     let release_version = "rev-1";
 
-    let expected_update = "update";
+    let expected_update = DeviceUpdateStatus::Update;
     let expected_size = 10u32;
 
     let api = Api::new(ApiOptions {
@@ -266,7 +269,7 @@ async fn get_update_device() {
     };
 
     if let Some(response) = api.devices().get_update(params).await.unwrap() {
-        assert_eq!(response.status, expected_update.to_string());
+        assert_eq!(response.status, expected_update);
         assert_eq!(response.manifest.unwrap()[0].size, Some(expected_size));
     } else {
         panic!();
@@ -274,3 +277,237 @@ async fn get_update_device() {
 
     m.assert_async().await;
 }
+
+fn device(prn: &str, tags: &[&str], updated_at: &str) -> Value {
+    json!({
+        "device": {
+            "product_prn": "product_prn",
+            "cohort_prn": null,
+            "description": null,
+            "quarantined": false,
+            "identifier": format!("{prn}-identifier"),
+            "inserted_at": "2019-08-24T14:15:22Z",
+            "last_connected_at": null,
+            "prn": prn,
+            "tags": tags,
+            "target": null,
+            "reported_release_prn": null,
+            "reported_release_version": null,
+            "reported_bundle_prn": null,
+            "updated_at": updated_at
+        }
+    })
+}
+
+fn strings(values: &[&str]) -> Vec<String> {
+    values.iter().map(|value| value.to_string()).collect()
+}
+
+#[test]
+fn apply_tag_changes() {
+    let current = strings(&["a", "b"]);
+
+    assert_eq!(
+        TagChange::Add(strings(&["b", "c", "c"])).apply(&current),
+        strings(&["a", "b", "c"])
+    );
+    assert_eq!(
+        TagChange::Remove(strings(&["a", "z"])).apply(&current),
+        strings(&["b"])
+    );
+    assert_eq!(
+        TagChange::Replace(strings(&["z", "z"])).apply(&current),
+        strings(&["z"])
+    );
+}
+
+#[tokio::test]
+async fn update_device_tags_retries_on_conflict() {
+    let mut server = Server::new_async().await;
+
+    let api = Api::new(ApiOptions {
+        api_key: API_KEY.into(),
+        endpoint: Some(server.url()),
+        ca_bundle_path: None,
+        api_version: 1,
+    });
+
+    let first = server
+        .mock("GET", "/devices/prn")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(device("prn", &["a"], "2024-01-01T00:00:00Z").to_string())
+        .expect(1)
+        .create_async()
+        .await;
+    // Someone else added "b" between our read and write.
+    let edited = server
+        .mock("GET", "/devices/prn")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(device("prn", &["a", "b"], "2024-01-01T00:01:00Z").to_string())
+        .expect(2)
+        .create_async()
+        .await;
+    let update = server
+        .mock("PATCH", "/devices/prn")
+        .match_body(Matcher::Json(
+            json!({ "prn": "prn", "tags": ["a", "b", "c"] }),
+        ))
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(device("prn", &["a", "b", "c"], "2024-01-01T00:02:00Z").to_string())
+        .expect(1)
+        .create_async()
+        .await;
+
+    let change = api
+        .devices()
+        .update_tags(UpdateDeviceTagsParams {
+            prn: "prn".to_string(),
+            change: TagChange::Add(strings(&["c"])),
+            max_attempts: 3,
+        })
+        .await
+        .unwrap();
+
+    assert_eq!(change.before, strings(&["a", "b"]));
+    assert_eq!(change.after, strings(&["a", "b", "c"]));
+    assert_eq!(change.added, strings(&["c"]));
+    assert!(change.removed.is_empty());
+
+    first.assert_async().await;
+    edited.assert_async().await;
+    update.assert_async().await;
+}
+
+#[tokio::test]
+async fn update_device_tags_gives_up_after_max_attempts() {
+    let mut server = Server::new_async().await;
+
+    let api = Api::new(ApiOptions {
+        api_key: API_KEY.into(),
+        endpoint: Some(server.url()),
+        ca_bundle_path: None,
+        api_version: 1,
+    });
+
+    server
+        .mock("GET", "/devices/prn")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(device("prn", &["a"], "2024-01-01T00:00:00Z").to_string())
+        .expect(1)
+        .create_async()
+        .await;
+    server
+        .mock("GET", "/devices/prn")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(device("prn", &["a"], "2024-01-01T00:01:00Z").to_string())
+        .create_async()
+        .await;
+    let update = server
+        .mock("PATCH", "/devices/prn")
+        .expect(0)
+        .create_async()
+        .await;
+
+    let result = api
+        .devices()
+        .update_tags(UpdateDeviceTagsParams {
+            prn: "prn".to_string(),
+            change: TagChange::Remove(strings(&["a"])),
+            max_attempts: 1,
+        })
+        .await;
+
+    assert!(matches!(
+        result,
+        Err(Error::UpdateConflict { attempts: 1, .. })
+    ));
+    update.assert_async().await;
+}
+
+#[tokio::test]
+async fn update_tags_of_matching_devices() {
+    let mut server = Server::new_async().await;
+
+    let api = Api::new(ApiOptions {
+        api_key: API_KEY.into(),
+        endpoint: Some(server.url()),
+        ca_bundle_path: None,
+        api_version: 1,
+    });
+
+    let list = server
+        .mock("GET", "/devices")
+        .match_query(Matcher::UrlEncoded("search".into(), "tags:'old'".into()))
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(
+            json!({
+                "devices": [
+                    device("one", &["x", "old"], "2024-01-01T00:00:00Z")["device"],
+                    device("two", &[], "2024-01-01T00:00:00Z")["device"],
+                    device("gone", &[], "2024-01-01T00:00:00Z")["device"],
+                ],
+                "next_page": null
+            })
+            .to_string(),
+        )
+        .expect(1)
+        .create_async()
+        .await;
+    server
+        .mock("GET", "/devices/one")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(device("one", &["x", "old"], "2024-01-01T00:00:00Z").to_string())
+        .create_async()
+        .await;
+    server
+        .mock("GET", "/devices/two")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(device("two", &[], "2024-01-01T00:00:00Z").to_string())
+        .create_async()
+        .await;
+    server
+        .mock("GET", "/devices/gone")
+        .with_status(404)
+        .with_header("content-type", "application/json")
+        .with_body(json!({ "message": "not found" }).to_string())
+        .create_async()
+        .await;
+    let update = server
+        .mock("PATCH", "/devices/one")
+        .match_body(Matcher::Json(json!({ "prn": "one", "tags": ["x"] })))
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(device("one", &["x"], "2024-01-01T00:01:00Z").to_string())
+        .expect(1)
+        .create_async()
+        .await;
+
+    let summary = api
+        .devices()
+        .update_tags_matching(UpdateMatchingDeviceTagsParams {
+            search: "tags:'old'".to_string(),
+            change: TagChange::Remove(strings(&["old"])),
+            max_attempts: 3,
+            concurrency: 2,
+        })
+        .await
+        .unwrap();
+
+    assert_eq!(summary.changes.len(), 2);
+    let changed: Vec<_> = summary.changed().map(|c| c.prn.as_str()).collect();
+    assert_eq!(changed, vec!["one"]);
+    assert_eq!(summary.changes[0].removed, strings(&["old"]));
+    assert_eq!(summary.failures.len(), 1);
+    assert_eq!(summary.failures[0].prn, "gone");
+
+    list.assert_async().await;
+    update.assert_async().await;
+}
@@ -0,0 +1,261 @@
+mod common;
+
+use common::API_KEY;
+use mockito::{Matcher, Server};
+use serde_json::json;
+
+use peridio_sdk::api::devices::{
+    CreateDeviceParams, DeleteDeviceParams, DeviceQuery, GetUpdateDeviceParams, UpdateDeviceParams,
+};
+use peridio_sdk::api::dry_run::PlannedOperation;
+use peridio_sdk::api::Api;
+use peridio_sdk::api::ApiOptions;
+use peridio_sdk::certificates::{self, LocalCa};
+
+#[tokio::test]
+async fn dry_run_plans_mutations() {
+    let mut server = Server::new_async().await;
+
+    let api = Api::new(ApiOptions {
+        api_key: API_KEY.into(),
+        endpoint: Some(server.url()),
+        ca_bundle_path: None,
+        api_version: 1,
+    })
+    .dry_run();
+    assert!(api.is_dry_run());
+
+    let list = server
+        .mock("GET", "/devices")
+        .match_query(Matcher::UrlEncoded("search".into(), "tags:'canary'".into()))
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(json!({ "devices": [], "next_page": null }).to_string())
+        .expect(1)
+        .create_async()
+        .await;
+    let mut mutations = Vec::new();
+    for (method, path) in [
+        ("POST", "/devices"),
+        ("PATCH", "/devices/device_prn"),
+        ("DELETE", "/devices/device_prn"),
+    ] {
+        mutations.push(
+            server
+                .mock(method, path)
+                .with_status(500)
+                .expect(0)
+                .create_async()
+                .await,
+        );
+    }
+
+    let devices = api
+        .devices()
+        .list(DeviceQuery::new().tag("canary").into())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(devices.devices.is_empty());
+
+    let created = api
+        .devices()
+        .create(CreateDeviceParams {
+            product_prn: "product_prn".to_string(),
+            description: None,
+            quarantined: None,
+            identifier: "sn-1".to_string(),
+            tags: Some(vec!["canary".to_string()]),
+            target: None,
+            cohort_prn: None,
+        })
+        .await
+        .unwrap();
+    assert!(created.is_none());
+
+    // Clones share the plan.
+    let clone = api.clone();
+    clone
+        .devices()
+        .update(UpdateDeviceParams {
+            product_prn: None,
+            cohort_prn: Some("cohort_prn".to_string()),
+            prn: "device_prn".to_string(),
+            description: None,
+            quarantined: None,
+            tags: None,
+            target: None,
+        })
+        .await
+        .unwrap();
+    clone
+        .devices()
+        .delete(DeleteDeviceParams {
+            prn: "device_prn".to_string(),
+        })
+        .await
+        .unwrap();
+
+    list.assert_async().await;
+    for mutation in mutations {
+        mutation.assert_async().await;
+    }
+
+    let planned = api.planned_operations();
+    assert_eq!(
+        planned
+            .iter()
+            .map(|operation| (operation.method.as_str(), operation.path.as_str()))
+            .collect::<Vec<_>>(),
+        vec![
+            ("POST", "/devices"),
+            ("PATCH", "/devices/device_prn"),
+            ("DELETE", "/devices/device_prn"),
+        ]
+    );
+    assert_eq!(
+        planned[0].json_body(),
+        Some(json!({
+            "product_prn": "product_prn",
+            "identifier": "sn-1",
+            "tags": ["canary"]
+        }))
+    );
+    assert_eq!(
+        planned[1],
+        PlannedOperation {
+            method: "PATCH".to_string(),
+            path: "/devices/device_prn".to_string(),
+            query: vec![],
+            body: Some(r#"{"cohort_prn":"cohort_prn","prn":"device_prn"}"#.to_string()),
+        }
+    );
+    assert_eq!(planned[2].body, None);
+}
+
+#[tokio::test]
+async fn no_plan_without_dry_run() {
+    let mut server = Server::new_async().await;
+
+    let api = Api::new(ApiOptions {
+        api_key: API_KEY.into(),
+        endpoint: Some(server.url()),
+        ca_bundle_path: None,
+        api_version: 1,
+    });
+    assert!(!api.is_dry_run());
+
+    let m = server
+        .mock("DELETE", "/devices/device_prn")
+        .with_status(204)
+        .expect(1)
+        .create_async()
+        .await;
+
+    api.devices()
+        .delete(DeleteDeviceParams {
+            prn: "device_prn".to_string(),
+        })
+        .await
+        .unwrap();
+
+    m.assert_async().await;
+    assert!(api.planned_operations().is_empty());
+}
+
+#[tokio::test]
+async fn dry_run_sends_update_checks_without_write() {
+    let mut server = Server::new_async().await;
+
+    let api = Api::new(ApiOptions {
+        api_key: API_KEY.into(),
+        endpoint: Some(server.url()),
+        ca_bundle_path: None,
+        api_version: 1,
+    })
+    .dry_run();
+
+    let m = server
+        .mock("POST", "/devices/device_prn/update")
+        .match_body(Matcher::PartialJson(json!({ "write": false })))
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body_from_file("tests/fixtures/devices-get-update-200.json")
+        .expect(1)
+        .create_async()
+        .await;
+
+    let params = |write| GetUpdateDeviceParams {
+        prn: "device_prn".to_string(),
+        release_prn: None,
+        bundle_prn: None,
+        release_version: None,
+        write,
+    };
+
+    let checked = api.devices().get_update(params(false)).await.unwrap();
+    assert!(checked.is_some());
+    let written = api.devices().get_update(params(true)).await.unwrap();
+    assert!(written.is_none());
+
+    m.assert_async().await;
+    let planned = api.planned_operations();
+    assert_eq!(planned.len(), 1);
+    assert_eq!(planned[0].path, "/devices/device_prn/update");
+    assert_eq!(planned[0].json_body().unwrap()["write"], json!(true));
+}
+
+#[tokio::test]
+async fn dry_run_provision_plans_device() {
+    let mut server = Server::new_async().await;
+
+    let api = Api::new(ApiOptions {
+        api_key: API_KEY.into(),
+        endpoint: Some(server.url()),
+        ca_bundle_path: None,
+        api_version: 1,
+    })
+    .dry_run();
+    let read =
+        |name: &str| std::fs::read_to_string(format!("tests/files/certificates/{name}")).unwrap();
+    let ca = LocalCa::from_pem(&read("ca.pem"), &read("ca-key.pem")).unwrap();
+
+    let list = server
+        .mock("GET", "/devices")
+        .match_query(Matcher::Any)
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(json!({ "devices": [], "next_page": null }).to_string())
+        .expect(1)
+        .create_async()
+        .await;
+
+    let provisioned = certificates::provision(
+        &api,
+        &ca,
+        CreateDeviceParams {
+            product_prn: "product_prn".to_string(),
+            description: None,
+            quarantined: None,
+            identifier: "sn1234".to_string(),
+            tags: None,
+            target: None,
+            cohort_prn: None,
+        },
+    )
+    .await
+    .unwrap();
+    assert!(provisioned.created);
+    assert!(provisioned.device.is_none());
+    assert!(provisioned.device_certificate.is_none());
+
+    list.assert_async().await;
+    // The certificate can't be registered before the device has a PRN.
+    assert_eq!(
+        api.planned_operations()
+            .iter()
+            .map(|operation| (operation.method.as_str(), operation.path.as_str()))
+            .collect::<Vec<_>>(),
+        vec![("POST", "/devices")]
+    );
+}
@@ -0,0 +1,941 @@
+use peridio_sdk::api::artifact_versions::CreateArtifactVersionParams;
+use peridio_sdk::api::artifacts::CreateArtifactParams;
+use peridio_sdk::api::binaries::{
+    BinaryState, CreateBinaryParams, MarkBinaryHashableParams, MarkBinarySignableParams,
+    UpdateBinaryParams,
+};
+use peridio_sdk::api::bundle_overrides::{
+    AddDeviceParams, CreateBundleOverrideParams, DeviceListParams, ListDevicesParams,
+    RemoveDeviceParams,
+};
+use peridio_sdk::api::bundles::{
+    Bundle, CreateBundleBinary, CreateBundleParams, CreateBundleParamsV2,
+};
+use peridio_sdk::api::ca_certificates::{CreateCaCertificateParams, ListCaCertificateParams};
+use peridio_sdk::api::cohorts::CreateCohortParams;
+use peridio_sdk::api::device_certificates::CreateDeviceCertificateParams;
+use peridio_sdk::api::devices::simulator::{
+    Expectation, Flag, Simulator, SimulatorOptions, VirtualDevice,
+};
+use peridio_sdk::api::devices::{
+    CreateDeviceParams, DeleteDeviceParams, DeviceOrder, DeviceQuery, DeviceUpdateSourceType,
+    GetDeviceParams, GetUpdateDeviceParams, ListDeviceParams,
+};
+use peridio_sdk::api::error::ApiError;
+use peridio_sdk::api::events::{EventType, WebhookEventType};
+use peridio_sdk::api::products::CreateProductParams;
+use peridio_sdk::api::releases::CreateReleaseParams;
+use peridio_sdk::api::signing_keys::{CreateSigningKeyParams, ListSigningKeysParams};
+use peridio_sdk::api::tunnels::{
+    CreateTunnelParams, GetTunnelParams, TunnelState, UpdateTunnelParams,
+};
+use peridio_sdk::api::webhooks::{
+    CreateWebhookParams, RollSecretWebhookParams, TestFireWebhookParams, UpdateWebhookParams,
+    WebhookEnabledEvent, WebhookState,
+};
+use peridio_sdk::api::{Api, ApiOptions, Error};
+use peridio_sdk::certificates::{self, parse_pem, LocalCa};
+use peridio_sdk::list_params::{Direction, ListParams};
+use peridio_sdk::testing::FakeServer;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+async fn create_product(api: &Api, name: &str) -> String {
+    api.products()
+        .create(CreateProductParams {
+            archived: None,
+            name: name.to_string(),
+        })
+        .await
+        .unwrap()
+        .unwrap()
+        .product
+        .prn
+}
+
+/// A V2 bundle with the one binary of a new artifact.
+async fn create_bundle(api: &Api, version: &str) -> String {
+    let artifact_prn = api
+        .artifacts()
+        .create(CreateArtifactParams {
+            custom_metadata: None,
+            description: None,
+            id: None,
+            name: format!("firmware-{version}"),
+        })
+        .await
+        .unwrap()
+        .unwrap()
+        .artifact
+        .prn;
+    let version = api
+        .artifact_versions()
+        .create(CreateArtifactVersionParams {
+            artifact_prn,
+            custom_metadata: None,
+            id: None,
+            description: None,
+            version: version.to_string(),
+        })
+        .await
+        .unwrap()
+        .unwrap()
+        .artifact_version;
+    let binary = api
+        .binaries()
+        .create(CreateBinaryParams {
+            artifact_version_prn: version.prn,
+            custom_metadata: None,
+            description: None,
+            hash: "a".repeat(64),
+            id: None,
+            size: 10,
+            target: "arm64".to_string(),
+        })
+        .await
+        .unwrap()
+        .unwrap()
+        .binary;
+
+    let bundle = api
+        .bundles()
+        .create(CreateBundleParams::V2(CreateBundleParamsV2 {
+            binaries: vec![CreateBundleBinary {
+                prn: binary.prn,
+                custom_metadata: None,
+            }],
+            id: None,
+            name: None,
+        }))
+        .await
+        .unwrap()
+        .unwrap()
+        .bundle;
+    match bundle {
+        Bundle::V2(bundle) => bundle.prn,
+        Bundle::V1(bundle) => panic!("created V1 bundle {}", bundle.prn),
+    }
+}
+
+fn create_release(bundle_prn: &str, cohort_prn: &str, version: &str) -> CreateReleaseParams {
+    CreateReleaseParams {
+        bundle_prn: bundle_prn.to_string(),
+        cohort_prn: cohort_prn.to_string(),
+        description: None,
+        disabled: None,
+        name: version.to_string(),
+        next_release_prn: None,
+        phase_mode: None,
+        phase_tags: None,
+        phase_value: None,
+        previous_release_prn: None,
+        required: false,
+        schedule_date: "2020-01-01T00:00:00Z".to_string(),
+        version: Some(version.to_string()),
+        version_requirement: None,
+    }
+}
+
+fn create_device(product_prn: &str, identifier: &str, tags: &[&str]) -> CreateDeviceParams {
+    CreateDeviceParams {
+        product_prn: product_prn.to_string(),
+        description: None,
+        quarantined: None,
+        identifier: identifier.to_string(),
+        tags: Some(tags.iter().map(|tag| tag.to_string()).collect()),
+        target: Some("arm64".to_string()),
+        cohort_prn: None,
+    }
+}
+
+#[tokio::test]
+async fn devices_workflow() {
+    let server = FakeServer::start().await.unwrap();
+    let api = server.api();
+
+    let product_prn = create_product(&api, "gateway").await;
+    assert_eq!(
+        product_prn,
+        format!(
+            "{}:product:00000000-0000-4000-8000-000000000001",
+            server.organization_prn()
+        )
+    );
+
+    let cohort = api
+        .cohorts()
+        .create(CreateCohortParams {
+            description: None,
+            name: "canary".to_string(),
+            product_prn: product_prn.clone(),
+        })
+        .await
+        .unwrap()
+        .unwrap()
+        .cohort;
+    assert_eq!(cohort.product_prn, product_prn);
+    assert!(cohort.prn.contains(":cohort:"));
+
+    for i in 0..5 {
+        let tags: &[&str] = if i % 2 == 0 { &["lab"] } else { &[] };
+        let device = api
+            .devices()
+            .create(CreateDeviceParams {
+                cohort_prn: Some(cohort.prn.clone()),
+                ..create_device(&product_prn, &format!("sn-{i}"), tags)
+            })
+            .await
+            .unwrap()
+            .unwrap()
+            .device;
+        assert!(!device.quarantined);
+    }
+
+    server.set_page_size(2);
+    let mut identifiers = Vec::new();
+    let mut pages = 0;
+    let mut page = None;
+    loop {
+        let response = api
+            .devices()
+            .list(ListDeviceParams {
+                list: ListParams {
+                    page,
+                    ..DeviceQuery::new()
+                        .cohort(&cohort.prn)
+                        .order_by(DeviceOrder::Identifier, Direction::Desc)
+                        .into()
+                },
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        pages += 1;
+        identifiers.extend(response.devices.into_iter().map(|d| d.identifier));
+        page = match response.next_page {
+            Some(next_page) => Some(next_page),
+            None => break,
+        };
+    }
+    assert_eq!(pages, 3);
+    assert_eq!(identifiers, vec!["sn-4", "sn-3", "sn-2", "sn-1", "sn-0"]);
+
+    let tagged = api
+        .devices()
+        .list(DeviceQuery::new().tag("lab").limit(10).into())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(tagged.devices.len(), 3);
+    assert_eq!(tagged.next_page, None);
+
+    let prn = tagged.devices[0].prn.clone();
+    api.devices()
+        .delete(DeleteDeviceParams { prn: prn.clone() })
+        .await
+        .unwrap();
+    assert_eq!(server.records("devices").len(), 4);
+    let error = api
+        .devices()
+        .get(GetDeviceParams { prn })
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        Error::StructuredError {
+            status: 404,
+            error: ApiError::Standard { .. }
+        }
+    ));
+}
+
+#[tokio::test]
+async fn validation_errors() {
+    let server = FakeServer::start().await.unwrap();
+    let api = server.api();
+
+    let product_prn = create_product(&api, "gateway").await;
+    api.devices()
+        .create(create_device(&product_prn, "sn-1", &[]))
+        .await
+        .unwrap();
+
+    let error = api
+        .devices()
+        .create(CreateDeviceParams {
+            cohort_prn: Some("prn:1:unknown".to_string()),
+            ..create_device(&product_prn, "sn-1", &[])
+        })
+        .await
+        .unwrap_err();
+    let Error::StructuredError {
+        status: 422,
+        error: ApiError::Validation { errors },
+    } = error
+    else {
+        panic!("unexpected error {error:?}");
+    };
+    assert_eq!(errors["identifier"], vec!["has already been taken"]);
+    assert_eq!(errors["cohort_prn"], vec!["does not exist"]);
+
+    let error = api
+        .devices()
+        .list(ListDeviceParams {
+            list: ListParams {
+                search: Some("identifer:'sn-1'".to_string()),
+                ..Default::default()
+            },
+        })
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("identifer is not searchable"));
+
+    let unauthorized = Api::new(ApiOptions {
+        api_key: String::new(),
+        endpoint: Some(server.url()),
+        ca_bundle_path: None,
+        api_version: 1,
+    });
+    let error = unauthorized
+        .products()
+        .create(CreateProductParams {
+            archived: None,
+            name: "x".to_string(),
+        })
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        Error::StructuredError {
+            status: 401,
+            error: ApiError::Status { .. }
+        }
+    ));
+}
+
+#[tokio::test]
+async fn binary_state_transitions() {
+    let server = FakeServer::start().await.unwrap();
+    let api = server.api();
+
+    let artifact = api
+        .artifacts()
+        .create(CreateArtifactParams {
+            custom_metadata: None,
+            description: None,
+            id: Some("5f0b3b4e-6b8c-4b7e-9c3e-3f2b2a1d0c9e".to_string()),
+            name: "firmware".to_string(),
+        })
+        .await
+        .unwrap()
+        .unwrap()
+        .artifact;
+    assert!(artifact
+        .prn
+        .ends_with(":artifact:5f0b3b4e-6b8c-4b7e-9c3e-3f2b2a1d0c9e"));
+
+    let version = api
+        .artifact_versions()
+        .create(CreateArtifactVersionParams {
+            artifact_prn: artifact.prn,
+            custom_metadata: None,
+            id: None,
+            description: None,
+            version: "1.0.0".to_string(),
+        })
+        .await
+        .unwrap()
+        .unwrap()
+        .artifact_version;
+
+    let binary = api
+        .binaries()
+        .create(CreateBinaryParams {
+            artifact_version_prn: version.prn,
+            custom_metadata: None,
+            description: None,
+            hash: "a".repeat(64),
+            id: None,
+            size: 10,
+            target: "arm64".to_string(),
+        })
+        .await
+        .unwrap()
+        .unwrap()
+        .binary;
+    assert_eq!(binary.state, BinaryState::Uploadable);
+
+    let error = api
+        .binaries()
+        .update(UpdateBinaryParams {
+            prn: binary.prn.clone(),
+            custom_metadata: None,
+            description: None,
+            state: Some(BinaryState::Signed),
+            hash: None,
+            size: None,
+        })
+        .await
+        .unwrap_err();
+    assert!(error
+        .to_string()
+        .contains("cannot transition from uploadable to signed"));
+
+    api.binaries()
+        .mark_hashable(MarkBinaryHashableParams {
+            prn: binary.prn.clone(),
+            hash: None,
+            size: None,
+        })
+        .await
+        .unwrap();
+    api.binaries()
+        .update(UpdateBinaryParams {
+            prn: binary.prn.clone(),
+            custom_metadata: None,
+            description: None,
+            state: Some(BinaryState::Hashing),
+            hash: None,
+            size: None,
+        })
+        .await
+        .unwrap();
+    let binary = api
+        .binaries()
+        .mark_signable(MarkBinarySignableParams {
+            prn: binary.prn.clone(),
+        })
+        .await
+        .unwrap()
+        .unwrap()
+        .binary;
+    assert_eq!(binary.state, BinaryState::Signable);
+    assert_eq!(server.records("binaries")[0]["state"], "signable");
+}
+
+#[tokio::test]
+async fn device_updates_from_releases() {
+    let server = FakeServer::start().await.unwrap();
+    let api = server.api();
+
+    let product_prn = create_product(&api, "gateway").await;
+    let cohort_prn = api
+        .cohorts()
+        .create(CreateCohortParams {
+            description: None,
+            name: "canary".to_string(),
+            product_prn: product_prn.clone(),
+        })
+        .await
+        .unwrap()
+        .unwrap()
+        .cohort
+        .prn;
+    let bundle_prn = create_bundle(&api, "1.1.0").await;
+    let release = api
+        .releases()
+        .create(create_release(&bundle_prn, &cohort_prn, "1.1.0"))
+        .await
+        .unwrap()
+        .unwrap()
+        .release;
+    assert!(release.schedule_complete);
+
+    let device_prn = api
+        .devices()
+        .create(CreateDeviceParams {
+            cohort_prn: Some(cohort_prn.clone()),
+            ..create_device(&product_prn, "sn-1", &["lab"])
+        })
+        .await
+        .unwrap()
+        .unwrap()
+        .device
+        .prn;
+
+    let check = |release_version: &str, write| GetUpdateDeviceParams {
+        prn: device_prn.clone(),
+        release_prn: None,
+        bundle_prn: None,
+        release_version: Some(release_version.to_string()),
+        write,
+    };
+    let update = api
+        .devices()
+        .get_update(check("1.0.0", false))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(update.bundle_prn.as_deref(), Some(bundle_prn.as_str()));
+    assert_eq!(update.source_prn.as_deref(), Some(release.prn.as_str()));
+    let manifest = update.manifest.unwrap();
+    assert_eq!(manifest.len(), 1);
+    assert_eq!(manifest[0].target.as_deref(), Some("arm64"));
+    assert_eq!(manifest[0].size, Some(10));
+    assert_eq!(
+        server.records("devices")[0]["reported_release_version"],
+        Value::Null
+    );
+
+    let update = api
+        .devices()
+        .get_update(check("1.1.0", true))
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(update.bundle_prn.is_none());
+    assert_eq!(
+        server.records("devices")[0]["reported_release_version"],
+        "1.1.0"
+    );
+
+    // The simulator runs against the fake server like against the real one:
+    // it reports the existing device's differing tags instead of changing
+    // them, and deletes the device it created when torn down.
+    let virtual_device = |identifier: &str, expectation| VirtualDevice {
+        identifier: identifier.to_string(),
+        cohort_prn: Some(cohort_prn.clone()),
+        tags: Vec::new(),
+        release_version: Some("1.0.0".to_string()),
+        expectation,
+        ..Default::default()
+    };
+    let simulator = Simulator::new(
+        &api,
+        SimulatorOptions {
+            sync: true,
+            product_prn: Some(product_prn.clone()),
+            ..Default::default()
+        },
+    );
+    let report = simulator
+        .run(vec![
+            virtual_device("sn-1", Expectation::Any),
+            virtual_device(
+                "sn-2",
+                Expectation::Bundle {
+                    bundle_prn: bundle_prn.clone(),
+                },
+            ),
+        ])
+        .await
+        .unwrap();
+    assert_eq!(
+        report.results[0].flags,
+        vec![Flag::StateMismatch {
+            field: "tags".to_string()
+        }]
+    );
+    assert_eq!(report.results[0].bundle_prn(), Some(bundle_prn.as_str()));
+    assert!(report.results[1].created);
+    assert!(report.results[1].flags.is_empty());
+    assert_eq!(server.records("devices")[0]["tags"], json!(["lab"]));
+    assert_eq!(server.records("devices").len(), 2);
+
+    let summary = simulator.teardown(&report).await;
+    assert_eq!(summary.deleted.len(), 1);
+    assert!(summary.failures.is_empty());
+    let devices = server.records("devices");
+    assert_eq!(devices.len(), 1);
+    assert_eq!(devices[0]["identifier"], "sn-1");
+}
+
+fn validation_errors_of(error: Error) -> std::collections::HashMap<String, Vec<String>> {
+    match error {
+        Error::StructuredError {
+            status: 422,
+            error: ApiError::Validation { errors },
+        } => errors,
+        error => panic!("unexpected error {error:?}"),
+    }
+}
+
+#[tokio::test]
+async fn bundle_overrides_win_over_releases() {
+    let server = FakeServer::start().await.unwrap();
+    let api = server.api();
+
+    let product_prn = create_product(&api, "gateway").await;
+    let cohort_prn = api
+        .cohorts()
+        .create(CreateCohortParams {
+            description: None,
+            name: "canary".to_string(),
+            product_prn: product_prn.clone(),
+        })
+        .await
+        .unwrap()
+        .unwrap()
+        .cohort
+        .prn;
+    let release_bundle_prn = create_bundle(&api, "1.1.0").await;
+    let override_bundle_prn = create_bundle(&api, "2.0.0-rc.1").await;
+    api.releases()
+        .create(create_release(&release_bundle_prn, &cohort_prn, "1.1.0"))
+        .await
+        .unwrap();
+    let bundle_override = api
+        .bundle_overrides()
+        .create(CreateBundleOverrideParams {
+            name: "lab".to_string(),
+            bundle_prn: override_bundle_prn.clone(),
+            starts_at: "2020-01-01T00:00:00Z".to_string(),
+            description: None,
+            ends_at: None,
+        })
+        .await
+        .unwrap()
+        .unwrap()
+        .bundle_override;
+
+    let device_prn = api
+        .devices()
+        .create(CreateDeviceParams {
+            cohort_prn: Some(cohort_prn.clone()),
+            ..create_device(&product_prn, "sn-1", &[])
+        })
+        .await
+        .unwrap()
+        .unwrap()
+        .device
+        .prn;
+    let check = || GetUpdateDeviceParams {
+        prn: device_prn.clone(),
+        release_prn: None,
+        bundle_prn: None,
+        release_version: Some("1.0.0".to_string()),
+        write: false,
+    };
+
+    api.bundle_overrides()
+        .add_device(AddDeviceParams {
+            prn: bundle_override.prn.clone(),
+            device_prn: device_prn.clone(),
+        })
+        .await
+        .unwrap();
+    let error = api
+        .bundle_overrides()
+        .add_device(AddDeviceParams {
+            prn: bundle_override.prn.clone(),
+            device_prn: device_prn.clone(),
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(
+        validation_errors_of(error)["device_prn"],
+        vec!["has already been taken"]
+    );
+    let members = api
+        .bundle_overrides()
+        .list_devices(ListDevicesParams {
+            prn: bundle_override.prn.clone(),
+            list: DeviceListParams::default(),
+        })
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(members.devices.len(), 1);
+    assert_eq!(members.devices[0].device_prn, device_prn);
+
+    let update = api.devices().get_update(check()).await.unwrap().unwrap();
+    assert_eq!(update.bundle_prn, Some(override_bundle_prn));
+    assert_eq!(update.source_prn, Some(bundle_override.prn.clone()));
+    assert_eq!(
+        update.source_type,
+        Some(DeviceUpdateSourceType::BundleOverride)
+    );
+
+    api.bundle_overrides()
+        .remove_device(RemoveDeviceParams {
+            prn: bundle_override.prn,
+            device_prn: device_prn.clone(),
+        })
+        .await
+        .unwrap();
+    let update = api.devices().get_update(check()).await.unwrap().unwrap();
+    assert_eq!(update.bundle_prn, Some(release_bundle_prn));
+    assert_eq!(update.source_type, Some(DeviceUpdateSourceType::Release));
+}
+
+#[tokio::test]
+async fn tunnel_lifecycle() {
+    let server = FakeServer::start().await.unwrap();
+    let api = server.api();
+
+    let product_prn = create_product(&api, "gateway").await;
+    let device_prn = api
+        .devices()
+        .create(create_device(&product_prn, "sn-1", &[]))
+        .await
+        .unwrap()
+        .unwrap()
+        .device
+        .prn;
+    let create = |ttl| CreateTunnelParams {
+        cidr_block_allowlist: None,
+        device_prn: device_prn.clone(),
+        device_public_key: None,
+        device_tunnel_port: 22,
+        ttl: Some(ttl),
+    };
+    let update = |prn: &str, state, ttl| UpdateTunnelParams {
+        prn: prn.to_string(),
+        state,
+        ttl,
+    };
+
+    let tunnel = api
+        .tunnels()
+        .create(create(60))
+        .await
+        .unwrap()
+        .unwrap()
+        .tunnel;
+    assert_eq!(tunnel.state, TunnelState::Open);
+    assert!(tunnel.server_public_key.is_some());
+    assert!(tunnel.server_tunnel_port.is_some());
+
+    let renewed = api
+        .tunnels()
+        .update(update(&tunnel.prn, None, Some(3600)))
+        .await
+        .unwrap()
+        .unwrap()
+        .tunnel;
+    assert!(renewed.expires_at > tunnel.expires_at);
+
+    let closed = api
+        .tunnels()
+        .update(update(&tunnel.prn, Some(TunnelState::Closed), None))
+        .await
+        .unwrap()
+        .unwrap()
+        .tunnel;
+    assert_eq!(closed.state, TunnelState::Closed);
+    // A closed tunnel is not renewed, and reports that it is closed.
+    let renewed = api
+        .tunnels()
+        .update(update(&tunnel.prn, None, Some(3600)))
+        .await
+        .unwrap()
+        .unwrap()
+        .tunnel;
+    assert_eq!(renewed.state, TunnelState::Closed);
+    assert_eq!(renewed.expires_at, closed.expires_at);
+    let error = api
+        .tunnels()
+        .update(update(&tunnel.prn, Some(TunnelState::Open), None))
+        .await
+        .unwrap_err();
+    assert!(validation_errors_of(error).contains_key("state"));
+
+    let expiring = api
+        .tunnels()
+        .create(create(0))
+        .await
+        .unwrap()
+        .unwrap()
+        .tunnel;
+    let expired = api
+        .tunnels()
+        .get(GetTunnelParams { prn: expiring.prn })
+        .await
+        .unwrap()
+        .unwrap()
+        .tunnel;
+    assert_eq!(expired.state, TunnelState::Expired);
+}
+
+#[tokio::test]
+async fn certificate_registration() {
+    let server = FakeServer::start().await.unwrap();
+    let api = server.api();
+    let read =
+        |name: &str| std::fs::read_to_string(format!("tests/files/certificates/{name}")).unwrap();
+
+    // Without a CA nothing can vouch for a device certificate.
+    let product_prn = create_product(&api, "gateway").await;
+    let device_prn = api
+        .devices()
+        .create(create_device(&product_prn, "sn-0", &[]))
+        .await
+        .unwrap()
+        .unwrap()
+        .device
+        .prn;
+    let ca = LocalCa::from_pem(&read("ca.pem"), &read("ca-key.pem")).unwrap();
+    let identity = ca.issue("sn-0").unwrap();
+    let error = api
+        .device_certificates()
+        .create(CreateDeviceCertificateParams {
+            certificate: identity.certificate_pem.clone(),
+            device_prn: device_prn.clone(),
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(
+        validation_errors_of(error)["certificate"],
+        vec!["is not issued by a registered CA certificate"]
+    );
+
+    // A verification certificate must be for a code the server handed out.
+    let error = api
+        .ca_certificates()
+        .create(CreateCaCertificateParams {
+            certificate: read("ca.pem"),
+            verification_certificate: identity.certificate_pem.clone(),
+            description: None,
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(
+        validation_errors_of(error)["verification_certificate"],
+        vec!["is not for an issued verification code"]
+    );
+
+    let ca_certificate =
+        certificates::register_ca(&api, &read("ca.pem"), &read("ca-key.pem"), None)
+            .await
+            .unwrap();
+    assert_eq!(
+        ca_certificate.serial,
+        parse_pem(&read("ca.pem")).unwrap().serial
+    );
+    let listed = api
+        .ca_certificates()
+        .list(ListCaCertificateParams::default())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(listed.ca_certificates.len(), 1);
+
+    let device_certificate = api
+        .device_certificates()
+        .create(CreateDeviceCertificateParams {
+            certificate: identity.certificate_pem.clone(),
+            device_prn,
+        })
+        .await
+        .unwrap()
+        .unwrap()
+        .device_certificate;
+    assert_eq!(
+        device_certificate.serial,
+        parse_pem(&identity.certificate_pem).unwrap().serial
+    );
+
+    let provisioned = certificates::provision(&api, &ca, create_device(&product_prn, "sn-1", &[]))
+        .await
+        .unwrap();
+    assert!(provisioned.created);
+    assert!(provisioned.device_certificate.is_some());
+    assert_eq!(server.records("device_certificates").len(), 2);
+}
+
+#[tokio::test]
+async fn webhooks_and_signing_keys() {
+    let server = FakeServer::start().await.unwrap();
+    let api = server.api();
+
+    let webhook = api
+        .webhooks()
+        .create(CreateWebhookParams {
+            description: None,
+            enabled_events: Some(vec![WebhookEnabledEvent::DeviceReleaseChanged]),
+            url: "https://example.com/hook".to_string(),
+        })
+        .await
+        .unwrap()
+        .unwrap()
+        .webhook;
+    assert_eq!(webhook.state, Some(WebhookState::Disabled));
+    assert_eq!(
+        webhook.enabled_events,
+        vec![WebhookEnabledEvent::DeviceReleaseChanged]
+    );
+
+    let rolled = api
+        .webhooks()
+        .roll_secret(RollSecretWebhookParams {
+            prn: webhook.prn.clone(),
+        })
+        .await
+        .unwrap()
+        .unwrap()
+        .webhook;
+    assert!(rolled.secret.is_some());
+    assert_ne!(rolled.secret, webhook.secret);
+
+    let enabled = api
+        .webhooks()
+        .update(UpdateWebhookParams {
+            prn: webhook.prn.clone(),
+            description: None,
+            url: None,
+            state: Some(WebhookState::Enabled),
+            enabled_events: None,
+        })
+        .await
+        .unwrap()
+        .unwrap()
+        .webhook;
+    assert_eq!(enabled.state, Some(WebhookState::Enabled));
+
+    let event = api
+        .webhooks()
+        .test_fire(TestFireWebhookParams {
+            prn: webhook.prn.clone(),
+        })
+        .await
+        .unwrap()
+        .unwrap()
+        .event;
+    let EventType::Webhook(event) = event.data else {
+        panic!("unexpected event {:?}", event.data);
+    };
+    let WebhookEventType::TestFire(test_fire) = event.data else {
+        panic!("unexpected event {:?}", event.data);
+    };
+    assert_eq!(test_fire.webhook_prn, webhook.prn);
+
+    let value = "MCowBQYDK2VwAyEAhdXdY2YUFxw0GxPtvDKTuVyBK6UDm8NsNbUKpRoHh/Q=";
+    let signing_key = api
+        .signing_keys()
+        .create(CreateSigningKeyParams {
+            value: value.to_string(),
+            name: "release".to_string(),
+        })
+        .await
+        .unwrap()
+        .unwrap()
+        .signing_key;
+    assert_eq!(
+        signing_key.keyid,
+        format!("{:x}", Sha256::digest(value.as_bytes()))
+    );
+    let found = api
+        .signing_keys()
+        .list(ListSigningKeysParams {
+            list: ListParams {
+                search: Some(format!("keyid:'{}'", signing_key.keyid)),
+                ..Default::default()
+            },
+        })
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(found.signing_keys.len(), 1);
+    let error = api
+        .signing_keys()
+        .create(CreateSigningKeyParams {
+            value: value.to_string(),
+            name: "release".to_string(),
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(
+        validation_errors_of(error)["name"],
+        vec!["has already been taken"]
+    );
+}
@@ -0,0 +1,103 @@
+use serde_json::json;
+
+use peridio_sdk::fwup::{parse_meta_conf, read_firmware, Error};
+
+#[test]
+fn read_firmware_archive() {
+    let firmware = read_firmware("tests/files/firmware.fw").unwrap();
+
+    assert_eq!(
+        firmware.hash,
+        "5e890b451d076042a117216763487a5821bc7e68418133bd6cb4bf2368ca515e"
+    );
+    assert_eq!(firmware.size, 493);
+
+    let metadata = &firmware.metadata;
+    assert_eq!(metadata.product, "Peridio Demo");
+    assert_eq!(
+        metadata.description.as_deref(),
+        Some("Demo firmware for \"rpi4\"")
+    );
+    assert_eq!(metadata.version, "1.2.3");
+    assert_eq!(metadata.platform, "rpi4");
+    assert_eq!(metadata.architecture, "arm");
+    assert_eq!(metadata.author.as_deref(), Some("Peridio"));
+    assert_eq!(metadata.vcs_identifiers.as_deref(), Some("5f1e3c7"));
+    assert_eq!(metadata.misc, None);
+    assert_eq!(metadata.uuid, "1d1c7e2c-8ec6-5a44-b4a6-58a3f2b5e8a1");
+    assert_eq!(metadata.fwup_version.as_deref(), Some("1.10.1"));
+}
+
+#[test]
+fn firmware_maps_to_create_params() {
+    let firmware = read_firmware("tests/files/firmware.fw").unwrap();
+
+    let artifact_version = firmware.artifact_version_params("artifact_prn");
+    assert_eq!(artifact_version.artifact_prn, "artifact_prn");
+    assert_eq!(artifact_version.version, "1.2.3");
+
+    let binary = firmware.binary_params("artifact_version_prn", None);
+    assert_eq!(binary.artifact_version_prn, "artifact_version_prn");
+    assert_eq!(binary.target, "rpi4");
+    assert_eq!(binary.hash, firmware.hash);
+    assert_eq!(binary.size, 493);
+    assert_eq!(
+        serde_json::to_value(&binary.custom_metadata).unwrap()["fwup"]["uuid"],
+        json!("1d1c7e2c-8ec6-5a44-b4a6-58a3f2b5e8a1")
+    );
+
+    let binary = firmware.binary_params("artifact_version_prn", Some("arm-linux".to_string()));
+    assert_eq!(binary.target, "arm-linux");
+}
+
+#[test]
+fn invalid_archives_are_rejected() {
+    assert!(matches!(
+        read_firmware("tests/files/firmware-no-meta.fw"),
+        Err(Error::MissingMetaConf { .. })
+    ));
+    assert!(matches!(
+        read_firmware("tests/files/firmware_test"),
+        Err(Error::Archive { .. })
+    ));
+    assert!(matches!(
+        read_firmware("tests/files/does-not-exist.fw"),
+        Err(Error::Io { .. })
+    ));
+}
+
+#[test]
+fn invalid_meta_conf_is_rejected() {
+    let valid = r#"
+meta-product = "product"
+meta-version = "1.0.0"
+meta-platform = "rpi4"
+meta-architecture = "arm"
+meta-uuid = "1d1c7e2c-8ec6-5a44-b4a6-58a3f2b5e8a1"
+"#;
+    assert!(parse_meta_conf(valid).is_ok());
+
+    let missing_platform = valid.replace("meta-platform = \"rpi4\"", "");
+    match parse_meta_conf(&missing_platform) {
+        Err(Error::MissingField { field }) => assert_eq!(field, "platform"),
+        other => panic!("expected MissingField, got {other:?}"),
+    }
+
+    let bad_version = valid.replace("1.0.0", "one");
+    assert!(matches!(
+        parse_meta_conf(&bad_version),
+        Err(Error::InvalidField { .. })
+    ));
+
+    let bad_uuid = valid.replace("1d1c7e2c-8ec6", "not-a-uuid");
+    assert!(matches!(
+        parse_meta_conf(&bad_uuid),
+        Err(Error::InvalidField { .. })
+    ));
+
+    let unterminated = valid.replace("\"product\"", "\"product");
+    assert!(matches!(
+        parse_meta_conf(&unterminated),
+        Err(Error::Syntax { line: 2, .. })
+    ));
+}
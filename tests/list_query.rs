@@ -0,0 +1,101 @@
+mod common;
+
+use common::API_KEY;
+use mockito::{Matcher, Server};
+use serde_json::json;
+
+use peridio_sdk::api::binaries::BinaryQuery;
+use peridio_sdk::api::devices::{DeviceOrder, DeviceQuery, ListDeviceParams};
+use peridio_sdk::api::releases::{ReleaseOrder, ReleaseQuery};
+use peridio_sdk::api::Api;
+use peridio_sdk::api::ApiOptions;
+use peridio_sdk::list_params::{Direction, ListParams};
+
+#[test]
+fn render_queries() {
+    let query = DeviceQuery::new()
+        .cohort("cohort_prn")
+        .tag("canary")
+        .tag("lab")
+        .quarantined(false)
+        .order_by(DeviceOrder::InsertedAt, Direction::Desc)
+        .limit(20);
+    assert_eq!(
+        query.search().as_deref(),
+        Some("cohort_prn:'cohort_prn' and tags:'canary' and tags:'lab' and quarantined:'false'")
+    );
+    assert_eq!(query.order().as_deref(), Some("inserted_at:desc"));
+
+    let params = ListParams::from(query);
+    assert_eq!(params.limit, Some(20));
+    assert_eq!(params.page, None);
+
+    let params = ListParams::from(ReleaseQuery::new().name("it's \\ done"));
+    assert_eq!(params.search.as_deref(), Some(r"name:'it\'s \\ done'"));
+    assert_eq!(params.order, None);
+
+    let params =
+        ListParams::from(ReleaseQuery::new().order_by(ReleaseOrder::Version, Direction::Asc));
+    assert_eq!(params.search, None);
+    assert_eq!(params.order.as_deref(), Some("version:asc"));
+
+    assert_eq!(
+        BinaryQuery::new()
+            .artifact_version("artifact_version_prn")
+            .target("arm64")
+            .search()
+            .as_deref(),
+        Some("artifact_version_prn:'artifact_version_prn' and target:'arm64'")
+    );
+}
+
+#[tokio::test]
+async fn list_devices_with_query() {
+    let mut server = Server::new_async().await;
+
+    let api = Api::new(ApiOptions {
+        api_key: API_KEY.into(),
+        endpoint: Some(server.url()),
+        ca_bundle_path: None,
+        api_version: 1,
+    });
+
+    let m = server
+        .mock("GET", "/devices")
+        .match_query(Matcher::AllOf(vec![
+            Matcher::UrlEncoded(
+                "search".into(),
+                "identifier:'sn-0001' and quarantined:'true'".into(),
+            ),
+            Matcher::UrlEncoded("order".into(), "identifier:asc".into()),
+            Matcher::UrlEncoded("page".into(), "2".into()),
+        ]))
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(json!({ "devices": [], "next_page": null }).to_string())
+        .expect(1)
+        .create_async()
+        .await;
+
+    let query = DeviceQuery::new()
+        .identifier("sn-0001")
+        .quarantined(true)
+        .order_by(DeviceOrder::Identifier, Direction::Asc);
+    let response = api
+        .devices()
+        .list(ListDeviceParams {
+            list: ListParams {
+                page: Some("2".to_string()),
+                ..query.into()
+            },
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert!(response.devices.is_empty());
+    m.assert_async().await;
+
+    let params: ListDeviceParams = DeviceQuery::new().tag("canary").into();
+    assert_eq!(params.list.search.as_deref(), Some("tags:'canary'"));
+}
@@ -0,0 +1,324 @@
+use chrono::{DateTime, Utc};
+use semver::Version;
+use serde_json::json;
+
+use peridio_sdk::api::bundle_overrides::BundleOverride;
+use peridio_sdk::api::releases::resolver::{
+    DeviceState, Outcome, OverrideMembership, ReleaseResolver, SkipReason, VersionRequirement,
+};
+use peridio_sdk::api::releases::{PhaseMode, Release};
+
+const COHORT_PRN: &str = "cohort_prn";
+
+fn now() -> DateTime<Utc> {
+    "2024-06-01T00:00:00Z".parse().unwrap()
+}
+
+fn release(prn: &str, version: &str, requirement: Option<&str>) -> Release {
+    serde_json::from_value(json!({
+        "bundle_prn": format!("bundle-{prn}"),
+        "cohort_prn": COHORT_PRN,
+        "description": null,
+        "disabled": false,
+        "inserted_at": "2024-01-01T00:00:00Z",
+        "name": prn,
+        "next_release_prn": null,
+        "organization_prn": "organization_prn",
+        "phase_mode": null,
+        "phase_tags": null,
+        "phase_type": null,
+        "phase_value": null,
+        "required": false,
+        "schedule_date": "2024-01-01T00:00:00Z",
+        "schedule_complete": true,
+        "prn": prn,
+        "updated_at": "2024-01-01T00:00:00Z",
+        "version": version,
+        "version_requirement": requirement,
+    }))
+    .unwrap()
+}
+
+fn device(version: &str) -> DeviceState {
+    DeviceState {
+        prn: "device_prn".to_string(),
+        cohort_prn: Some(COHORT_PRN.to_string()),
+        tags: vec!["canary".to_string()],
+        release_version: Some(version.to_string()),
+    }
+}
+
+fn selected_release(outcome: &Outcome) -> &str {
+    match outcome {
+        Outcome::Release { release_prn, .. } => release_prn,
+        other => panic!("expected a release, got {other:?}"),
+    }
+}
+
+#[test]
+fn version_requirement_matching() {
+    let v = |s: &str| Version::parse(s).unwrap();
+
+    let exact: VersionRequirement = "= 1.0.0".parse().unwrap();
+    assert!(exact.matches(&v("1.0.0")));
+    assert!(!exact.matches(&v("1.0.1")));
+
+    let compatible: VersionRequirement = "~> 2.1".parse().unwrap();
+    assert!(compatible.matches(&v("2.9.0")));
+    assert!(!compatible.matches(&v("3.0.0")));
+
+    let compatible_patch: VersionRequirement = "~> 2.1.3".parse().unwrap();
+    assert!(compatible_patch.matches(&v("2.1.9")));
+    assert!(!compatible_patch.matches(&v("2.2.0")));
+
+    let range: VersionRequirement = ">= 1.0.0 and < 2.0.0 or == 3.0.0".parse().unwrap();
+    assert!(range.matches(&v("1.5.0")));
+    assert!(range.matches(&v("3.0.0")));
+    assert!(!range.matches(&v("2.0.0")));
+
+    assert!("".parse::<VersionRequirement>().is_err());
+    assert!(">= 1.0".parse::<VersionRequirement>().is_err());
+}
+
+#[test]
+fn resolves_newest_matching_release() {
+    let releases = vec![
+        release("r1", "1.0.0", None),
+        release("r2", "1.1.0", Some(">= 1.0.0")),
+        release("r3", "2.0.0", Some("~> 1.1")),
+    ];
+
+    let resolution = ReleaseResolver::new(&releases)
+        .at(now())
+        .resolve(&device("1.0.0"))
+        .unwrap();
+
+    assert_eq!(selected_release(&resolution.outcome), "r2");
+    assert_eq!(resolution.bundle_prn(), Some("bundle-r2"));
+    assert!(resolution
+        .skipped
+        .iter()
+        .any(|s| s.release_prn == "r1" && s.reason == SkipReason::NotNewer));
+    assert!(
+        resolution
+            .skipped
+            .iter()
+            .any(|s| s.release_prn == "r3"
+                && matches!(s.reason, SkipReason::RequirementNotMet { .. }))
+    );
+}
+
+#[test]
+fn required_release_cannot_be_skipped() {
+    let mut required = release("r2", "1.1.0", None);
+    required.required = true;
+    let releases = vec![required, release("r3", "1.2.0", None)];
+
+    let resolution = ReleaseResolver::new(&releases)
+        .at(now())
+        .resolve(&device("1.0.0"))
+        .unwrap();
+
+    assert_eq!(selected_release(&resolution.outcome), "r2");
+    assert!(resolution.skipped.iter().any(|s| s.release_prn == "r3"
+        && s.reason
+            == SkipReason::BlockedByRequired {
+                release_prn: "r2".to_string()
+            }));
+}
+
+#[test]
+fn required_release_outside_phase_does_not_block() {
+    let mut required = release("r2", "1.1.0", None);
+    required.required = true;
+    required.phase_mode = Some(PhaseMode::Tags);
+    required.phase_tags = Some(vec!["beta".to_string()]);
+    let releases = vec![required, release("r3", "1.2.0", None)];
+
+    let resolution = ReleaseResolver::new(&releases)
+        .at(now())
+        .resolve(&device("1.0.0"))
+        .unwrap();
+
+    assert_eq!(selected_release(&resolution.outcome), "r3");
+    assert!(resolution
+        .skipped
+        .iter()
+        .any(|s| s.release_prn == "r2" && s.reason == SkipReason::NotInPhase));
+}
+
+#[test]
+fn releases_follow_next_release_links() {
+    // Linked r1 -> r2 -> r3 -> r4, although r3 has a lower version than r2.
+    // r2 is disabled, which does not break the chain.
+    let linked = |prn: &str, version: &str, next: Option<&str>| {
+        let mut release = release(prn, version, None);
+        release.next_release_prn = next.map(str::to_string);
+        release
+    };
+    let releases = || {
+        let mut r2 = linked("r2", "1.3.0", Some("r3"));
+        r2.disabled = Some(true);
+        vec![
+            linked("r1", "1.1.0", Some("r2")),
+            r2,
+            linked("r3", "1.2.0", Some("r4")),
+            linked("r4", "1.1.5", None),
+            release("r5", "1.0.5", None),
+        ]
+    };
+
+    for rotation in 0..5 {
+        let mut releases = releases();
+        releases.rotate_left(rotation);
+        let resolution = ReleaseResolver::new(&releases)
+            .at(now())
+            .resolve(&device("1.0.0"))
+            .unwrap();
+
+        // In version order r3 would be the newest release offered.
+        assert_eq!(selected_release(&resolution.outcome), "r4");
+    }
+
+    // A required release in the chain blocks the releases linked after it,
+    // whatever their version.
+    let mut releases = releases();
+    releases[2].required = true;
+    let resolution = ReleaseResolver::new(&releases)
+        .at(now())
+        .resolve(&device("1.0.0"))
+        .unwrap();
+    assert_eq!(selected_release(&resolution.outcome), "r3");
+    assert!(resolution.skipped.iter().any(|s| s.release_prn == "r4"
+        && s.reason
+            == SkipReason::BlockedByRequired {
+                release_prn: "r3".to_string()
+            }));
+}
+
+#[test]
+fn disabled_scheduled_and_phased_releases_are_skipped() {
+    let mut disabled = release("r2", "1.1.0", None);
+    disabled.disabled = Some(true);
+    let mut scheduled = release("r3", "1.2.0", None);
+    scheduled.schedule_date = "2030-01-01T00:00:00Z".to_string();
+    let mut tagged = release("r4", "1.3.0", None);
+    tagged.phase_mode = Some(PhaseMode::Tags);
+    tagged.phase_tags = Some(vec!["beta".to_string()]);
+    let mut empty_phase = release("r5", "1.4.0", None);
+    empty_phase.phase_mode = Some(PhaseMode::Numeric);
+    empty_phase.phase_value = Some(0.0);
+    let releases = vec![
+        release("r1", "1.0.1", None),
+        disabled,
+        scheduled,
+        tagged,
+        empty_phase,
+    ];
+
+    let resolution = ReleaseResolver::new(&releases)
+        .at(now())
+        .resolve(&device("1.0.0"))
+        .unwrap();
+
+    assert_eq!(selected_release(&resolution.outcome), "r1");
+    let reason = |prn: &str| {
+        resolution
+            .skipped
+            .iter()
+            .find(|s| s.release_prn == prn)
+            .map(|s| s.reason.clone())
+            .unwrap()
+    };
+    assert_eq!(reason("r2"), SkipReason::Disabled);
+    assert!(matches!(reason("r3"), SkipReason::Scheduled { .. }));
+    assert_eq!(reason("r4"), SkipReason::NotInPhase);
+    assert_eq!(reason("r5"), SkipReason::NotInPhase);
+}
+
+#[test]
+fn active_bundle_override_wins() {
+    let releases = vec![release("r1", "1.1.0", None)];
+    let bundle_override: BundleOverride = serde_json::from_value(json!({
+        "name": "hotfix",
+        "description": null,
+        "inserted_at": "2024-01-01T00:00:00Z",
+        "organization_prn": "organization_prn",
+        "prn": "override_prn",
+        "updated_at": "2024-01-01T00:00:00Z",
+        "ends_at": null,
+        "starts_at": "2024-05-01T00:00:00Z",
+        "bundle_prn": "override_bundle_prn",
+    }))
+    .unwrap();
+
+    let resolver = ReleaseResolver::new(&releases)
+        .with_override(OverrideMembership {
+            bundle_override: &bundle_override,
+            device_prns: vec!["device_prn".to_string()],
+        })
+        .at(now());
+
+    let resolution = resolver.resolve(&device("1.0.0")).unwrap();
+    assert_eq!(
+        resolution.outcome,
+        Outcome::BundleOverride {
+            bundle_override_prn: "override_prn".to_string(),
+            bundle_prn: "override_bundle_prn".to_string(),
+        }
+    );
+
+    let mut other = device("1.0.0");
+    other.prn = "other_device_prn".to_string();
+    let resolution = resolver.resolve(&other).unwrap();
+    assert_eq!(selected_release(&resolution.outcome), "r1");
+}
+
+#[test]
+fn device_without_release_version() {
+    let releases = vec![
+        release("r1", "1.0.0", None),
+        release("r2", "1.1.0", Some(">= 1.0.0")),
+    ];
+
+    let mut state = device("1.0.0");
+    state.release_version = None;
+
+    let resolution = ReleaseResolver::new(&releases)
+        .at(now())
+        .resolve(&state)
+        .unwrap();
+
+    assert_eq!(selected_release(&resolution.outcome), "r1");
+}
+
+#[test]
+fn releases_with_and_without_versions() {
+    let unversioned = |prn: &str, schedule_date: &str| {
+        let mut release = release(prn, "0.0.0", None);
+        release.version = None;
+        release.schedule_date = schedule_date.to_string();
+        release
+    };
+    let releases = || {
+        vec![
+            release("r1", "1.2.0", None),
+            unversioned("r2", "2024-03-01T00:00:00Z"),
+            release("r3", "1.1.0", None),
+            unversioned("r4", "2024-02-01T00:00:00Z"),
+            release("r5", "1.3.0", Some("= 0.9.0")),
+            unversioned("r6", "2024-04-01T00:00:00Z"),
+        ]
+    };
+
+    for rotation in 0..6 {
+        let mut releases = releases();
+        releases.rotate_left(rotation);
+        let resolution = ReleaseResolver::new(&releases)
+            .at(now())
+            .resolve(&device("1.0.0"))
+            .unwrap();
+
+        assert_eq!(selected_release(&resolution.outcome), "r1");
+    }
+}
@@ -0,0 +1,150 @@
+mod common;
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use common::API_KEY;
+use mockito::{Matcher, Server, ServerGuard};
+use serde_json::json;
+
+use peridio_sdk::api::releases::rollout::{
+    GateFailureAction, RolloutController, RolloutPlan, RolloutStatus,
+};
+use peridio_sdk::api::Api;
+use peridio_sdk::api::ApiOptions;
+
+const RELEASE_PRN: &str = "prn";
+
+fn api(server: &ServerGuard) -> Api {
+    Api::new(ApiOptions {
+        api_key: API_KEY.into(),
+        endpoint: Some(server.url()),
+        ca_bundle_path: None,
+        api_version: 1,
+    })
+}
+
+fn state_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "peridio-rollout-{name}-{}.json",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+async fn mock_update(server: &mut ServerGuard, body: serde_json::Value) -> mockito::Mock {
+    server
+        .mock("PATCH", &*format!("/releases/{RELEASE_PRN}"))
+        .match_body(Matcher::PartialJson(body))
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body_from_file("tests/fixtures/releases-update-200.json")
+        .expect(1)
+        .create_async()
+        .await
+}
+
+#[tokio::test]
+async fn rollout_completes_all_steps() {
+    let mut server = Server::new_async().await;
+    let api = api(&server);
+    let path = state_path("complete");
+
+    let m1 = mock_update(&mut server, json!({ "phase_value": 0.05 })).await;
+    let m2 = mock_update(&mut server, json!({ "phase_value": 1.0 })).await;
+
+    let gate_calls = AtomicUsize::new(0);
+    let plan = RolloutPlan::uniform(RELEASE_PRN, &[0.05, 1.0], Duration::ZERO);
+    let mut controller = RolloutController::new(&api, plan, &path, |_| {
+        gate_calls.fetch_add(1, Ordering::SeqCst);
+        async { true }
+    })
+    .unwrap();
+
+    let state = controller.run().await.unwrap();
+    assert_eq!(state.status, RolloutStatus::Completed);
+    assert_eq!(state.current_step, Some(1));
+    assert_eq!(gate_calls.load(Ordering::SeqCst), 2);
+
+    m1.assert_async().await;
+    m2.assert_async().await;
+
+    let persisted = controller.state().await.unwrap();
+    assert_eq!(persisted, state);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn failed_gate_disables_release_and_resume_continues() {
+    let mut server = Server::new_async().await;
+    let api = api(&server);
+    let path = state_path("disable");
+
+    let step1 = mock_update(&mut server, json!({ "phase_value": 0.25 })).await;
+    let disable = mock_update(&mut server, json!({ "disabled": true })).await;
+
+    let plan = RolloutPlan::uniform(RELEASE_PRN, &[0.25, 1.0], Duration::ZERO);
+    let mut controller = RolloutController::new(&api, plan.clone(), &path, |_| async { false })
+        .unwrap()
+        .on_failure(GateFailureAction::Disable);
+
+    let state = controller.run().await.unwrap();
+    assert_eq!(state.status, RolloutStatus::Disabled);
+    assert_eq!(state.current_step, Some(0));
+    step1.assert_async().await;
+    disable.assert_async().await;
+
+    // A restarted controller re-enables the release, re-checks the failed
+    // step and carries on from there.
+    let enable = mock_update(&mut server, json!({ "disabled": false })).await;
+    let step2 = mock_update(&mut server, json!({ "phase_value": 1.0 })).await;
+
+    let mut controller = RolloutController::new(&api, plan, &path, |_| async { true }).unwrap();
+    let state = controller.resume().await.unwrap();
+    assert_eq!(state.status, RolloutStatus::Completed);
+    enable.assert_async().await;
+    step2.assert_async().await;
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn paused_rollout_is_not_advanced_by_run() {
+    let mut server = Server::new_async().await;
+    let api = api(&server);
+    let path = state_path("pause");
+
+    let step1 = mock_update(&mut server, json!({ "phase_value": 0.5 })).await;
+
+    let plan = RolloutPlan::uniform(RELEASE_PRN, &[0.5, 1.0], Duration::ZERO);
+    let mut controller = RolloutController::new(&api, plan, &path, |_| async { false }).unwrap();
+
+    let state = controller.run().await.unwrap();
+    assert_eq!(state.status, RolloutStatus::Paused);
+
+    let state = controller.run().await.unwrap();
+    assert_eq!(state.status, RolloutStatus::Paused);
+    step1.assert_async().await;
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn invalid_plans_are_rejected() {
+    let api = Api::new(ApiOptions {
+        api_key: API_KEY.into(),
+        endpoint: None,
+        ca_bundle_path: None,
+        api_version: 1,
+    });
+    let gate = |_| async { true };
+
+    let empty = RolloutPlan::uniform(RELEASE_PRN, &[], Duration::ZERO);
+    assert!(RolloutController::new(&api, empty, state_path("empty"), gate).is_err());
+
+    let decreasing = RolloutPlan::uniform(RELEASE_PRN, &[0.5, 0.25], Duration::ZERO);
+    assert!(RolloutController::new(&api, decreasing, state_path("decreasing"), gate).is_err());
+
+    let out_of_range = RolloutPlan::uniform(RELEASE_PRN, &[5.0], Duration::ZERO);
+    assert!(RolloutController::new(&api, out_of_range, state_path("range"), gate).is_err());
+}
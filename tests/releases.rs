@@ -227,6 +227,7 @@ async fn update_release() {
 
     let params = UpdateReleaseParams {
         prn: expected_prn.to_string(),
+        bundle_prn: None,
         description: Some(expected_description.to_string()),
         disabled: None,
         name: Some(expected_name.to_string()),
@@ -0,0 +1,349 @@
+mod common;
+
+use std::time::Duration;
+
+use common::API_KEY;
+use mockito::{Matcher, Server};
+use serde_json::{json, Value};
+
+use peridio_sdk::api::tunnels::session::{self, SessionOptions, TunnelSession};
+use peridio_sdk::api::tunnels::{CreateTunnelParams, TunnelState};
+use peridio_sdk::api::Api;
+use peridio_sdk::api::ApiOptions;
+
+fn tunnel(prn: &str, state: &str, inserted_at: &str, expires_at: &str) -> Value {
+    json!({
+        "cidr_block_allowlist": null,
+        "device_prn": "device_prn",
+        "device_proxy_ip_address": "10.0.1.1",
+        "device_proxy_port": 47539,
+        "device_public_key": null,
+        "device_tunnel_port": 22,
+        "expires_at": expires_at,
+        "inserted_at": inserted_at,
+        "organization_prn": "organization_prn",
+        "prn": prn,
+        "server_proxy_ip_address": "10.0.0.1",
+        "server_proxy_port": 49293,
+        "server_public_key": null,
+        "server_tunnel_ip_address": "3.82.23.99",
+        "server_tunnel_port": 47532,
+        "state": state,
+        "updated_at": inserted_at
+    })
+}
+
+fn options() -> SessionOptions {
+    SessionOptions {
+        ttl: 60,
+        renew_before: Duration::from_secs(10),
+        min_renew_interval: Duration::from_millis(10),
+        open_timeout: Duration::from_secs(5),
+        poll_interval: Duration::from_millis(1),
+    }
+}
+
+fn create_params() -> CreateTunnelParams {
+    CreateTunnelParams {
+        cidr_block_allowlist: None,
+        device_prn: "device_prn".to_string(),
+        device_public_key: None,
+        device_tunnel_port: 22,
+        ttl: None,
+    }
+}
+
+#[tokio::test]
+async fn session_opens_renews_and_closes() {
+    let mut server = Server::new_async().await;
+
+    let api = Api::new(ApiOptions {
+        api_key: API_KEY.into(),
+        endpoint: Some(server.url()),
+        ca_bundle_path: None,
+        api_version: 1,
+    });
+
+    // Expiring now, so the keepalive renews as soon as it is allowed to.
+    let expires_at = chrono::Utc::now().to_rfc3339();
+    let create = server
+        .mock("POST", "/tunnels")
+        .match_body(Matcher::PartialJson(json!({ "ttl": 60 })))
+        .with_status(201)
+        .with_header("content-type", "application/json")
+        .with_body(
+            json!({ "tunnel": tunnel("prn", "requested", "2001-01-01T00:00:00Z", &expires_at) })
+                .to_string(),
+        )
+        .create_async()
+        .await;
+    let get = server
+        .mock("GET", "/tunnels/prn")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(
+            json!({ "tunnel": tunnel("prn", "open", "2001-01-01T00:00:00Z", &expires_at) })
+                .to_string(),
+        )
+        .create_async()
+        .await;
+    let renew = server
+        .mock("PATCH", "/tunnels/prn")
+        .match_body(Matcher::Json(json!({ "prn": "prn", "ttl": 60 })))
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(
+            json!({ "tunnel": tunnel("prn", "open", "2001-01-01T00:00:00Z", &expires_at) })
+                .to_string(),
+        )
+        .expect_at_least(1)
+        .create_async()
+        .await;
+    let close = server
+        .mock("PATCH", "/tunnels/prn")
+        .match_body(Matcher::Json(json!({ "prn": "prn", "state": "closed" })))
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(
+            json!({ "tunnel": tunnel("prn", "closed", "2001-01-01T00:00:00Z", &expires_at) })
+                .to_string(),
+        )
+        .expect(1)
+        .create_async()
+        .await;
+
+    let session = TunnelSession::open(&api, create_params(), options())
+        .await
+        .unwrap();
+    assert_eq!(session.tunnel().state, TunnelState::Open);
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let closed = session.close().await.unwrap();
+    assert_eq!(closed.state, TunnelState::Closed);
+
+    create.assert_async().await;
+    get.assert_async().await;
+    renew.assert_async().await;
+    close.assert_async().await;
+}
+
+#[tokio::test]
+async fn dropped_session_closes_tunnel() {
+    let mut server = Server::new_async().await;
+
+    let api = Api::new(ApiOptions {
+        api_key: API_KEY.into(),
+        endpoint: Some(server.url()),
+        ca_bundle_path: None,
+        api_version: 1,
+    });
+
+    let expires_at = "2999-01-01T00:00:00Z";
+    let create = server
+        .mock("POST", "/tunnels")
+        .with_status(201)
+        .with_header("content-type", "application/json")
+        .with_body(
+            json!({ "tunnel": tunnel("prn", "open", "2001-01-01T00:00:00Z", expires_at) })
+                .to_string(),
+        )
+        .create_async()
+        .await;
+    let close = server
+        .mock("PATCH", "/tunnels/prn")
+        .match_body(Matcher::PartialJson(json!({ "state": "closed" })))
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(
+            json!({ "tunnel": tunnel("prn", "closed", "2001-01-01T00:00:00Z", expires_at) })
+                .to_string(),
+        )
+        .expect(1)
+        .create_async()
+        .await;
+
+    let session = TunnelSession::open(&api, create_params(), options())
+        .await
+        .unwrap();
+    drop(session);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    create.assert_async().await;
+    close.assert_async().await;
+}
+
+#[tokio::test]
+async fn failed_close_is_retried_on_drop() {
+    let mut server = Server::new_async().await;
+
+    let api = Api::new(ApiOptions {
+        api_key: API_KEY.into(),
+        endpoint: Some(server.url()),
+        ca_bundle_path: None,
+        api_version: 1,
+    });
+
+    let expires_at = "2999-01-01T00:00:00Z";
+    let create = server
+        .mock("POST", "/tunnels")
+        .with_status(201)
+        .with_header("content-type", "application/json")
+        .with_body(
+            json!({ "tunnel": tunnel("prn", "open", "2001-01-01T00:00:00Z", expires_at) })
+                .to_string(),
+        )
+        .create_async()
+        .await;
+    // Once by close and once more when the session is dropped.
+    let close = server
+        .mock("PATCH", "/tunnels/prn")
+        .match_body(Matcher::PartialJson(json!({ "state": "closed" })))
+        .with_status(500)
+        .expect(2)
+        .create_async()
+        .await;
+
+    let session = TunnelSession::open(&api, create_params(), options())
+        .await
+        .unwrap();
+    assert!(session.close().await.is_err());
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    create.assert_async().await;
+    close.assert_async().await;
+}
+
+#[tokio::test]
+async fn keepalive_stops_once_tunnel_is_gone() {
+    for (status, body) in [
+        (
+            200,
+            json!({ "tunnel": tunnel("prn", "expired", "2001-01-01T00:00:00Z", "2001-01-01T00:00:00Z") }),
+        ),
+        (404, json!({ "errors": { "detail": "Not Found" } })),
+    ] {
+        let mut server = Server::new_async().await;
+
+        let api = Api::new(ApiOptions {
+            api_key: API_KEY.into(),
+            endpoint: Some(server.url()),
+            ca_bundle_path: None,
+            api_version: 1,
+        });
+
+        let expires_at = chrono::Utc::now().to_rfc3339();
+        let create = server
+            .mock("POST", "/tunnels")
+            .with_status(201)
+            .with_header("content-type", "application/json")
+            .with_body(
+                json!({ "tunnel": tunnel("prn", "open", "2001-01-01T00:00:00Z", &expires_at) })
+                    .to_string(),
+            )
+            .create_async()
+            .await;
+        let renew = server
+            .mock("PATCH", "/tunnels/prn")
+            .match_body(Matcher::Json(json!({ "prn": "prn", "ttl": 60 })))
+            .with_status(status)
+            .with_header("content-type", "application/json")
+            .with_body(body.to_string())
+            .expect(1)
+            .create_async()
+            .await;
+
+        let session = TunnelSession::open(&api, create_params(), options())
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        create.assert_async().await;
+        renew.assert_async().await;
+        drop(session);
+    }
+}
+
+#[tokio::test]
+async fn reap_closes_stale_tunnels() {
+    let mut server = Server::new_async().await;
+
+    let api = Api::new(ApiOptions {
+        api_key: API_KEY.into(),
+        endpoint: Some(server.url()),
+        ca_bundle_path: None,
+        api_version: 1,
+    });
+
+    let now = chrono::Utc::now().to_rfc3339();
+    // Created long ago but kept alive by a session renewing its TTL.
+    let mut renewed = tunnel(
+        "renewed_prn",
+        "open",
+        "2001-01-01T00:00:00Z",
+        "2999-01-01T00:00:00Z",
+    );
+    renewed["updated_at"] = json!(now);
+    let list = server
+        .mock("GET", "/tunnels")
+        .match_query(Matcher::Any)
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(
+            json!({
+                "tunnels": [
+                    tunnel("expired_prn", "open", &now, "2001-01-01T00:00:00Z"),
+                    tunnel("broken_prn", "open", "2001-01-01T00:00:00Z", "2999-01-01T00:00:00Z"),
+                    tunnel("old_prn", "requested", "2001-01-01T00:00:00Z", "2999-01-01T00:00:00Z"),
+                    renewed,
+                    tunnel("fresh_prn", "open", &now, "2999-01-01T00:00:00Z"),
+                    tunnel("closed_prn", "closed", "2001-01-01T00:00:00Z", "2001-01-01T00:00:00Z"),
+                ],
+                "next_page": null
+            })
+            .to_string(),
+        )
+        .create_async()
+        .await;
+
+    let mut closes = Vec::new();
+    for prn in ["expired_prn", "old_prn"] {
+        closes.push(
+            server
+                .mock("PATCH", &*format!("/tunnels/{prn}"))
+                .match_body(Matcher::PartialJson(json!({ "state": "closed" })))
+                .with_status(200)
+                .with_header("content-type", "application/json")
+                .with_body(json!({ "tunnel": tunnel(prn, "closed", &now, &now) }).to_string())
+                .expect(1)
+                .create_async()
+                .await,
+        );
+    }
+    closes.push(
+        server
+            .mock("PATCH", "/tunnels/broken_prn")
+            .with_status(500)
+            .expect(1)
+            .create_async()
+            .await,
+    );
+    closes.push(
+        server
+            .mock("PATCH", "/tunnels/renewed_prn")
+            .expect(0)
+            .create_async()
+            .await,
+    );
+
+    let reaped = session::reap(&api, Duration::from_secs(24 * 60 * 60))
+        .await
+        .unwrap();
+    assert_eq!(reaped.closed, vec!["expired_prn", "old_prn"]);
+    assert_eq!(reaped.failures.len(), 1);
+    assert_eq!(reaped.failures[0].prn, "broken_prn");
+
+    list.assert_async().await;
+    for close in closes {
+        close.assert_async().await;
+    }
+}
@@ -0,0 +1,196 @@
+mod common;
+
+use common::API_KEY;
+use mockito::{Matcher, Server};
+use serde_json::Value;
+
+use peridio_sdk::api::tunnels::wireguard::{self, Error, Keypair, WireGuardConfig};
+use peridio_sdk::api::tunnels::CreateTunnelParams;
+use peridio_sdk::api::Api;
+use peridio_sdk::api::ApiOptions;
+
+#[test]
+fn keypair_derivation() {
+    let keypair = Keypair::generate();
+    let derived = Keypair::from_private_key(&keypair.private_key).unwrap();
+    assert_eq!(derived, keypair);
+    assert_ne!(keypair.private_key, keypair.public_key);
+
+    assert!(matches!(
+        Keypair::from_private_key("c2hvcnQ="),
+        Err(Error::InvalidKey { .. })
+    ));
+    assert!(!format!("{keypair:?}").contains(&keypair.private_key));
+}
+
+fn public_key_of(config: &WireGuardConfig) -> String {
+    Keypair::from_private_key(&config.interface.private_key)
+        .unwrap()
+        .public_key
+}
+
+#[tokio::test]
+async fn create_tunnel_and_render_configs() {
+    let mut server = Server::new_async().await;
+
+    let api = Api::new(ApiOptions {
+        api_key: API_KEY.into(),
+        endpoint: Some(server.url()),
+        ca_bundle_path: None,
+        api_version: 1,
+    });
+
+    let server_keys = Keypair::generate();
+    let fixture = std::fs::read_to_string("tests/fixtures/tunnels-create-201.json")
+        .unwrap()
+        .replace("server_public_Key", &server_keys.public_key);
+
+    // The server echoes the device public key it was sent.
+    let m = server
+        .mock("POST", "/tunnels")
+        .match_body(Matcher::Regex("\"device_public_key\":\"[^\"]+\"".into()))
+        .with_status(201)
+        .with_header("content-type", "application/json")
+        .with_body_from_request(move |request| {
+            let body: Value = serde_json::from_slice(request.body().unwrap()).unwrap();
+            let device_public_key = body["device_public_key"].as_str().unwrap();
+            fixture
+                .replace(
+                    "\"device_public_key\",",
+                    &format!("\"{device_public_key}\","),
+                )
+                .into_bytes()
+        })
+        .create_async()
+        .await;
+
+    let session = wireguard::create(
+        &api,
+        CreateTunnelParams {
+            cidr_block_allowlist: Some(vec!["10.0.0.1/32".to_string()]),
+            device_prn: "device_prn".to_string(),
+            device_public_key: None,
+            device_tunnel_port: 22,
+            ttl: None,
+        },
+    )
+    .await
+    .unwrap();
+    m.assert_async().await;
+
+    let device = session.device_config().unwrap();
+    let rendered = device.to_string();
+    assert!(rendered.contains("Address = 10.0.1.1/32"));
+    assert!(rendered.contains("ListenPort = 47539"));
+    assert!(rendered.contains("Endpoint = 3.82.23.99:47532"));
+    assert!(rendered.contains("AllowedIPs = 10.0.0.1/32"));
+    assert!(rendered.contains("PersistentKeepalive = 25"));
+    assert_eq!(rendered.parse::<WireGuardConfig>().unwrap(), device);
+
+    // The device's private key belongs to the public key the server knows
+    // for it, and the device trusts the server's key.
+    assert_eq!(
+        Some(public_key_of(&device)),
+        session.tunnel.device_public_key
+    );
+    assert_eq!(device.peers.len(), 1);
+    assert_eq!(device.peers[0].public_key, server_keys.public_key);
+
+    assert_eq!(session.operator_endpoint().unwrap(), "10.0.0.1:49293");
+}
+
+#[tokio::test]
+async fn create_rejects_mismatched_device_key() {
+    let mut server = Server::new_async().await;
+
+    let api = Api::new(ApiOptions {
+        api_key: API_KEY.into(),
+        endpoint: Some(server.url()),
+        ca_bundle_path: None,
+        api_version: 1,
+    });
+
+    let fixture = std::fs::read_to_string("tests/fixtures/tunnels-create-201.json")
+        .unwrap()
+        .replace(
+            "\"device_public_key\",",
+            &format!("\"{}\",", Keypair::generate().public_key),
+        );
+    let m = server
+        .mock("POST", "/tunnels")
+        .with_status(201)
+        .with_header("content-type", "application/json")
+        .with_body(fixture)
+        .create_async()
+        .await;
+
+    let error = wireguard::create(
+        &api,
+        CreateTunnelParams {
+            cidr_block_allowlist: None,
+            device_prn: "device_prn".to_string(),
+            device_public_key: None,
+            device_tunnel_port: 22,
+            ttl: None,
+        },
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(error, Error::KeyMismatch { .. }));
+
+    m.assert_async().await;
+}
+
+#[test]
+fn parse_wg_quick_config() {
+    let interface_key = Keypair::generate();
+    let peer_key = Keypair::generate();
+    let contents = format!(
+        "# managed by tooling
+[Interface]
+PrivateKey = {}
+Address = 10.0.1.1/32, fd00::1/128
+ListenPort = 51820
+PostUp = iptables -A FORWARD -i %i -j ACCEPT
+
+[Peer]
+PublicKey = {} # server
+Endpoint = 3.82.23.99:47532
+AllowedIPs = 10.0.0.1/32
+AllowedIPs = 10.0.0.2/32
+",
+        interface_key.private_key, peer_key.public_key
+    );
+
+    let config: WireGuardConfig = contents.parse().unwrap();
+    assert_eq!(config.interface.private_key, interface_key.private_key);
+    assert_eq!(
+        config.interface.addresses,
+        vec!["10.0.1.1/32", "fd00::1/128"]
+    );
+    assert_eq!(config.interface.listen_port, Some(51820));
+    assert_eq!(config.peers.len(), 1);
+    assert_eq!(config.peers[0].public_key, peer_key.public_key);
+    assert_eq!(
+        config.peers[0].allowed_ips,
+        vec!["10.0.0.1/32", "10.0.0.2/32"]
+    );
+    assert_eq!(config.peers[0].persistent_keepalive, None);
+
+    assert!(matches!(
+        "[Peer]\nPublicKey = abc".parse::<WireGuardConfig>(),
+        Err(Error::InvalidKey { .. })
+    ));
+    assert!(matches!(
+        "[Interface]\nAddress = 10.0.0.1/32".parse::<WireGuardConfig>(),
+        Err(Error::MissingField { .. })
+    ));
+    assert!(matches!(
+        "[Interface]\nListenPort = lots".parse::<WireGuardConfig>(),
+        Err(Error::Syntax { line: 2, .. })
+    ));
+    assert!(matches!(
+        "[Tunnel]".parse::<WireGuardConfig>(),
+        Err(Error::Syntax { line: 1, .. })
+    ));
+}
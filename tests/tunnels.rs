@@ -2,8 +2,11 @@ mod common;
 
 use common::API_KEY;
 use mockito::Server;
+use std::time::Duration;
 
-use peridio_sdk::api::tunnels::{CreateTunnelParams, GetTunnelParams, UpdateTunnelParams};
+use peridio_sdk::api::tunnels::{
+    CreateTunnelParams, GetTunnelParams, TunnelState, UpdateTunnelParams, WaitForTunnelStateParams,
+};
 
 use peridio_sdk::api::Api;
 use peridio_sdk::api::ApiOptions;
@@ -34,6 +37,7 @@ async fn create_tunnel() {
     let params = CreateTunnelParams {
         cidr_block_allowlist: Some(cidr_block_allowlist.clone()),
         device_prn: device_prn.to_string(),
+        device_public_key: None,
         device_tunnel_port: port,
         ttl: Some(ttl),
     };
@@ -60,7 +64,7 @@ async fn create_tunnel() {
 #[tokio::test]
 async fn get_tunnel() {
     let mut server = Server::new_async().await;
-    let expected_state = "requested";
+    let expected_state = TunnelState::Requested;
     let expected_server_proxy_port = 49293;
     let expected_device_prn = "device_prn";
     let expected_organization_prn = "organization_prn";
@@ -87,7 +91,7 @@ async fn get_tunnel() {
 
     match api.tunnels().get(params).await.unwrap() {
         Some(tunnel) => {
-            assert_eq!(tunnel.tunnel.state, expected_state.to_string());
+            assert_eq!(tunnel.tunnel.state, expected_state);
             assert_eq!(
                 tunnel.tunnel.server_proxy_port,
                 Some(expected_server_proxy_port)
@@ -107,7 +111,7 @@ async fn get_tunnel() {
 #[tokio::test]
 async fn update_tunnel() {
     let mut server = Server::new_async().await;
-    let expected_state = "closed";
+    let expected_state = TunnelState::Closed;
     let expected_prn = "1";
 
     let api = Api::new(ApiOptions {
@@ -127,13 +131,13 @@ async fn update_tunnel() {
 
     let params = UpdateTunnelParams {
         prn: expected_prn.to_string(),
-        state: Some(expected_state.to_string()),
+        state: Some(expected_state.clone()),
         ttl: Some(30),
     };
 
     match api.tunnels().update(params).await.unwrap() {
         Some(tunnel) => {
-            assert_eq!(tunnel.tunnel.state, expected_state.to_string());
+            assert_eq!(tunnel.tunnel.state, expected_state);
             assert_eq!(
                 tunnel.tunnel.server_tunnel_ip_address,
                 Some("3.82.23.99".to_owned())
@@ -144,3 +148,60 @@ async fn update_tunnel() {
 
     m.assert_async().await;
 }
+
+#[tokio::test]
+async fn wait_for_tunnel_state() {
+    let mut server = Server::new_async().await;
+
+    let api = Api::new(ApiOptions {
+        api_key: API_KEY.into(),
+        endpoint: Some(server.url()),
+        ca_bundle_path: None,
+        api_version: 1,
+    });
+
+    let fixture = std::fs::read_to_string("tests/fixtures/tunnels-get-200.json").unwrap();
+    let requested = server
+        .mock("GET", "/tunnels/prn")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(fixture.clone())
+        .expect(1)
+        .create_async()
+        .await;
+    let open = server
+        .mock("GET", "/tunnels/prn")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(fixture.replace("\"requested\"", "\"open\""))
+        .expect(1)
+        .create_async()
+        .await;
+    let closed = server
+        .mock("GET", "/tunnels/closed_prn")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(fixture.replace("\"requested\"", "\"closed\""))
+        .expect(1)
+        .create_async()
+        .await;
+
+    let params = |prn: &str| WaitForTunnelStateParams {
+        prn: prn.to_string(),
+        target_states: vec![TunnelState::Open],
+        timeout: Duration::from_secs(5),
+        poll_interval: Duration::from_millis(1),
+    };
+
+    let tunnel = api.tunnels().wait_for_state(params("prn")).await.unwrap();
+    assert_eq!(tunnel.state, TunnelState::Open);
+
+    assert!(matches!(
+        api.tunnels().wait_for_state(params("closed_prn")).await,
+        Err(peridio_sdk::api::Error::UnexpectedState { .. })
+    ));
+
+    requested.assert_async().await;
+    open.assert_async().await;
+    closed.assert_async().await;
+}
@@ -5,6 +5,7 @@ use mockito::Server;
 use peridio_sdk::api::webhooks::CreateWebhookParams;
 use peridio_sdk::api::webhooks::GetWebhookParams;
 use peridio_sdk::api::webhooks::UpdateWebhookParams;
+use peridio_sdk::api::webhooks::{WebhookEnabledEvent, WebhookState};
 use peridio_sdk::api::Api;
 use peridio_sdk::api::ApiOptions;
 
@@ -12,9 +13,9 @@ use peridio_sdk::api::ApiOptions;
 async fn create_webhook() {
     let mut server = Server::new_async().await;
     let expected_url = "https://peridio.com";
-    let expected_state = "disabled";
+    let expected_state = WebhookState::Disabled;
     let expected_description = "description";
-    let expected_enabled_events = vec!["device.release_changed".to_string()];
+    let expected_enabled_events = vec![WebhookEnabledEvent::DeviceReleaseChanged];
 
     let api = Api::new(ApiOptions {
         api_key: API_KEY.into(),
@@ -44,7 +45,7 @@ async fn create_webhook() {
                 Some(expected_description.to_string())
             );
             assert_eq!(webhook.webhook.url, Some(expected_url.to_string()));
-            assert_eq!(webhook.webhook.state, Some(expected_state.to_string()));
+            assert_eq!(webhook.webhook.state, Some(expected_state.clone()));
             assert_eq!(webhook.webhook.enabled_events, expected_enabled_events);
         }
         _ => panic!(),
@@ -58,9 +59,9 @@ async fn get_webhook() {
     let mut server = Server::new_async().await;
     let expected_description = "description";
     let expected_prn = "prn";
-    let expected_state = "enabled";
+    let expected_state = WebhookState::Enabled;
     let expected_url = "https://peridio.com";
-    let expected_enabled_events = vec!["device.release_changed".to_string()];
+    let expected_enabled_events = vec![WebhookEnabledEvent::DeviceReleaseChanged];
 
     let api = Api::new(ApiOptions {
         api_key: API_KEY.into(),
@@ -88,7 +89,7 @@ async fn get_webhook() {
                 Some(expected_description.to_string())
             );
             assert_eq!(webhook.webhook.url, Some(expected_url.to_string()));
-            assert_eq!(webhook.webhook.state, Some(expected_state.to_string()));
+            assert_eq!(webhook.webhook.state, Some(expected_state.clone()));
             assert_eq!(webhook.webhook.enabled_events, expected_enabled_events);
         }
         _ => panic!(),
@@ -103,8 +104,8 @@ async fn update_webhook() {
     let expected_description = "description";
     let expected_prn = "prn";
     let expected_url = "https://peridio.com";
-    let expected_state = "enabled";
-    let expected_enabled_events = vec!["device.release_changed".to_string()];
+    let expected_state = WebhookState::Enabled;
+    let expected_enabled_events = vec![WebhookEnabledEvent::DeviceReleaseChanged];
 
     let api = Api::new(ApiOptions {
         api_key: API_KEY.into(),
@@ -126,7 +127,7 @@ async fn update_webhook() {
         enabled_events: Some(expected_enabled_events.clone()),
         description: Some(expected_description.to_string()),
         url: Some(expected_url.to_string()),
-        state: Some(expected_state.to_string()),
+        state: Some(expected_state.clone()),
     };
 
     match api.webhooks().update(params).await.unwrap() {
@@ -135,7 +136,7 @@ async fn update_webhook() {
                 webhook.webhook.description,
                 Some(expected_description.to_string())
             );
-            assert_eq!(webhook.webhook.state, Some(expected_state.to_string()));
+            assert_eq!(webhook.webhook.state, Some(expected_state.clone()));
             assert_eq!(webhook.webhook.enabled_events, expected_enabled_events);
             assert_eq!(webhook.webhook.url, Some(expected_url.to_string()));
         }
@@ -144,3 +145,29 @@ async fn update_webhook() {
 
     m.assert_async().await;
 }
+
+#[test]
+fn webhook_enums_round_trip() {
+    let events: Vec<WebhookEnabledEvent> = serde_json::from_value(serde_json::json!([
+        "device.release_changed",
+        "device.new_event"
+    ]))
+    .unwrap();
+    assert_eq!(
+        events,
+        vec![
+            WebhookEnabledEvent::DeviceReleaseChanged,
+            WebhookEnabledEvent::Unknown("device.new_event".to_string())
+        ]
+    );
+    assert_eq!(
+        serde_json::to_value(&events).unwrap(),
+        serde_json::json!(["device.release_changed", "device.new_event"])
+    );
+
+    assert_eq!(
+        "disabled".parse::<WebhookState>().unwrap(),
+        WebhookState::Disabled
+    );
+    assert_eq!(WebhookState::Enabled.to_string(), "enabled");
+}